@@ -0,0 +1,134 @@
+use crate::wav_writer::WavWriter;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+// Headers are brought up to date about once a second of stereo audio,
+// so a recording cut short by a crash is still readable.
+const FLUSH_SAMPLES: usize = 96_000;
+
+/// Records the APU output to a stereo WAV file and optionally each channel, before panning
+/// and volume, to its own mono file: `out.wav` gets `out.ch1.wav` to `out.ch4.wav`.
+pub struct AudioRecorder {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Option<Vec<WavWriter<BufWriter<File>>>>,
+    since_flush: usize,
+    // The APU can't fail a step, the first error is kept for `finish`.
+    error: Option<std::io::Error>,
+}
+
+fn create_wav(
+    path: &Path,
+    channels: u16,
+    sample_rate: u32,
+) -> Result<WavWriter<BufWriter<File>>, std::io::Error> {
+    WavWriter::new(BufWriter::new(File::create(path)?), channels, sample_rate)
+}
+
+/// Path of the mono file of `channel` (1-4) next to the mix at `path`.
+pub fn stem_path(path: &Path, channel: usize) -> PathBuf {
+    path.with_extension(format!("ch{}.wav", channel))
+}
+
+impl AudioRecorder {
+    pub fn create(
+        path: &Path,
+        sample_rate: u32,
+        stems: bool,
+    ) -> Result<AudioRecorder, std::io::Error> {
+        let mix = create_wav(path, 2, sample_rate)?;
+        let stems = if stems {
+            Some(
+                (1..=4)
+                    .map(|c| create_wav(&stem_path(path, c), 1, sample_rate))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        } else {
+            None
+        };
+
+        Ok(AudioRecorder {
+            mix,
+            stems,
+            since_flush: 0,
+            error: None,
+        })
+    }
+
+    pub fn has_stems(&self) -> bool {
+        self.stems.is_some()
+    }
+
+    /// Appends interleaved left and right samples of the mix.
+    pub fn write_mix(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let mut result = self.mix.write_samples(samples);
+        self.since_flush += samples.len();
+        if result.is_ok() && self.since_flush >= FLUSH_SAMPLES {
+            self.since_flush = 0;
+            result = self.flush();
+        }
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    /// Appends the samples of channel `channel` (0-3).
+    pub fn write_stem(&mut self, channel: usize, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Some(stems) = &mut self.stems {
+            if let Err(e) = stems[channel].write_samples(samples) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.mix.flush()?;
+        for stem in self.stems.iter_mut().flatten() {
+            stem.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Completes the files, reporting any error that happened while recording.
+    pub fn finish(mut self) -> Result<(), std::io::Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        self.mix.finish()?;
+        for stem in self.stems.into_iter().flatten() {
+            stem.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_mix_and_stems() {
+        let dir = std::env::temp_dir().join(format!("yobemag-recorder-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.wav");
+
+        let mut recorder = AudioRecorder::create(&path, 48_000, true).unwrap();
+        recorder.write_mix(&[0.0; 8]);
+        recorder.write_stem(2, &[0.5; 3]);
+        recorder.finish().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap().len(), 44 + 16);
+        assert_eq!(std::fs::read(stem_path(&path, 3)).unwrap().len(), 44 + 6);
+        assert_eq!(std::fs::read(stem_path(&path, 1)).unwrap().len(), 44);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use crate::memory_device::ReadWrite;
+use crate::{
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
 
 // BCPS/BGPI - CGB Mode Only - Background Palette Index
 // This register is used to address a byte in the CGBs Background Palette Memory.
@@ -55,3 +58,15 @@ impl ReadWrite for BackgroundPaletteIndex {
         ))
     }
 }
+
+impl SaveState for BackgroundPaletteIndex {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.value, self.auto_increment as u8]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.value = state.u8()?;
+        self.auto_increment = state.bool()?;
+        Ok(())
+    }
+}
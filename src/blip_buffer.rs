@@ -0,0 +1,211 @@
+use std::f64::consts::PI;
+
+// Each amplitude change is spread over this many output samples, which delays the
+// output by half of them.
+const TAPS: usize = 16;
+// Resolution of the position of a change between two output samples.
+const PHASES: usize = 64;
+// Cutoff of the low-pass filter as a fraction of the output sample rate,
+// a bit below Nyquist to leave room for the window.
+const CUTOFF: f64 = 0.45;
+
+// Dynamic rate control can nudge the output rate by at most 0.5%.
+pub const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// Band-limited synthesis of a signal described by its amplitude changes (deltas).
+/// Each delta is added as a band-limited step at its exact position between output
+/// samples, so square waves don't alias when resampled from the 2^22 Hz master clock.
+pub struct BlipBuffer {
+    clock_rate: u64,
+    // Effective output rate in mHz, including the dynamic rate adjustment.
+    rate: u64,
+    sample_rate: u32,
+
+    // Position of the start of the current frame in output samples,
+    // scaled by `clock_rate * 1000`.
+    offset: u64,
+    // Differences between consecutive output samples, integrated when read.
+    deltas: Vec<f32>,
+    integrator: f32,
+    kernel: Vec<[f32; TAPS]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> BlipBuffer {
+        let mut blip = BlipBuffer {
+            clock_rate: u64::from(clock_rate),
+            rate: 0,
+            sample_rate,
+            offset: 0,
+            deltas: vec![0.0; TAPS],
+            integrator: 0.0,
+            kernel: step_kernel(),
+        };
+        blip.set_rate_adjustment(0.0);
+        blip
+    }
+
+    fn scale(&self) -> u64 {
+        self.clock_rate * 1000
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.set_rate_adjustment(0.0);
+    }
+
+    /// Stretches the output by `adjustment` (clamped to ±0.5%): a positive value produces
+    /// more samples for the same emulated time, to refill a host buffer running low.
+    pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+        let adjustment = adjustment.clamp(-MAX_RATE_ADJUSTMENT, MAX_RATE_ADJUSTMENT);
+        self.rate = (f64::from(self.sample_rate) * 1000.0 * (1.0 + adjustment)).round() as u64;
+    }
+
+    /// Adds a change of amplitude `delta` at `time` clocks from the start of the frame.
+    pub fn add_delta(&mut self, time: u32, delta: f32) {
+        let position = self.offset + u64::from(time) * self.rate;
+        let index = (position / self.scale()) as usize;
+        let phase = ((position % self.scale()) * PHASES as u64 / self.scale()) as usize;
+
+        if self.deltas.len() < index + TAPS {
+            self.deltas.resize(index + TAPS, 0.0);
+        }
+        for (d, k) in self.deltas[index..index + TAPS]
+            .iter_mut()
+            .zip(self.kernel[phase].iter())
+        {
+            *d += delta * k;
+        }
+    }
+
+    /// Ends the frame after `time` clocks, the samples before it become available.
+    pub fn end_frame(&mut self, time: u32) {
+        self.offset += u64::from(time) * self.rate;
+        let needed = (self.offset / self.scale()) as usize + TAPS;
+        if self.deltas.len() < needed {
+            self.deltas.resize(needed, 0.0);
+        }
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        (self.offset / self.scale()) as usize
+    }
+
+    /// Appends the available samples to `out`.
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let count = self.samples_avail();
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.offset -= count as u64 * self.scale();
+    }
+}
+
+// Blackman windowed sinc, the impulse response of the low-pass filter,
+// `x` in output samples from its center.
+fn impulse(x: f64) -> f64 {
+    let half = (TAPS / 2) as f64;
+    if x.abs() >= half {
+        return 0.0;
+    }
+
+    let sinc = if x == 0.0 {
+        2.0 * CUTOFF
+    } else {
+        (2.0 * PI * CUTOFF * x).sin() / (PI * x)
+    };
+    let w = (x + half) / (2.0 * half);
+    let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+    sinc * window
+}
+
+// For each phase, the difference between consecutive output samples of a band-limited
+// unit step happening `phase / PHASES` samples after the first tap, plus half the taps.
+// Each row sums to 1 so that a step settles exactly on its amplitude.
+fn step_kernel() -> Vec<[f32; TAPS]> {
+    const STEPS: usize = 32;
+    let half = (TAPS / 2) as f64;
+    (0..PHASES)
+        .map(|phase| {
+            let start = phase as f64 / PHASES as f64 + half;
+            let mut row = [0.0; TAPS];
+            for (i, tap) in row.iter_mut().enumerate() {
+                // Integral of the impulse over (i - 1, i] relative to the step.
+                let from = i as f64 - 1.0 - start;
+                *tap = (0..STEPS)
+                    .map(|s| impulse(from + (s as f64 + 0.5) / STEPS as f64))
+                    .sum::<f64>()
+                    / STEPS as f64;
+            }
+            let sum = row.iter().sum::<f64>();
+            row.map(|t| (t / sum) as f32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK: u32 = 4_194_304;
+
+    #[test]
+    fn one_second_of_samples() {
+        let mut blip = BlipBuffer::new(CLOCK, 48_000);
+        blip.end_frame(CLOCK / 2);
+        blip.end_frame(CLOCK / 2);
+        assert_eq!(blip.samples_avail(), 48_000);
+        let mut out = vec![];
+        blip.read_samples(&mut out);
+        assert_eq!(out.len(), 48_000);
+        assert_eq!(blip.samples_avail(), 0);
+    }
+
+    #[test]
+    fn step_settles_on_amplitude() {
+        let mut blip = BlipBuffer::new(CLOCK, 44_100);
+        blip.add_delta(1_000, 0.5);
+        blip.end_frame(10_000);
+        let mut out = vec![];
+        blip.read_samples(&mut out);
+
+        assert!((out[out.len() - 1] - 0.5).abs() < 1e-5);
+        assert!(out[0].abs() < 1e-5);
+        // No sample overshoots by much, the window keeps ringing low.
+        assert!(out.iter().all(|s| *s < 0.55 && *s > -0.05));
+    }
+
+    #[test]
+    fn rate_adjustment_is_clamped() {
+        let mut blip = BlipBuffer::new(CLOCK, 48_000);
+        blip.set_rate_adjustment(0.005);
+        blip.end_frame(CLOCK);
+        assert_eq!(blip.samples_avail(), 48_240);
+
+        let mut blip = BlipBuffer::new(CLOCK, 48_000);
+        blip.set_rate_adjustment(-0.5);
+        blip.end_frame(CLOCK);
+        assert_eq!(blip.samples_avail(), 47_760);
+    }
+
+    #[test]
+    fn high_frequency_square_is_attenuated() {
+        // A 262 kHz square wave is far above the output Nyquist frequency,
+        // once band-limited almost nothing is left of it.
+        let mut blip = BlipBuffer::new(CLOCK, 48_000);
+        let mut amplitude = 0.0;
+        for t in (0..CLOCK / 10).step_by(8) {
+            let target = if (t / 8) % 2 == 0 { 1.0 } else { 0.0 };
+            blip.add_delta(t, target - amplitude);
+            amplitude = target;
+        }
+        blip.end_frame(CLOCK / 10);
+        let mut out = vec![];
+        blip.read_samples(&mut out);
+
+        let settled = &out[TAPS..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        assert!(settled.iter().all(|s| (s - mean).abs() < 0.05));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::save_state::{SaveState, StateReader};
+
+// DMG/MGB/SGB boot ROMs are 256 bytes, mapped at 0x0000-0x00FF.
+const DMG_BOOT_ROM_LEN: usize = 0x100;
+// CGB/AGB boot ROMs are 2304 bytes: 0x0000-0x00FF plus 0x0200-0x08FF,
+// the hole in the middle leaves the cartridge header visible to the boot code.
+const CGB_BOOT_ROM_LEN: usize = 0x900;
+
+/// The boot ROM is overlaid on top of the cartridge at power on, it shows the logo,
+/// checks the header and then unmaps itself writing to 0xFF50, jumping to the cartridge at 0x0100.
+#[derive(Default)]
+pub struct BootRom {
+    data: Vec<u8>,
+
+    // 0xFF50: Boot ROM disable.
+    // Writing a non-zero value unmaps the boot ROM, it can't be mapped back until reset.
+    active: bool,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> Result<BootRom, std::io::Error> {
+        match data.len() {
+            DMG_BOOT_ROM_LEN | CGB_BOOT_ROM_LEN => Ok(BootRom { data, active: true }),
+            len => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("boot rom can't be {} bytes long.", len),
+            )),
+        }
+    }
+
+    fn maps(&self, address: usize) -> bool {
+        self.active
+            && ((0x0000..=0x00FF).contains(&address)
+                || (self.data.len() == CGB_BOOT_ROM_LEN && (0x0200..=0x08FF).contains(&address)))
+    }
+}
+
+// Only ever read a byte at a time: a word at the end of a mapped range is half cartridge,
+// the MMU puts it together through the overlay.
+impl BootRom {
+    pub fn contains(&self, address: usize) -> bool {
+        self.maps(address) || 0xFF50 == address
+    }
+
+    pub fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
+        match address {
+            0xFF50 => Ok(if self.active { 0xFE } else { 0xFF }),
+            _ => Ok(self.data[address]),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
+        match address {
+            0xFF50 => {
+                if value != 0 {
+                    self.active = false;
+                }
+                Ok(())
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("can't write boot rom at {:#04x}", address),
+            )),
+        }
+    }
+}
+
+// The image itself comes with the emulator the state is loaded in.
+impl SaveState for BootRom {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.active as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.active = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_len() {
+        assert!(BootRom::new(vec![0; 0x200]).is_err());
+    }
+
+    #[test]
+    fn dmg_mapping() {
+        let mut boot = BootRom::new(vec![0x31; DMG_BOOT_ROM_LEN]).unwrap();
+        assert!(boot.contains(0x0000));
+        assert!(boot.contains(0x00FF));
+        assert!(!boot.contains(0x0100));
+        assert!(!boot.contains(0x0200));
+        assert_eq!(boot.read_byte(0x0000).unwrap(), 0x31);
+
+        boot.write_byte(0xFF50, 0x01).unwrap();
+        assert!(!boot.active);
+        assert!(!boot.contains(0x0000));
+        assert!(boot.contains(0xFF50));
+        assert_eq!(boot.read_byte(0xFF50).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn cgb_mapping() {
+        let boot = BootRom::new(vec![0; CGB_BOOT_ROM_LEN]).unwrap();
+        assert!(boot.contains(0x00FF));
+        assert!(!boot.contains(0x0100));
+        assert!(!boot.contains(0x01FF));
+        assert!(boot.contains(0x0200));
+        assert!(boot.contains(0x08FF));
+        assert!(!boot.contains(0x0900));
+    }
+
+    #[test]
+    fn zero_write_keeps_mapping() {
+        let mut boot = BootRom::new(vec![0; DMG_BOOT_ROM_LEN]).unwrap();
+        boot.write_byte(0xFF50, 0x00).unwrap();
+        assert!(boot.active);
+    }
+}
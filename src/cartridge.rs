@@ -1,12 +1,61 @@
 use crate::cartridge_header::*;
+use crate::infrared::InfraredPeer;
 use crate::memory_device::*;
+use crate::rtc::{self, RealTimeClock};
+use crate::save_state::{SaveState, StateReader};
 use std::fs;
 
-#[allow(dead_code)]
+/// A cartridge is a memory device that can also carry hardware powered by its own battery,
+/// like external RAM and a real time clock, that survives when the console is switched off.
+/// Its save state holds the bank registers and RAM, the ROM comes with the emulator.
+pub trait Cartridge: ReadWrite + SaveState {
+    fn header(&self) -> Option<&CartridgeHeader> {
+        None
+    }
+
+    /// Advances hardware living on the cartridge, cycles are master clock cycles.
+    fn step(&mut self, _cycles: u32) {}
+
+    /// Content of the `.sav` file, if the cartridge has a battery.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores battery-backed state from the content of a `.sav` file.
+    fn load_save_data(&mut self, _data: &[u8]) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    /// Puts something in front of the infrared LED and sensor of cartridges carrying them,
+    /// like HuC1 and HuC3. The peer is stepped with the cartridge.
+    fn connect_infrared(&mut self, _peer: Box<dyn InfraredPeer>) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "this cartridge has no infrared port.",
+        ))
+    }
+}
+
+fn load_ram(ram: &mut [u8], data: &[u8]) -> Result<(), std::io::Error> {
+    if data.len() < ram.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "save file too short: {} bytes of {}.",
+                data.len(),
+                ram.len()
+            ),
+        ));
+    }
+
+    ram.copy_from_slice(&data[..ram.len()]);
+    Ok(())
+}
+
 pub struct NoMBCartridge {
     header: CartridgeHeader,
 
-    // 0x0150-0x3FFF
+    // 0x0000-0x7FFF
     rom: Vec<u8>,
 }
 
@@ -18,7 +67,7 @@ impl NoMBCartridge {
 
 impl ReadWrite for NoMBCartridge {
     fn contains(&self, address: usize) -> bool {
-        (0x0000..=0x3FFF).contains(&address)
+        (0x0000..=0x7FFF).contains(&address)
     }
 
     fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
@@ -32,15 +81,30 @@ impl ReadWrite for NoMBCartridge {
     }
 
     fn write_byte(&mut self, _address: usize, _value: u8) -> Result<(), std::io::Error> {
-        unimplemented!()
+        // Without a memory bank controller writes to ROM go nowhere, games still issue
+        // bank switches (Tetris writes 0x2000) that have to be ignored.
+        Ok(())
     }
 
     fn write_word(&mut self, _address: usize, _value: u16) -> Result<(), std::io::Error> {
-        unimplemented!();
+        Ok(())
+    }
+}
+
+impl SaveState for NoMBCartridge {
+    fn save_state(&self, _out: &mut Vec<u8>) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+impl Cartridge for NoMBCartridge {
+    fn header(&self) -> Option<&CartridgeHeader> {
+        Some(&self.header)
     }
 }
 
-#[allow(dead_code)]
 pub struct MBC1 {
     header: CartridgeHeader,
 
@@ -68,7 +132,7 @@ impl MBC1 {
         MBC1 {
             header,
             rom,
-            ram: Vec::with_capacity(ram_size),
+            ram: vec![0; ram_size],
             ram_enable: false,
             romram_mode: false,
             bank: 0x01,
@@ -150,8 +214,7 @@ impl ReadWrite for MBC1 {
             }
             0xA000..=0xBFFF => {
                 if self.ram_enable {
-                    let i =
-                        self.ram_bank() as usize * 0x2000_usize + address as usize - 0xA000_usize;
+                    let i = self.ram_bank() as usize * 0x2000_usize + address - 0xA000_usize;
                     self.ram[i] = value;
                 }
             }
@@ -166,13 +229,245 @@ impl ReadWrite for MBC1 {
     }
 }
 
-pub fn make_cartridge(filename: &str) -> Result<Box<dyn ReadWrite>, std::io::Error> {
+impl SaveState for MBC1 {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ram);
+        out.extend_from_slice(&[self.ram_enable as u8, self.romram_mode as u8, self.bank]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        state.fill(&mut self.ram)?;
+        self.ram_enable = state.bool()?;
+        self.romram_mode = state.bool()?;
+        self.bank = state.u8()?;
+        Ok(())
+    }
+}
+
+impl Cartridge for MBC1 {
+    fn header(&self) -> Option<&CartridgeHeader> {
+        Some(&self.header)
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.header.battery {
+            return None;
+        }
+
+        Some(self.ram.clone())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        load_ram(&mut self.ram, data)
+    }
+}
+
+pub struct MBC3 {
+    header: CartridgeHeader,
+
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    // 0x0000-0x1FFF: RAM and Timer Enable (write only)
+    //  - 00: Disable RAM and RTC registers (default)
+    //  - 0A: Enable RAM and RTC registers
+    ram_enable: bool,
+
+    // 0x2000-0x3FFF: ROM Bank Number (write only), 7 bits, 00h selects bank 01h.
+    rom_bank: u8,
+
+    // 0x4000-0x5FFF: RAM Bank Number or RTC Register Select (write only)
+    //  - 00-03: map the corresponding external RAM bank into 0xA000-0xBFFF
+    //  - 08-0C: map the corresponding RTC register into 0xA000-0xBFFF
+    ram_bank: u8,
+
+    // Only cartridges with "TIMER" in their type have a clock.
+    rtc: Option<RealTimeClock>,
+}
+
+impl MBC3 {
+    fn new(rom: Vec<u8>, header: CartridgeHeader) -> MBC3 {
+        let ram_size = header.ram_in_bytes();
+        let rtc = if header.timer {
+            Some(RealTimeClock::default())
+        } else {
+            None
+        };
+
+        MBC3 {
+            header,
+            rom,
+            ram: vec![0; ram_size],
+            ram_enable: false,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            rtc,
+        }
+    }
+
+    fn ram_index(&self, address: usize) -> usize {
+        self.ram_bank as usize * 0x2000_usize + address - 0xA000_usize
+    }
+}
+
+impl ReadWrite for MBC3 {
+    fn contains(&self, address: usize) -> bool {
+        (0x0000..=0x7FFF).contains(&address) || (0xA000..=0xBFFF).contains(&address)
+    }
+
+    fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
+        match address {
+            0x0000..=0x3FFF => Ok(self.rom[address]),
+            0x4000..=0x7FFF => {
+                let i = self.rom_bank as usize * 0x4000_usize + address - 0x4000_usize;
+                Ok(self.rom[i % self.rom.len()])
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enable {
+                    return Ok(0xFF);
+                }
+
+                match (self.ram_bank, &self.rtc) {
+                    (0x08..=0x0C, Some(rtc)) => Ok(rtc.read(self.ram_bank)),
+                    (0x00..=0x07, _) => Ok(self
+                        .ram
+                        .get(self.ram_index(address))
+                        .copied()
+                        .unwrap_or(0xFF)),
+                    _ => Ok(0xFF),
+                }
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("MBC3 can't read byte at {:#04x}", address),
+            )),
+        }
+    }
+
+    fn read_word(&self, address: usize) -> Result<u16, std::io::Error> {
+        let low = self.read_byte(address)?;
+        let high = self.read_byte(address + 1)?;
+        Ok(u16::from(low) | (u16::from(high) << 8))
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = match value & 0x7F {
+                    0x00 => 0x01,
+                    n => n,
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write_latch(value);
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enable {
+                    return Ok(());
+                }
+
+                let i = self.ram_index(address);
+                match (self.ram_bank, self.rtc.as_mut()) {
+                    (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_bank, value),
+                    (0x00..=0x07, _) => {
+                        if let Some(b) = self.ram.get_mut(i) {
+                            *b = value;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("MBC3 can't write byte at {:#04x}", address),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_word(&mut self, address: usize, value: u16) -> Result<(), std::io::Error> {
+        self.write_byte(address, (value & 0xFF) as u8)?;
+        self.write_byte(address + 1, (value >> 8) as u8)
+    }
+}
+
+impl SaveState for MBC3 {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ram);
+        out.extend_from_slice(&[self.ram_enable as u8, self.rom_bank, self.ram_bank]);
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(out);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        state.fill(&mut self.ram)?;
+        self.ram_enable = state.bool()?;
+        self.rom_bank = state.u8()?;
+        self.ram_bank = state.u8()?;
+        match &mut self.rtc {
+            Some(rtc) => rtc.load_state(state),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Cartridge for MBC3 {
+    fn header(&self) -> Option<&CartridgeHeader> {
+        Some(&self.header)
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.step(cycles);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.header.battery {
+            return None;
+        }
+
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.footer(rtc::unix_timestamp()));
+        }
+
+        Some(data)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        load_ram(&mut self.ram, data)?;
+
+        let footer = &data[self.ram.len()..];
+        if footer.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(rtc) = self.rtc.as_mut() {
+            let (mut loaded, timestamp) = RealTimeClock::from_footer(footer)?;
+            loaded.fast_forward(rtc::unix_timestamp().saturating_sub(timestamp));
+            *rtc = loaded;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn make_cartridge(filename: &str) -> Result<Box<dyn Cartridge>, std::io::Error> {
     let data = fs::read(filename)?;
     let header = CartridgeHeader::new(&data)?;
-    println!("Cartridge type {}", header.memory_bank_type);
     match header.memory_bank_type {
         MemoryBankType::NoMemoryBank => Ok(Box::new(NoMBCartridge::new(data, header))),
         MemoryBankType::MBC1 => Ok(Box::new(MBC1::new(data, header))),
+        MemoryBankType::MBC3 => Ok(Box::new(MBC3::new(data, header))),
         _ => Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             "no implementation for this memory bank type.",
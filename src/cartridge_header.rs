@@ -1,10 +1,20 @@
 use std::{fmt, num::Wrapping};
 
 pub struct CartridgeHeader {
-    // title: String,
+    pub title: String,
+    // Sum of the title bytes 0x0134-0x0143, used by the CGB boot ROM to pick a palette for DMG games.
+    pub title_checksum: u8,
+    // Only games published by Nintendo get colorized by the CGB boot ROM.
+    pub nintendo_licensee: bool,
     pub memory_bank_type: MemoryBankType,
     ram_size: RamSize,
-    // gameboy_color_support: GameBoyColorFlag,
+    // Cartridge RAM is kept alive by a battery, so it needs to be saved.
+    pub battery: bool,
+    // Cartridge has a real time clock (MBC3 + TIMER).
+    pub timer: bool,
+    pub gameboy_color_support: GameBoyColorFlag,
+    // Checksum of the header at 0x014D, its value also changes the flags left by the boot ROM.
+    pub header_checksum: u8,
 }
 
 /// Specifies which Memory Bank Controller (if any) is used in
@@ -41,7 +51,7 @@ const NINTENDO_LOGO: [u8; 48] = [
 ];
 
 /// original games have all nintengo logo bytes inside its cartridge.
-fn check_logo(data: &[u8]) -> Result<(), std::io::Error> {
+pub fn check_logo(data: &[u8]) -> Result<(), std::io::Error> {
     match data[0x104..0x134].iter().cmp(NINTENDO_LOGO.iter()) {
         std::cmp::Ordering::Equal => Ok(()),
         std::cmp::Ordering::Less | std::cmp::Ordering::Greater => Err(std::io::Error::new(
@@ -81,12 +91,48 @@ fn decode_memory_bank_type(data: &[u8]) -> MemoryBankType {
     }
 }
 
+fn decode_title(data: &[u8]) -> String {
+    data[0x134..0x143]
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as char)
+        .collect()
+}
+
+fn decode_title_checksum(data: &[u8]) -> u8 {
+    data[0x134..=0x143]
+        .iter()
+        .fold(0, |acc: u8, &v| acc.wrapping_add(v))
+}
+
+/// Old licensee code is at 0x014B, 0x33 means the new one at 0x0144-0x0145 is used instead.
+fn decode_nintendo_licensee(data: &[u8]) -> bool {
+    match data[0x14B] {
+        0x01 => true,
+        0x33 => &data[0x144..=0x145] == b"01",
+        _ => false,
+    }
+}
+
+fn decode_battery(data: &[u8]) -> bool {
+    matches!(
+        data[0x147],
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+    )
+}
+
+fn decode_timer(data: &[u8]) -> bool {
+    matches!(data[0x147], 0x0F | 0x10)
+}
+
 /// Specifies the size of the external RAM in the cartridge (if any).
 enum RamSize {
     None,
     OneBankOf2Kb,
     OneBankOf8Kb,
     FourBankOf8Kb,
+    SixteenBankOf8Kb,
+    EightBankOf8Kb,
 }
 
 impl From<u8> for RamSize {
@@ -96,6 +142,8 @@ impl From<u8> for RamSize {
             0x1 => RamSize::OneBankOf2Kb,
             0x2 => RamSize::OneBankOf8Kb,
             0x3 => RamSize::FourBankOf8Kb,
+            0x4 => RamSize::SixteenBankOf8Kb,
+            0x5 => RamSize::EightBankOf8Kb,
             _ => panic!("unknown ram size"),
         }
     }
@@ -105,12 +153,13 @@ impl From<u8> for RamSize {
 /// This is required, otherwise the CGB switches itself into Non-CGB-Mode.
 ///  - 80h: Game supports CGB functions, but works on old gameboys also.
 ///  - C0h: Game works on CGB only (physically the same as 80h).
+///
 /// Values with Bit 7 set, and either Bit 2 or 3 set, will switch the gameboy
 /// into a special non-CGB-mode with uninitialized palettes. Purpose unknown,
 /// eventually this has been supposed to be used to colorize monochrome games
 /// that include fixed palette data at a special location in ROM.
 #[derive(Debug, PartialEq, Eq)]
-enum GameBoyColorFlag {
+pub enum GameBoyColorFlag {
     /// Uses GB features only; default
     GB,
     /// Uses CGB features but works on GB
@@ -129,16 +178,27 @@ impl From<u8> for GameBoyColorFlag {
     }
 }
 
+impl GameBoyColorFlag {
+    pub fn supports_cgb(&self) -> bool {
+        *self != GameBoyColorFlag::GB
+    }
+}
+
 impl CartridgeHeader {
     pub fn new(data: &[u8]) -> Result<Self, std::io::Error> {
         check_logo(data)?;
         valid_checksum(data)?;
 
         Ok(CartridgeHeader {
-            // title: String::from_utf8(t).unwrap(),
+            title: decode_title(data),
+            title_checksum: decode_title_checksum(data),
+            nintendo_licensee: decode_nintendo_licensee(data),
             memory_bank_type: decode_memory_bank_type(data),
             ram_size: data[0x149].into(),
-            // gameboy_color_support: data[0x149].into(),
+            battery: decode_battery(data),
+            timer: decode_timer(data),
+            gameboy_color_support: data[0x143].into(),
+            header_checksum: data[0x14D],
         })
     }
 
@@ -148,6 +208,8 @@ impl CartridgeHeader {
             RamSize::OneBankOf2Kb => 2 * 1024,
             RamSize::OneBankOf8Kb => 8 * 1024,
             RamSize::FourBankOf8Kb => 4 * (8 * 1024),
+            RamSize::SixteenBankOf8Kb => 16 * (8 * 1024),
+            RamSize::EightBankOf8Kb => 8 * (8 * 1024),
         }
     }
 }
@@ -167,20 +229,22 @@ mod tests {
             .collect::<Vec<u8>>();
         assert_eq!(32768, data.len());
         let header = CartridgeHeader::new(&data);
-        assert_eq!(header.is_err(), false);
+        assert!(header.is_ok());
     }
 
-    // FIXME: enable it when title is needed
-    // #[test]
-    // fn verify_title() {
-    //     let data = fs::read_to_string("./testdata/tetris")
-    //         .expect("file not found!")
-    //         .split(',')
-    //         .map(|n| n.parse().unwrap())
-    //         .collect::<Vec<u8>>();
-    //     let header = CartridgeHeader::new(&data);
-    //     assert_eq!(header.unwrap().title, "TETRIS");
-    // }
+    #[test]
+    fn verify_title() {
+        let data = fs::read_to_string("./testdata/tetris")
+            .expect("file not found!")
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect::<Vec<u8>>();
+        let header = CartridgeHeader::new(&data).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert!(header.nintendo_licensee);
+        // "TETRIS" is the sum of its ASCII letters.
+        assert_eq!(header.title_checksum, 0xDB);
+    }
 
     #[test]
     fn verify_memory_bank_type() {
@@ -207,15 +271,26 @@ mod tests {
         assert_eq!(header.unwrap().ram_in_bytes(), 0);
     }
 
-    // FIXME: enable it when gameboy_color_support is needed
-    // #[test]
-    // fn verify_gameboy_color_support() {
-    //     let data = fs::read_to_string("./testdata/tetris")
-    //         .expect("file not found!")
-    //         .split(',')
-    //         .map(|n| n.parse().unwrap())
-    //         .collect::<Vec<u8>>();
-    //     let header = CartridgeHeader::new(&data);
-    //     assert_eq!(header.unwrap().gameboy_color_support, GameBoyColorFlag::GB);
-    // }
+    #[test]
+    fn verify_battery_and_timer() {
+        let data = fs::read_to_string("./testdata/tetris")
+            .expect("file not found!")
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect::<Vec<u8>>();
+        let header = CartridgeHeader::new(&data).unwrap();
+        assert!(!header.battery);
+        assert!(!header.timer);
+    }
+
+    #[test]
+    fn verify_gameboy_color_support() {
+        let data = fs::read_to_string("./testdata/tetris")
+            .expect("file not found!")
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect::<Vec<u8>>();
+        let header = CartridgeHeader::new(&data);
+        assert_eq!(header.unwrap().gameboy_color_support, GameBoyColorFlag::GB);
+    }
 }
@@ -2,7 +2,13 @@ use core::panic;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::{memory_device::ReadWrite, opcodes::*, prefix_opcodes::PrefixOpCode, register::*};
+use crate::{
+    memory_device::ReadWrite,
+    opcodes::*,
+    prefix_opcodes::PrefixOpCode,
+    register::*,
+    save_state::{SaveState, StateReader},
+};
 
 // One cycle of the master clock is called a "clock", or a "t-cycle".
 // It can either equal 0.25 µs, or 0.125 µs in CGB double-speed.
@@ -16,6 +22,9 @@ pub struct CentralProcessingUnit {
     // Interrupt master enable flag is reset by DI and prohibits all interrupts.
     // It is set by EI and acknowledges the interrupt setting by the IE register.
     ime: bool,
+
+    // Prints every opcode executed.
+    trace: bool,
 }
 
 impl CentralProcessingUnit {
@@ -26,9 +35,40 @@ impl CentralProcessingUnit {
             stop: false,
             halt: false,
             ime: false,
+            trace: false,
         }
     }
 
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.registers.program_counter
+    }
+
+    /// Jumps to `address` as a CALL placed at `return_address` would, waking the CPU up.
+    /// Players of ripped music drive the sound driver this way.
+    pub fn call(&mut self, address: u16, return_address: u16) {
+        self.halt = false;
+        self.stop = false;
+        self.stack_add(return_address);
+        self.registers.program_counter = address;
+    }
+
+    /// True once STOP ran, nothing wakes it up but `call`.
+    pub fn is_stopped(&self) -> bool {
+        self.stop
+    }
+
     pub fn need_toggle_speed(&self) -> bool {
         self.registers.program_counter == 0x10
     }
@@ -48,7 +88,9 @@ impl CentralProcessingUnit {
 
         let op_code = self.fetch_byte();
         let opcode: OpCode = op_code.into();
-        println!("{} (0x{:02x})", opcode, op_code);
+        if self.trace {
+            println!("{} (0x{:02x})", opcode, op_code);
+        }
 
         match opcode {
             OpCode::LdBNext => self.ld_r_next(Register::B),
@@ -302,7 +344,7 @@ impl CentralProcessingUnit {
     fn fetch_byte(&mut self) -> u8 {
         let address = self.registers.program_counter as usize;
         self.registers.program_counter += 1;
-        match self.mmu.as_ref().borrow().read_byte(address as usize) {
+        match self.mmu.as_ref().borrow().read_byte(address) {
             Ok(v) => v,
             Err(e) => panic!("{}", e),
         }
@@ -310,7 +352,7 @@ impl CentralProcessingUnit {
 
     fn fetch_word(&mut self) -> u16 {
         let address = self.registers.program_counter as usize;
-        let word = match self.mmu.as_ref().borrow().read_word(address as usize) {
+        let word = match self.mmu.as_ref().borrow().read_word(address) {
             Ok(v) => v,
             Err(e) => panic!("{}", e),
         };
@@ -334,7 +376,7 @@ impl CentralProcessingUnit {
     fn halt(&mut self) -> u8 {
         self.halt = true;
 
-        0
+        4
     }
 
     fn ld_r_next(&mut self, reg: Register) -> u8 {
@@ -1438,6 +1480,78 @@ impl CentralProcessingUnit {
             PrefixOpCode::SwapH => self.swap_r(Register::H),
             PrefixOpCode::SwapL => self.swap_r(Register::L),
             PrefixOpCode::SwapA => self.swap_r(Register::A),
+
+            PrefixOpCode::RlB => self.rl_r(Register::B),
+            PrefixOpCode::RlC => self.rl_r(Register::C),
+            PrefixOpCode::RlD => self.rl_r(Register::D),
+            PrefixOpCode::RlE => self.rl_r(Register::E),
+            PrefixOpCode::RlH => self.rl_r(Register::H),
+            PrefixOpCode::RlL => self.rl_r(Register::L),
+            PrefixOpCode::RlA => self.rl_r(Register::A),
+
+            PrefixOpCode::Bit0B => self.bit_r(0, Register::B),
+            PrefixOpCode::Bit0C => self.bit_r(0, Register::C),
+            PrefixOpCode::Bit0D => self.bit_r(0, Register::D),
+            PrefixOpCode::Bit0E => self.bit_r(0, Register::E),
+            PrefixOpCode::Bit0H => self.bit_r(0, Register::H),
+            PrefixOpCode::Bit0L => self.bit_r(0, Register::L),
+            PrefixOpCode::Bit0A => self.bit_r(0, Register::A),
+
+            PrefixOpCode::Bit1B => self.bit_r(1, Register::B),
+            PrefixOpCode::Bit1C => self.bit_r(1, Register::C),
+            PrefixOpCode::Bit1D => self.bit_r(1, Register::D),
+            PrefixOpCode::Bit1E => self.bit_r(1, Register::E),
+            PrefixOpCode::Bit1H => self.bit_r(1, Register::H),
+            PrefixOpCode::Bit1L => self.bit_r(1, Register::L),
+            PrefixOpCode::Bit1A => self.bit_r(1, Register::A),
+
+            PrefixOpCode::Bit2B => self.bit_r(2, Register::B),
+            PrefixOpCode::Bit2C => self.bit_r(2, Register::C),
+            PrefixOpCode::Bit2D => self.bit_r(2, Register::D),
+            PrefixOpCode::Bit2E => self.bit_r(2, Register::E),
+            PrefixOpCode::Bit2H => self.bit_r(2, Register::H),
+            PrefixOpCode::Bit2L => self.bit_r(2, Register::L),
+            PrefixOpCode::Bit2A => self.bit_r(2, Register::A),
+
+            PrefixOpCode::Bit3B => self.bit_r(3, Register::B),
+            PrefixOpCode::Bit3C => self.bit_r(3, Register::C),
+            PrefixOpCode::Bit3D => self.bit_r(3, Register::D),
+            PrefixOpCode::Bit3E => self.bit_r(3, Register::E),
+            PrefixOpCode::Bit3H => self.bit_r(3, Register::H),
+            PrefixOpCode::Bit3L => self.bit_r(3, Register::L),
+            PrefixOpCode::Bit3A => self.bit_r(3, Register::A),
+
+            PrefixOpCode::Bit4B => self.bit_r(4, Register::B),
+            PrefixOpCode::Bit4C => self.bit_r(4, Register::C),
+            PrefixOpCode::Bit4D => self.bit_r(4, Register::D),
+            PrefixOpCode::Bit4E => self.bit_r(4, Register::E),
+            PrefixOpCode::Bit4H => self.bit_r(4, Register::H),
+            PrefixOpCode::Bit4L => self.bit_r(4, Register::L),
+            PrefixOpCode::Bit4A => self.bit_r(4, Register::A),
+
+            PrefixOpCode::Bit5B => self.bit_r(5, Register::B),
+            PrefixOpCode::Bit5C => self.bit_r(5, Register::C),
+            PrefixOpCode::Bit5D => self.bit_r(5, Register::D),
+            PrefixOpCode::Bit5E => self.bit_r(5, Register::E),
+            PrefixOpCode::Bit5H => self.bit_r(5, Register::H),
+            PrefixOpCode::Bit5L => self.bit_r(5, Register::L),
+            PrefixOpCode::Bit5A => self.bit_r(5, Register::A),
+
+            PrefixOpCode::Bit6B => self.bit_r(6, Register::B),
+            PrefixOpCode::Bit6C => self.bit_r(6, Register::C),
+            PrefixOpCode::Bit6D => self.bit_r(6, Register::D),
+            PrefixOpCode::Bit6E => self.bit_r(6, Register::E),
+            PrefixOpCode::Bit6H => self.bit_r(6, Register::H),
+            PrefixOpCode::Bit6L => self.bit_r(6, Register::L),
+            PrefixOpCode::Bit6A => self.bit_r(6, Register::A),
+
+            PrefixOpCode::Bit7B => self.bit_r(7, Register::B),
+            PrefixOpCode::Bit7C => self.bit_r(7, Register::C),
+            PrefixOpCode::Bit7D => self.bit_r(7, Register::D),
+            PrefixOpCode::Bit7E => self.bit_r(7, Register::E),
+            PrefixOpCode::Bit7H => self.bit_r(7, Register::H),
+            PrefixOpCode::Bit7L => self.bit_r(7, Register::L),
+            PrefixOpCode::Bit7A => self.bit_r(7, Register::A),
         }
     }
 
@@ -1499,6 +1613,23 @@ impl CentralProcessingUnit {
         8
     }
 
+    fn rl_r(&mut self, reg: Register) -> u8 {
+        let r = self.registers.get_register(&reg);
+        let result = self.alu_rl(r);
+        self.registers.set_register(&reg, result);
+
+        8
+    }
+
+    fn bit_r(&mut self, bit: u8, reg: Register) -> u8 {
+        let r = self.registers.get_register(&reg);
+        self.registers.flags.zero = r & (1 << bit) == 0;
+        self.registers.flags.negative = false;
+        self.registers.flags.half_carry = true;
+
+        8
+    }
+
     fn swap_r(&mut self, reg: Register) -> u8 {
         let r = match reg {
             Register::B => &mut self.registers.b,
@@ -1510,7 +1641,7 @@ impl CentralProcessingUnit {
             Register::A => &mut self.registers.a,
         };
 
-        let result = (*r >> 4) | (*r << 4);
+        let result = (*r).rotate_left(4);
         self.registers.flags.zero = result == 0;
         self.registers.flags.negative = false;
         self.registers.flags.half_carry = false;
@@ -1534,6 +1665,21 @@ impl CentralProcessingUnit {
     }
 }
 
+impl SaveState for CentralProcessingUnit {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.registers.save_state(out);
+        out.extend_from_slice(&[self.stop as u8, self.halt as u8, self.ime as u8]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.registers.load_state(state)?;
+        self.stop = state.bool()?;
+        self.halt = state.bool()?;
+        self.ime = state.bool()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::cell::RefCell;
@@ -1541,7 +1687,7 @@ mod tests {
     use std::rc::Rc;
 
     use crate::memory_device::ReadWrite;
-    use crate::register::{ConditionOperand, Register, RegisterWord};
+    use crate::register::{ConditionOperand, Register, RegisterWord, Registers};
 
     use super::CentralProcessingUnit;
 
@@ -1580,6 +1726,19 @@ mod tests {
         }};
     }
 
+    #[test]
+    fn verify_set_registers() {
+        let mc = Rc::new(RefCell::new(MockDevice {
+            bytes: collection! {},
+            words: collection! {},
+        }));
+        let mut cpu = CentralProcessingUnit::new(mc.clone());
+        cpu.set_registers(Registers::default());
+        assert_eq!(cpu.registers.program_counter, 0x0000);
+        assert_eq!(cpu.registers.af(), 0x0000);
+        assert_eq!(cpu.registers.stack_pointer, 0x0000);
+    }
+
     #[test]
     fn verify_ld_r_next() {
         let mc = Rc::new(RefCell::new(MockDevice {
@@ -1789,10 +1948,10 @@ mod tests {
             let cycle = cpu.rlca();
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 2);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -1804,10 +1963,10 @@ mod tests {
             let cycle = cpu.rlca();
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 4);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
     }
 
@@ -1823,10 +1982,10 @@ mod tests {
             let cycle = cpu.rr_a();
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 128);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -1838,10 +1997,10 @@ mod tests {
             let cycle = cpu.rr_a();
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 129);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -1853,10 +2012,10 @@ mod tests {
             let cycle = cpu.rr_a();
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 129);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
     }
 
@@ -1926,10 +2085,10 @@ mod tests {
             let cycle = cpu.inc_r(Register::A);
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 16);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -1941,10 +2100,10 @@ mod tests {
             let cycle = cpu.inc_r(Register::A);
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 2);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
     }
 
@@ -1959,10 +2118,10 @@ mod tests {
         let cycle = cpu.inc_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.hl(), 11);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -1977,10 +2136,10 @@ mod tests {
             let cycle = cpu.dec_r(Register::A);
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 15);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, true);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -1992,10 +2151,10 @@ mod tests {
             let cycle = cpu.dec_r(Register::A);
             assert_eq!(cycle, 4);
             assert_eq!(cpu.registers.a, 0);
-            assert_eq!(cpu.registers.flags.zero, true);
-            assert_eq!(cpu.registers.flags.negative, true);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(cpu.registers.flags.zero);
+            assert!(cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
     }
 
@@ -2080,10 +2239,10 @@ mod tests {
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 60);
         assert_eq!(cpu.registers.b, 20);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2098,10 +2257,10 @@ mod tests {
             let cycle = cpu.and_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 0);
-            assert_eq!(cpu.registers.flags.zero, true);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -2113,10 +2272,10 @@ mod tests {
             let cycle = cpu.and_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 3);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
     }
 
@@ -2203,10 +2362,10 @@ mod tests {
             let cycle = cpu.add_a_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 21);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -2218,10 +2377,10 @@ mod tests {
             let cycle = cpu.add_a_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 255);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
     }
 
@@ -2237,10 +2396,10 @@ mod tests {
             let cycle = cpu.sub_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 70);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, true);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -2252,10 +2411,10 @@ mod tests {
             let cycle = cpu.sub_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 0);
-            assert_eq!(cpu.registers.flags.zero, true);
-            assert_eq!(cpu.registers.flags.negative, true);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(cpu.registers.flags.zero);
+            assert!(cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
     }
 
@@ -2271,10 +2430,10 @@ mod tests {
         let cycle = cpu.xor_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 91);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2288,10 +2447,10 @@ mod tests {
         let cycle = cpu.rlc_r(Register::C);
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.c, 6);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2305,10 +2464,46 @@ mod tests {
         let cycle = cpu.srl_r(Register::C);
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.c, 1);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
+    }
+
+    #[test]
+    fn verify_rl_r() {
+        let mc = Rc::new(RefCell::new(MockDevice {
+            bytes: collection! {},
+            words: collection! {},
+        }));
+        let mut cpu = CentralProcessingUnit::new(mc.clone());
+        cpu.registers.c = 0x81;
+        cpu.registers.flags.carry = false;
+        let cycle = cpu.rl_r(Register::C);
+        assert_eq!(cycle, 8);
+        assert_eq!(cpu.registers.c, 0x02);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.carry);
+    }
+
+    #[test]
+    fn verify_bit_r() {
+        let mc = Rc::new(RefCell::new(MockDevice {
+            bytes: collection! {},
+            words: collection! {},
+        }));
+        let mut cpu = CentralProcessingUnit::new(mc.clone());
+        cpu.registers.h = 0x80;
+        cpu.registers.flags.carry = true;
+        let cycle = cpu.bit_r(7, Register::H);
+        assert_eq!(cycle, 8);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
+
+        cpu.bit_r(6, Register::H);
+        assert!(cpu.registers.flags.zero);
     }
 
     #[test]
@@ -2323,10 +2518,10 @@ mod tests {
             let cycle = cpu.rr_r(Register::B);
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.b, 129);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
     }
 
@@ -2341,10 +2536,10 @@ mod tests {
         let cycle = cpu.xor_n();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 11);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2359,10 +2554,10 @@ mod tests {
             let cycle = cpu.adc_a_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 11);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -2374,10 +2569,10 @@ mod tests {
             let cycle = cpu.adc_a_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 1);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
     }
 
@@ -2419,10 +2614,10 @@ mod tests {
         let cycle = cpu.or_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 15);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2436,10 +2631,10 @@ mod tests {
         let cycle = cpu.dec_hl();
         assert_eq!(cycle, 12);
         assert_eq!(cpu.mmu.as_ref().borrow().read_byte(16).unwrap(), 98);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2454,10 +2649,10 @@ mod tests {
         let cycle = cpu.add_hl_rr(RegisterWord::BC);
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.hl(), 110);
-        assert_eq!(cpu.registers.flags.zero, true);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2500,10 +2695,10 @@ mod tests {
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 5);
             assert_eq!(cpu.registers.b, 160);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
     }
 
@@ -2519,10 +2714,10 @@ mod tests {
             let cycle = cpu.or_n();
             assert_eq!(cycle, 8);
             assert_eq!(cpu.registers.a, 11);
-            assert_eq!(cpu.registers.flags.zero, false);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, false);
-            assert_eq!(cpu.registers.flags.carry, false);
+            assert!(!cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(!cpu.registers.flags.half_carry);
+            assert!(!cpu.registers.flags.carry);
         }
     }
 
@@ -2537,10 +2732,10 @@ mod tests {
             let cycle = cpu.jp_f_nn(ConditionOperand::NZ);
             assert_eq!(cycle, 12);
             assert_eq!(cpu.registers.program_counter, 258);
-            assert_eq!(cpu.registers.flags.zero, true);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
         {
             let mc = Rc::new(RefCell::new(MockDevice {
@@ -2551,10 +2746,10 @@ mod tests {
             let cycle = cpu.jp_f_nn(ConditionOperand::Z);
             assert_eq!(cycle, 16);
             assert_eq!(cpu.registers.program_counter, 558);
-            assert_eq!(cpu.registers.flags.zero, true);
-            assert_eq!(cpu.registers.flags.negative, false);
-            assert_eq!(cpu.registers.flags.half_carry, true);
-            assert_eq!(cpu.registers.flags.carry, true);
+            assert!(cpu.registers.flags.zero);
+            assert!(!cpu.registers.flags.negative);
+            assert!(cpu.registers.flags.half_carry);
+            assert!(cpu.registers.flags.carry);
         }
     }
 
@@ -2570,10 +2765,10 @@ mod tests {
         let cycle = cpu.add_a_r(Register::B);
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 15);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2587,10 +2782,10 @@ mod tests {
         assert_eq!(cycle, 12);
         assert_eq!(cpu.registers.stack_pointer, 65534);
         assert_eq!(cpu.registers.hl(), 18);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2644,10 +2839,10 @@ mod tests {
         let cycle = cpu.add_a_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 11);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2663,10 +2858,10 @@ mod tests {
         let cycle = cpu.adc_r(Register::B);
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 17);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2680,10 +2875,10 @@ mod tests {
         let cycle = cpu.adc_a_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 28);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2698,10 +2893,10 @@ mod tests {
         let cycle = cpu.sub_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 15);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2717,10 +2912,10 @@ mod tests {
         let cycle = cpu.sbc_a_r(Register::B);
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 9);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2735,10 +2930,10 @@ mod tests {
         let cycle = cpu.sbc_a_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 6);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2752,10 +2947,10 @@ mod tests {
         let cycle = cpu.sbc_a_n();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 6);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2770,10 +2965,10 @@ mod tests {
         let cycle = cpu.and_r(Register::B);
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 0);
-        assert_eq!(cpu.registers.flags.zero, true);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2788,10 +2983,10 @@ mod tests {
         let cycle = cpu.and_hl();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 0);
-        assert_eq!(cpu.registers.flags.zero, true);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2807,10 +3002,10 @@ mod tests {
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 64);
         assert_eq!(cpu.registers.c, 60);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2826,10 +3021,10 @@ mod tests {
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.a, 1);
         assert_eq!(cpu.registers.hl(), 5);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2842,10 +3037,10 @@ mod tests {
         let cycle = cpu.add_sp();
         assert_eq!(cycle, 8);
         assert_eq!(cpu.registers.stack_pointer, 8);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2859,10 +3054,10 @@ mod tests {
         let cycle = cpu.daa();
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 113);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2876,10 +3071,10 @@ mod tests {
         let cycle = cpu.cpl();
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 244);
-        assert_eq!(cpu.registers.flags.zero, true);
-        assert_eq!(cpu.registers.flags.negative, true);
-        assert_eq!(cpu.registers.flags.half_carry, true);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(cpu.registers.flags.zero);
+        assert!(cpu.registers.flags.negative);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2891,10 +3086,10 @@ mod tests {
         let mut cpu = CentralProcessingUnit::new(mc.clone());
         let cycle = cpu.ccf();
         assert_eq!(cycle, 4);
-        assert_eq!(cpu.registers.flags.zero, true);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2906,10 +3101,10 @@ mod tests {
         let mut cpu = CentralProcessingUnit::new(mc.clone());
         let cycle = cpu.scf();
         assert_eq!(cycle, 4);
-        assert_eq!(cpu.registers.flags.zero, true);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2923,10 +3118,10 @@ mod tests {
         let cycle = cpu.rla();
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 23);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, false);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
     }
 
     #[test]
@@ -2940,10 +3135,10 @@ mod tests {
         let cycle = cpu.rrca();
         assert_eq!(cycle, 4);
         assert_eq!(cpu.registers.a, 133);
-        assert_eq!(cpu.registers.flags.zero, false);
-        assert_eq!(cpu.registers.flags.negative, false);
-        assert_eq!(cpu.registers.flags.half_carry, false);
-        assert_eq!(cpu.registers.flags.carry, true);
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.negative);
+        assert!(!cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
     }
 
     #[test]
@@ -0,0 +1,385 @@
+use std::str::FromStr;
+
+/// 24-bit color as written in the RGB frame buffer.
+pub type Rgb = [u8; 3];
+
+// Colors stored in the CGB boot ROM, four RGB555 colors per palette.
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+// Palette combinations as (OBJ0, OBJ1, BG), each one is the index of the first of four
+// consecutive colors in `PALETTES`. Most start at a palette boundary, a few of them
+// start in the middle of a palette and run into the next one.
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (4 * 4, 4 * 4, 29 * 4),
+    (18 * 4, 18 * 4, 18 * 4),
+    (20 * 4, 20 * 4, 20 * 4),
+    (24 * 4, 24 * 4, 24 * 4),
+    (9 * 4, 9 * 4, 9 * 4),
+    (0, 0, 0),
+    (27 * 4, 27 * 4, 27 * 4),
+    (5 * 4, 5 * 4, 5 * 4),
+    (12 * 4, 12 * 4, 12 * 4),
+    (26 * 4, 26 * 4, 26 * 4),
+    (16 * 4, 8 * 4, 8 * 4),
+    (4 * 4, 28 * 4, 28 * 4),
+    (4 * 4, 2 * 4, 2 * 4),
+    (3 * 4, 4 * 4, 4 * 4),
+    (4 * 4, 29 * 4, 29 * 4),
+    (28 * 4, 4 * 4, 28 * 4),
+    (2 * 4, 17 * 4, 2 * 4),
+    (16 * 4, 16 * 4, 8 * 4),
+    (4 * 4, 4 * 4, 7 * 4),
+    (4 * 4, 4 * 4, 18 * 4),
+    (4 * 4, 4 * 4, 20 * 4),
+    (19 * 4, 19 * 4, 9 * 4),
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4),
+    (17 * 4, 17 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 3 * 4),
+    (28 * 4, 28 * 4, 0),
+    (3 * 4, 3 * 4, 0),
+    (0, 0, 4),
+    (18 * 4, 22 * 4, 18 * 4),
+    (20 * 4, 22 * 4, 20 * 4),
+    (24 * 4, 22 * 4, 24 * 4),
+    (16 * 4, 22 * 4, 8 * 4),
+    (17 * 4, 4 * 4, 13 * 4),
+    (28 * 4 - 1, 0, 14 * 4),
+    (28 * 4 - 1, 4 * 4, 15 * 4),
+    (19 * 4, 22 * 4, 9 * 4),
+    (16 * 4, 28 * 4, 10 * 4),
+    (4 * 4, 23 * 4, 28 * 4),
+    (17 * 4, 22 * 4, 2 * 4),
+    (4 * 4, 0, 2 * 4),
+    (4 * 4, 28 * 4, 3 * 4),
+    (28 * 4, 3 * 4, 0),
+    (3 * 4, 28 * 4, 4 * 4),
+    (21 * 4, 28 * 4, 4 * 4),
+    (3 * 4, 28 * 4, 0),
+    (25 * 4, 3 * 4, 28 * 4),
+    (0, 28 * 4, 8 * 4),
+    (4 * 4, 3 * 4, 28 * 4),
+    (28 * 4, 3 * 4, 6 * 4),
+    (4 * 4, 28 * 4, 29 * 4),
+];
+
+// Sum of the title bytes of the Nintendo games known by the CGB boot ROM.
+// Checksums from `FIRST_DUPLICATE` on are shared by several games, they also have to match
+// the fourth letter of the title in `DUPLICATE_LETTERS`.
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+    0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+const FIRST_DUPLICATE: usize = 65;
+const DUPLICATE_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Palette combination of each entry of `TITLE_CHECKSUMS`.
+const COMBINATION_PER_CHECKSUM: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// Palette combinations selected holding a direction, optionally with A or B,
+/// while the CGB logo is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManualPalette {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl ManualPalette {
+    fn combination(&self) -> u8 {
+        match self {
+            ManualPalette::Up => 5,
+            ManualPalette::UpA => 43,
+            ManualPalette::UpB => 28,
+            ManualPalette::Left => 48,
+            ManualPalette::LeftA => 40,
+            ManualPalette::LeftB => 7,
+            ManualPalette::Down => 8,
+            ManualPalette::DownA => 3,
+            ManualPalette::DownB => 49,
+            ManualPalette::Right => 1,
+            ManualPalette::RightA => 0,
+            ManualPalette::RightB => 6,
+        }
+    }
+}
+
+impl FromStr for ManualPalette {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "up" => Ok(ManualPalette::Up),
+            "up+a" => Ok(ManualPalette::UpA),
+            "up+b" => Ok(ManualPalette::UpB),
+            "left" => Ok(ManualPalette::Left),
+            "left+a" => Ok(ManualPalette::LeftA),
+            "left+b" => Ok(ManualPalette::LeftB),
+            "down" => Ok(ManualPalette::Down),
+            "down+a" => Ok(ManualPalette::DownA),
+            "down+b" => Ok(ManualPalette::DownB),
+            "right" => Ok(ManualPalette::Right),
+            "right+a" => Ok(ManualPalette::RightA),
+            "right+b" => Ok(ManualPalette::RightB),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown palette {}, use a direction optionally followed by +a or +b.",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+/// Colors shown for the four shades of the DMG palette registers:
+/// BGP (0xFF47) picks from `bg`, OBP0 (0xFF48) from `obj0` and OBP1 (0xFF49) from `obj1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmgPalettes {
+    pub bg: [Rgb; 4],
+    pub obj0: [Rgb; 4],
+    pub obj1: [Rgb; 4],
+}
+
+impl Default for DmgPalettes {
+    fn default() -> Self {
+        let gray = [[0xFF; 3], [0xAA; 3], [0x55; 3], [0x00; 3]];
+        DmgPalettes {
+            bg: gray,
+            obj0: gray,
+            obj1: gray,
+        }
+    }
+}
+
+impl DmgPalettes {
+    /// Palettes the CGB boot ROM picks for a DMG game.
+    /// Only games with a Nintendo licensee are looked up, the others get combination 0.
+    pub fn from_title(nintendo_licensee: bool, title_checksum: u8, fourth_letter: u8) -> Self {
+        if !nintendo_licensee {
+            return DmgPalettes::from_combination(0);
+        }
+
+        let combination = TITLE_CHECKSUMS
+            .iter()
+            .enumerate()
+            .find(|(i, c)| {
+                **c == title_checksum
+                    && (*i < FIRST_DUPLICATE
+                        || DUPLICATE_LETTERS[*i - FIRST_DUPLICATE] == fourth_letter)
+            })
+            .map(|(i, _)| COMBINATION_PER_CHECKSUM[i])
+            .unwrap_or(0);
+
+        DmgPalettes::from_combination(combination)
+    }
+
+    fn from_combination(id: u8) -> Self {
+        let (obj0, obj1, bg) = COMBINATIONS[id as usize];
+        DmgPalettes {
+            bg: colors(bg),
+            obj0: colors(obj0),
+            obj1: colors(obj1),
+        }
+    }
+
+    /// Parses user-defined palettes, one per line as four hex colors from lightest to darkest:
+    ///
+    ///   # classic green LCD
+    ///   bg   = 9BBC0F 8BAC0F 306230 0F380F
+    ///   obj0 = 9BBC0F 8BAC0F 306230 0F380F
+    ///
+    /// `obj0` and `obj1` default to the `bg` colors when missing.
+    pub fn from_config(config: &str) -> Result<Self, std::io::Error> {
+        let mut bg = None;
+        let mut obj0 = None;
+        let mut obj1 = None;
+
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid_config(line))?;
+            let palette = parse_colors(value).ok_or_else(|| invalid_config(line))?;
+            match key.trim() {
+                "bg" => bg = Some(palette),
+                "obj0" => obj0 = Some(palette),
+                "obj1" => obj1 = Some(palette),
+                _ => return Err(invalid_config(line)),
+            }
+        }
+
+        let bg = bg.ok_or_else(|| invalid_config("missing bg palette"))?;
+        Ok(DmgPalettes {
+            bg,
+            obj0: obj0.unwrap_or(bg),
+            obj1: obj1.unwrap_or(bg),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        DmgPalettes::from_config(&std::fs::read_to_string(path)?)
+    }
+}
+
+impl From<ManualPalette> for DmgPalettes {
+    fn from(p: ManualPalette) -> Self {
+        DmgPalettes::from_combination(p.combination())
+    }
+}
+
+fn colors(first: usize) -> [Rgb; 4] {
+    let mut out = [[0; 3]; 4];
+    for (i, color) in out.iter_mut().enumerate() {
+        let index = first + i;
+        *color = rgb555(PALETTES[index / 4][index % 4]);
+    }
+    out
+}
+
+// Scales each 5 bits component to 8 bits, repeating the high bits in the low ones
+// so that 0x1F becomes 0xFF.
+fn rgb555(color: u16) -> Rgb {
+    let scale = |c: u16| ((c & 0x1F) << 3 | (c & 0x1F) >> 2) as u8;
+    [scale(color), scale(color >> 5), scale(color >> 10)]
+}
+
+fn parse_colors(value: &str) -> Option<[Rgb; 4]> {
+    let colors = value
+        .split_whitespace()
+        .map(|c| {
+            let c = c.trim_start_matches('#');
+            if c.len() != 6 {
+                return None;
+            }
+            let v = u32::from_str_radix(c, 16).ok()?;
+            Some([(v >> 16) as u8, (v >> 8) as u8, v as u8])
+        })
+        .collect::<Option<Vec<Rgb>>>()?;
+
+    colors.try_into().ok()
+}
+
+fn invalid_config(line: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid palette config: {}", line),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb555_scales_to_full_range() {
+        assert_eq!(rgb555(0x7FFF), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(rgb555(0x0000), [0x00, 0x00, 0x00]);
+        assert_eq!(rgb555(0x32BF), [0xFF, 0xAD, 0x63]);
+    }
+
+    #[test]
+    fn tetris_palette() {
+        let p = DmgPalettes::from_title(true, 0xDB, b'R');
+        assert_eq!(
+            p.bg,
+            [
+                [0xFF, 0xFF, 0xFF],
+                [0xFF, 0xFF, 0x00],
+                [0xFF, 0x00, 0x00],
+                [0, 0, 0]
+            ]
+        );
+    }
+
+    #[test]
+    fn fourth_letter_disambiguates() {
+        // POKEMON BLUE and VEGAS STAKES share the checksum.
+        let blue = DmgPalettes::from_title(true, 0x61, b'E');
+        assert_eq!(blue, DmgPalettes::from_combination(11));
+        let vegas = DmgPalettes::from_title(true, 0x61, b'A');
+        assert_eq!(vegas, DmgPalettes::from_combination(41));
+        let unknown = DmgPalettes::from_title(true, 0x61, b'Z');
+        assert_eq!(unknown, DmgPalettes::from_combination(0));
+    }
+
+    #[test]
+    fn other_licensees_get_default() {
+        let p = DmgPalettes::from_title(false, 0xDB, b'R');
+        assert_eq!(p, DmgPalettes::from(ManualPalette::RightA));
+    }
+
+    #[test]
+    fn manual_palettes() {
+        let p = DmgPalettes::from("left+b".parse::<ManualPalette>().unwrap());
+        assert_eq!(p.bg[1], [0xA5, 0xA5, 0xA5]);
+        let p = DmgPalettes::from(ManualPalette::RightB);
+        assert_eq!(p.bg[0], [0, 0, 0]);
+        assert_eq!(p.bg[3], [0xFF, 0xFF, 0xFF]);
+        assert!("up+c".parse::<ManualPalette>().is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let p = DmgPalettes::from_config(
+            "# classic green\nbg = 9BBC0F 8BAC0F 306230 0F380F\nobj1 = #FFFFFF #AAAAAA #555555 #000000\n",
+        )
+        .unwrap();
+        assert_eq!(p.bg[0], [0x9B, 0xBC, 0x0F]);
+        assert_eq!(p.obj0, p.bg);
+        assert_eq!(p.obj1[1], [0xAA; 3]);
+
+        assert!(DmgPalettes::from_config("obj0 = FFFFFF 000000").is_err());
+        assert!(DmgPalettes::from_config("fg = FFFFFF AAAAAA 555555 000000").is_err());
+    }
+}
@@ -1,26 +1,786 @@
-use crate::{cartridge::make_cartridge, cpu::CentralProcessingUnit, mmu::MemoryManagmentUnit};
+use crate::{
+    audio_recorder::AudioRecorder,
+    boot_rom::BootRom,
+    cartridge::make_cartridge,
+    cpu::CentralProcessingUnit,
+    dmg_palette::DmgPalettes,
+    hle_boot::HleBoot,
+    infrared::InfraredPeer,
+    input::{InputLayer, Macro},
+    input_output_registers::{Button, Buttons},
+    memory_device::ReadWrite,
+    mmu::MemoryManagmentUnit,
+    model::Model,
+    png_writer::crc32,
+    register::Registers,
+    save_state::{SaveState, StateReader},
+    serial_data_transfer::SerialPeer,
+    vgm_logger::VgmLogger,
+};
 use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
+/// How the console gets from power on to the cartridge entry point at 0x0100.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Boot {
+    /// Start straight from 0x0100 with the state the boot ROM of the model leaves behind.
+    Skip,
+    /// Run a boot ROM image read from this file, starting at 0x0000 from zeroed registers.
+    Rom(PathBuf),
+    /// Run the built-in boot sequence, it doesn't need any proprietary image.
+    Hle,
+}
+
+// Dots in a frame, the time `run_frame` gives up waiting for a v-blank with the LCD off.
+const FRAME_CYCLES: u32 = 70_224;
+
+const STATE_MAGIC: &[u8] = b"yobemag state 1\n";
+
+/// Options to start an `Emulator` with, all but the rom have defaults:
+/// `EmulatorBuilder::new("game.gb").model(Model::Cgb).build()`.
+#[derive(Clone, Debug)]
+pub struct EmulatorBuilder {
+    rom: PathBuf,
+    model: Model,
+    boot: Boot,
+    palettes: Option<DmgPalettes>,
+    sample_rate: Option<u32>,
+    trace: bool,
+    saving: bool,
+}
+
+impl EmulatorBuilder {
+    /// Starts from the rom in `rom`, on the default model, skipping the boot ROM.
+    pub fn new(rom: impl Into<PathBuf>) -> EmulatorBuilder {
+        EmulatorBuilder {
+            rom: rom.into(),
+            model: Model::default(),
+            boot: Boot::Skip,
+            palettes: None,
+            sample_rate: None,
+            trace: false,
+            saving: true,
+        }
+    }
+
+    pub fn model(mut self, model: Model) -> EmulatorBuilder {
+        self.model = model;
+        self
+    }
+
+    pub fn boot(mut self, boot: Boot) -> EmulatorBuilder {
+        self.boot = boot;
+        self
+    }
+
+    /// Colors of DMG shades instead of the ones the model picks.
+    pub fn palettes(mut self, palettes: DmgPalettes) -> EmulatorBuilder {
+        self.palettes = Some(palettes);
+        self
+    }
+
+    /// Samples per second of each channel given by `Emulator::drain_audio`.
+    pub fn sample_rate(mut self, sample_rate: u32) -> EmulatorBuilder {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Prints every opcode executed, off by default.
+    pub fn trace(mut self, trace: bool) -> EmulatorBuilder {
+        self.trace = trace;
+        self
+    }
+
+    /// Reads and writes battery-backed RAM in a `.sav` file next to the rom, on by default.
+    pub fn saving(mut self, saving: bool) -> EmulatorBuilder {
+        self.saving = saving;
+        self
+    }
+
+    pub fn build(self) -> Result<Emulator, std::io::Error> {
+        let filename = self.rom.to_str().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("rom path {} is not valid UTF-8.", self.rom.display()),
+            )
+        })?;
+        let mut device = make_cartridge(filename)?;
+        // Save states only fit the rom they were made with.
+        let rom_crc32 = crc32(&std::fs::read(&self.rom)?);
+        let save_path = self.rom.with_extension("sav");
+        if self.saving && save_path.exists() {
+            device.load_save_data(&std::fs::read(&save_path)?)?;
+        }
+
+        let model = self.model;
+        let registers = match device.header() {
+            Some(h) => model.post_boot_registers(
+                h.header_checksum,
+                h.gameboy_color_support.supports_cgb(),
+                if h.nintendo_licensee {
+                    h.title_checksum
+                } else {
+                    0x00
+                },
+            ),
+            None => model.post_boot_registers(0x00, false, 0x00),
+        };
+
+        // The CGB boot ROM colorizes DMG games looking up the title, a real boot ROM image
+        // does it by itself through the color palette registers.
+        let colors = match device.header() {
+            Some(h) if model.is_cgb() && !h.gameboy_color_support.supports_cgb() => {
+                DmgPalettes::from_title(
+                    h.nintendo_licensee,
+                    h.title_checksum,
+                    h.title.as_bytes().get(3).copied().unwrap_or(0x00),
+                )
+            }
+            _ => DmgPalettes::default(),
+        };
+
+        let boot_rom = match &self.boot {
+            Boot::Rom(path) => BootRom::new(std::fs::read(path)?)?,
+            Boot::Skip | Boot::Hle => BootRom::default(),
+        };
+
+        let mmu = Rc::new(RefCell::new(MemoryManagmentUnit::new(
+            device, boot_rom, model,
+        )));
+        if let Some(palettes) = self.palettes {
+            mmu.borrow_mut().set_dmg_palettes(palettes);
+        } else if !matches!(self.boot, Boot::Rom(_)) {
+            mmu.borrow_mut().set_dmg_palettes(colors);
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            mmu.borrow_mut().sound_mut().set_sample_rate(sample_rate);
+        }
+        let mut cpu = CentralProcessingUnit::new(mmu.clone());
+        cpu.set_trace(self.trace);
+        let mut hle_boot = None;
+        match self.boot {
+            Boot::Skip => {
+                mmu.borrow_mut().apply_post_boot_state()?;
+                cpu.set_registers(registers);
+            }
+            Boot::Rom(_) => cpu.set_registers(Registers::default()),
+            Boot::Hle => {
+                hle_boot = Some(HleBoot::new(&mut *mmu.borrow_mut(), registers)?);
+                cpu.set_registers(Registers::default());
+            }
+        }
+
+        Ok(Emulator {
+            mmu,
+            cpu,
+            hle_boot,
+            hle_boot_error: None,
+            rom_crc32,
+            input: InputLayer::default(),
+            save_path,
+            saving: self.saving,
+            vgm_path: None,
+            vgm_log_done: None,
+            options: self,
+        })
+    }
+}
+
+/// A console with a cartridge in, see the crate documentation.
 pub struct Emulator {
     mmu: Rc<RefCell<MemoryManagmentUnit>>,
     cpu: CentralProcessingUnit,
+
+    // While running the built-in boot sequence the CPU is kept still.
+    hle_boot: Option<HleBoot>,
+    // What cut the boot sequence short, for the caller to hear about.
+    hle_boot_error: Option<std::io::Error>,
+
+    rom_crc32: u32,
+
+    // Turns the buttons held by the host into the ones pressed each frame.
+    input: InputLayer,
+
+    // Battery-backed RAM (and clock) is stored next to the rom with `.sav` extension.
+    save_path: PathBuf,
+    saving: bool,
+
+    // Where the VGM log in progress goes.
+    vgm_path: Option<PathBuf>,
+    // A VGM log that stopped by itself, for the caller to hear about.
+    vgm_log_done: Option<Result<PathBuf, std::io::Error>>,
+
+    // What it was built with, to start over on `reset`.
+    options: EmulatorBuilder,
 }
 
 impl Emulator {
-    pub fn new(filename: &str) -> Result<Emulator, std::io::Error> {
-        let device = make_cartridge(filename)?;
-        let mmu = Rc::new(RefCell::new(MemoryManagmentUnit::new(device)));
-        let cpu = CentralProcessingUnit::new(mmu.clone());
-        Ok(Emulator { mmu, cpu })
+    /// Loads the rom in `filename` on the given `model`, starting it as described by `boot`.
+    /// Shorthand for `EmulatorBuilder` with the other options left alone.
+    pub fn new(filename: &str, model: Model, boot: Boot) -> Result<Emulator, std::io::Error> {
+        EmulatorBuilder::new(filename)
+            .model(model)
+            .boot(boot)
+            .build()
     }
 
-    pub fn step(&mut self) {
+    /// Turns the console off and on again. The options it was built with, the colors set
+    /// since, the input settings and battery-backed RAM are kept: what's connected to the
+    /// ports, serial sinks, recordings and muted channels have to be set up again.
+    pub fn reset(&mut self) -> Result<(), std::io::Error> {
+        let mut fresh = self.options.clone().build()?;
+        if let Some(data) = self.save_data() {
+            fresh.load_save_data(&data)?;
+        }
+        fresh.input = std::mem::take(&mut self.input);
+        fresh.saving = self.saving;
+        std::mem::replace(self, fresh).close()
+    }
+
+    /// Runs one instruction, or one scanline of the built-in boot sequence, and returns the
+    /// cycles it took.
+    pub fn step(&mut self) -> u32 {
+        if self.hle_boot.is_some() {
+            return self.step_hle_boot();
+        }
+
         if self.cpu.need_toggle_speed() {
             self.mmu.borrow_mut().toggle_speed();
         }
 
         let clock_cycles = self.cpu.step();
         self.mmu.borrow_mut().step(clock_cycles);
+
+        if self.mmu.borrow().vgm_logging_done() {
+            self.vgm_log_done = self.stop_vgm_logging().transpose();
+        }
+
+        clock_cycles
+    }
+
+    /// True once the CPU ran STOP: it doesn't move time forward anymore.
+    pub fn is_stopped(&self) -> bool {
+        self.hle_boot.is_none() && self.cpu.is_stopped()
+    }
+
+    // Steps until `done`, or for good if the CPU stops. Peripherals running emulators in
+    // lockstep use it to bring each one up to the same time.
+    pub(crate) fn step_until(&mut self, mut done: impl FnMut() -> bool) {
+        while !done() && !self.is_stopped() {
+            self.step();
+        }
+    }
+
+    /// Runs up to the start of the next v-blank, with the buttons the input layer gives for
+    /// this frame.
+    pub fn run_frame(&mut self) {
+        self.run_frame_until(|_, _| false);
+    }
+
+    /// Runs a frame like `run_frame`, calling `stop` after each instruction with the cycles
+    /// it took. A CPU in HALT goes on to the end of the frame, a STOP ends it right away.
+    /// Returns true if `stop` cut the frame short.
+    pub fn run_frame_until(&mut self, mut stop: impl FnMut(&Emulator, u32) -> bool) -> bool {
+        let buttons = self.input.next_frame();
+        self.mmu.borrow_mut().set_buttons(buttons);
+
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES {
+            let step = self.step();
+            if stop(self, step) {
+                return true;
+            }
+            if self.is_stopped() || self.mmu.borrow().v_blank() {
+                break;
+            }
+            cycles += step;
+        }
+        false
+    }
+
+    /// The picture on the screen, `SCREEN_WIDTH` by `SCREEN_HEIGHT` pixels of 3 bytes, red,
+    /// green and blue, row after row from the top left.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.mmu.borrow().framebuffer().to_vec()
+    }
+
+    /// Takes the sound produced since the last call, left and right samples interleaved.
+    /// Only the last second or so is kept when it isn't drained.
+    pub fn drain_audio(&mut self) -> Vec<f32> {
+        self.mmu.borrow_mut().sound_mut().drain_samples()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.mmu.borrow_mut().sound_mut().sample_rate()
+    }
+
+    /// Makes a bit more (positive) or fewer samples per frame, at most 0.5% either way, for
+    /// a frontend to keep its audio buffer from running dry or overflowing.
+    pub fn set_audio_rate_adjustment(&mut self, adjustment: f64) {
+        self.mmu
+            .borrow_mut()
+            .sound_mut()
+            .set_rate_adjustment(adjustment);
+    }
+
+    // Should a write fail, the sequence is cut short and the game starts, the error is kept
+    // for `take_hle_boot_error`.
+    fn step_hle_boot(&mut self) -> u32 {
+        let Some(boot) = &mut self.hle_boot else {
+            return 0;
+        };
+        let step = boot.step(&mut *self.mmu.borrow_mut());
+        let cycles = *step.as_ref().unwrap_or(&0);
+        self.mmu.borrow_mut().step(cycles);
+
+        if boot.is_done() || step.is_err() {
+            if let Some(boot) = self.hle_boot.take() {
+                let post_boot = self.mmu.borrow_mut().apply_post_boot_state();
+                self.hle_boot_error = step.and(post_boot).err();
+                self.cpu.set_registers(boot.into_registers());
+            }
+        }
+
+        cycles
+    }
+
+    /// The error that cut the built-in boot sequence short, if any. Given once.
+    pub fn take_hle_boot_error(&mut self) -> Option<std::io::Error> {
+        self.hle_boot_error.take()
+    }
+
+    /// The buttons pressed, as the game sees them.
+    pub fn buttons(&self) -> Buttons {
+        self.mmu.borrow().buttons()
+    }
+
+    /// Holds down `buttons` and releases the others, until the next call. Meant to be called
+    /// between frames, the next `run_frame` passes them through turbo, macros and the filter.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.input.set_held(buttons);
+        self.mmu.borrow_mut().set_buttons(buttons);
+    }
+
+    /// Makes `button` alternate every `frames` frames while held, or stops with `None`.
+    pub fn set_turbo(&mut self, button: Button, frames: Option<u32>) {
+        self.input.set_turbo(button, frames);
+    }
+
+    /// Keeps Left+Right and Up+Down from reaching the game together.
+    pub fn set_filter_opposing(&mut self, filter: bool) {
+        self.input.set_filter_opposing(filter);
+    }
+
+    /// Names an input macro, to play it later with `play_macro`.
+    pub fn add_macro(&mut self, name: &str, steps: Macro) {
+        self.input.add_macro(name, steps);
+    }
+
+    /// Plays the macro called `name` from the next frame on, on top of the buttons held.
+    pub fn play_macro(&mut self, name: &str) -> Result<(), std::io::Error> {
+        self.input.play_macro(name)
+    }
+
+    /// Overrides the colors of DMG shades, like the palettes picked holding a
+    /// button combination at boot on CGB or user-defined ones.
+    pub fn set_dmg_palettes(&mut self, colors: DmgPalettes) {
+        self.options.palettes = Some(colors);
+        self.mmu.borrow_mut().set_dmg_palettes(colors);
+    }
+
+    /// Copies the bytes sent over the serial port to `sink`, test ROMs print their results there.
+    pub fn set_serial_sink(&mut self, sink: Box<dyn std::io::Write>) {
+        self.mmu.borrow_mut().set_serial_sink(sink);
+    }
+
+    /// Plugs a link cable into the serial port.
+    pub fn connect_serial(&mut self, peer: Box<dyn SerialPeer>) {
+        self.mmu.borrow_mut().connect_serial(peer);
+    }
+
+    /// Puts something in front of the infrared port.
+    pub fn connect_infrared(&mut self, peer: Box<dyn InfraredPeer>) {
+        self.mmu.borrow_mut().connect_infrared(peer);
+    }
+
+    /// Puts something in front of the infrared port of the cartridge, for the ones with one.
+    pub fn connect_cartridge_infrared(
+        &mut self,
+        peer: Box<dyn InfraredPeer>,
+    ) -> Result<(), std::io::Error> {
+        self.mmu.borrow_mut().connect_cartridge_infrared(peer)
+    }
+
+    /// Records the sound output to the WAV file at `path`, along with one mono file per
+    /// channel before panning when `stems` is set. A recording in progress is finished first.
+    pub fn start_audio_recording(
+        &mut self,
+        path: &Path,
+        stems: bool,
+    ) -> Result<(), std::io::Error> {
+        self.stop_audio_recording()?;
+        let mut mmu = self.mmu.borrow_mut();
+        let recorder = AudioRecorder::create(path, mmu.sound_mut().sample_rate(), stems)?;
+        mmu.sound_mut().start_recording(recorder);
+        Ok(())
+    }
+
+    /// Completes the WAV files being recorded, if any.
+    pub fn stop_audio_recording(&mut self) -> Result<(), std::io::Error> {
+        match self.mmu.borrow_mut().sound_mut().stop_recording() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Logs writes to the sound registers to the VGM file at `path`, the file is complete
+    /// up to the last second or so all along. Frames are counted from now, 70224 cycles each
+    /// whether the LCD is on or not: the music loops back to `loop_frame` and logging stops by
+    /// itself at `stop_frame`. A log in progress is finished first.
+    pub fn start_vgm_logging(
+        &mut self,
+        path: &Path,
+        loop_frame: Option<u64>,
+        stop_frame: Option<u64>,
+    ) -> Result<(), std::io::Error> {
+        self.stop_vgm_logging()?;
+        let vgm = VgmLogger::create(path, loop_frame, stop_frame)?;
+        self.mmu.borrow_mut().start_vgm_logging(vgm);
+        self.vgm_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Finishes the VGM file being logged, if any, and returns its path.
+    pub fn stop_vgm_logging(&mut self) -> Result<Option<PathBuf>, std::io::Error> {
+        let vgm = self.mmu.borrow_mut().stop_vgm_logging();
+        match (vgm, self.vgm_path.take()) {
+            (Some(vgm), Some(path)) => {
+                vgm.finish()?;
+                Ok(Some(path))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The path of the VGM log written when it reached its stop frame, or the error writing
+    /// it. Given once.
+    pub fn take_vgm_log_done(&mut self) -> Option<Result<PathBuf, std::io::Error>> {
+        self.vgm_log_done.take()
+    }
+
+    /// Leaves sound channel `channel` (1-4) out of the output, or puts it back.
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) -> Result<(), std::io::Error> {
+        if !(1..=4).contains(&channel) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("there is no sound channel {}, they are 1-4.", channel),
+            ));
+        }
+        self.mmu
+            .borrow_mut()
+            .sound_mut()
+            .set_channel_muted(channel - 1, muted);
+        Ok(())
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.cpu.program_counter()
+    }
+
+    pub(crate) fn registers(&self) -> &Registers {
+        self.cpu.registers()
+    }
+
+    /// The byte at `address` as the CPU would read it, 0xFF where nothing answers.
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.mmu
+            .borrow()
+            .read_byte(usize::from(address))
+            .unwrap_or(0xFF)
+    }
+
+    /// CRC-32 of the registers, the screen and work RAM, two runs going the same way have
+    /// the same checksums.
+    pub fn state_checksum(&self) -> u32 {
+        let registers = self.cpu.registers();
+        let mut bytes = vec![];
+        for word in [
+            registers.af(),
+            registers.bc(),
+            registers.de(),
+            registers.hl(),
+            registers.stack_pointer,
+            registers.program_counter,
+        ] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let mmu = self.mmu.borrow();
+        bytes.extend_from_slice(mmu.framebuffer());
+        for address in 0xC000..0xE000 {
+            bytes.push(mmu.read_byte(address).unwrap_or(0xFF));
+        }
+        crc32(&bytes)
+    }
+
+    /// Everything the console needs to carry on from now, for `load_state` of an emulator
+    /// started from the same rom on the same model. What's connected to the ports, the input
+    /// settings and the sound not drained yet aren't part of it.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = STATE_MAGIC.to_vec();
+        out.push(self.options.model as u8);
+        out.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        match &self.hle_boot {
+            Some(boot) => {
+                out.push(1);
+                boot.save_state(&mut out);
+            }
+            None => out.push(0),
+        }
+        self.cpu.save_state(&mut out);
+        self.mmu.borrow().save_state(&mut out);
+        out
+    }
+
+    /// Carries on from a state made by `save_state`. On error the emulator is left as it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), std::io::Error> {
+        let current = self.save_state();
+        self.read_state(state).inspect_err(|_| {
+            // Nothing can go wrong reading back what was just written.
+            let _ = self.read_state(&current);
+        })
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        let invalid = |message: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+        };
+        let mut state = StateReader::new(data);
+        if state.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(invalid("not a yobemag save state."));
+        }
+        if state.u8()? != self.options.model as u8 {
+            return Err(invalid("the state was saved on another model."));
+        }
+        if state.u32()? != self.rom_crc32 {
+            return Err(invalid("the state was saved with another rom."));
+        }
+
+        self.hle_boot = match state.bool()? {
+            true => {
+                let mut boot = self.hle_boot.take().unwrap_or_default();
+                boot.load_state(&mut state)?;
+                Some(boot)
+            }
+            false => None,
+        };
+        self.cpu.load_state(&mut state)?;
+        self.mmu.borrow_mut().load_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(invalid("the state goes on past its end."));
+        }
+        Ok(())
+    }
+
+    /// Title in the cartridge header.
+    pub fn title(&self) -> Option<String> {
+        self.mmu.borrow().cartridge_title()
+    }
+
+    /// Battery-backed RAM (and clock), if the cartridge has any.
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mmu.borrow().save_data()
+    }
+
+    /// Replaces battery-backed RAM, as read from a `.sav` file.
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        self.mmu.borrow_mut().load_save_data(data)
+    }
+
+    /// Leaves the `.sav` file alone from now on, like when replaying a movie.
+    pub fn disable_saving(&mut self) {
+        self.options.saving = false;
+        self.saving = false;
+    }
+
+    /// Finishes the audio and VGM recordings and writes battery-backed RAM to the `.sav`
+    /// file. Dropping the emulator does it too, but the errors are lost.
+    pub fn close(mut self) -> Result<(), std::io::Error> {
+        self.finish()
+    }
+
+    // Done once: the `.sav` file is left alone afterwards.
+    fn finish(&mut self) -> Result<(), std::io::Error> {
+        let audio = self.stop_audio_recording();
+        let vgm = self.stop_vgm_logging().map(|_| ());
+        let save = self.save();
+        self.saving = false;
+        audio.and(vgm).and(save)
+    }
+
+    /// Writes battery-backed RAM to the `.sav` file, if the cartridge has any.
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        if !self.saving {
+            return Ok(());
+        }
+        match self.mmu.borrow().save_data() {
+            Some(data) => std::fs::write(&self.save_path, data),
+            None => Ok(()),
+        }
+    }
+}
+
+// Without `close`, whatever goes wrong finishing up is lost.
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmg_palette::ManualPalette;
+    use crate::test_rom::{emulator, TestRom};
+
+    #[test]
+    fn frames_picture_and_sound() {
+        let rom = TestRom::tetris(&[]);
+        let mut emu = EmulatorBuilder::new(rom.path())
+            .model(Model::Dmg)
+            .sample_rate(32_000)
+            .build()
+            .unwrap();
+        assert_eq!(emu.sample_rate(), 32_000);
+        assert_eq!(emu.framebuffer().len(), 160 * 144 * 3);
+
+        emu.run_frame();
+        assert!(emu.mmu.borrow().v_blank());
+        // Two frames are about 1/30 s, left and right.
+        emu.run_frame();
+        let samples = emu.drain_audio();
+        assert!((2000..2300).contains(&samples.len()), "{}", samples.len());
+        assert_eq!(samples.len() % 2, 0);
+        assert!(emu.drain_audio().is_empty());
+    }
+
+    #[test]
+    fn channels_are_1_to_4() {
+        let mut emu = emulator(&[]);
+        assert!(emu.set_channel_muted(1, true).is_ok());
+        assert!(emu.set_channel_muted(4, true).is_ok());
+        assert!(emu.set_channel_muted(0, true).is_err());
+        assert!(emu.set_channel_muted(5, false).is_err());
+        emu.close().unwrap();
+    }
+
+    #[test]
+    fn halt_runs_to_the_end_of_the_frame() {
+        // HALT, JR -3 and nothing to wake it up, then STOP.
+        for (code, stopped) in [(vec![0x76, 0x18, 0xFD], false), (vec![0x10, 0x00], true)] {
+            let mut emu = emulator(&code);
+
+            let mut cycles = 0;
+            emu.run_frame_until(|_, step| {
+                cycles += step;
+                false
+            });
+            assert_eq!(emu.is_stopped(), stopped);
+            assert_eq!(cycles > 1000, !stopped, "{}", cycles);
+        }
+    }
+
+    #[test]
+    fn hle_boot_hands_over() {
+        let rom = TestRom::tetris(&[]);
+        let mut emu = EmulatorBuilder::new(rom.path())
+            .boot(Boot::Hle)
+            .build()
+            .unwrap();
+        while emu.hle_boot.is_some() {
+            emu.step();
+        }
+        assert_eq!(emu.cpu.program_counter(), 0x0100);
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let rom = TestRom::tetris(&[]);
+        let mut emu = EmulatorBuilder::new(rom.path()).build().unwrap();
+        emu.run_frame();
+        let state = emu.save_state();
+        let frames = |emu: &mut Emulator| {
+            (0..3)
+                .map(|_| {
+                    emu.run_frame();
+                    emu.state_checksum()
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = frames(&mut emu);
+
+        let mut other = EmulatorBuilder::new(rom.path()).build().unwrap();
+        other.load_state(&state).unwrap();
+        assert_eq!(frames(&mut other), first);
+        emu.load_state(&state).unwrap();
+        assert_eq!(frames(&mut emu), first);
+
+        // A bad state leaves the emulator alone.
+        let checksum = emu.state_checksum();
+        assert!(emu.load_state(&state[..state.len() - 1]).is_err());
+        assert!(emu.load_state(b"something else").is_err());
+        let mut longer = state.clone();
+        longer.push(0x00);
+        assert!(emu.load_state(&longer).is_err());
+        assert_eq!(emu.state_checksum(), checksum);
+        let mut dmg = EmulatorBuilder::new(rom.path())
+            .model(Model::Dmg)
+            .build()
+            .unwrap();
+        assert!(dmg.load_state(&state).is_err());
+        // Another version of the game, with the same title.
+        assert!(emulator(&[0x00]).load_state(&state).is_err());
+    }
+
+    #[test]
+    fn state_during_hle_boot() {
+        let rom = TestRom::tetris(&[]);
+        let mut emu = EmulatorBuilder::new(rom.path())
+            .boot(Boot::Hle)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            emu.step();
+        }
+        let state = emu.save_state();
+        let mut other = emulator(&[]);
+        other.load_state(&state).unwrap();
+        while emu.hle_boot.is_some() {
+            emu.step();
+            other.step();
+        }
+        assert!(other.hle_boot.is_none());
+        assert_eq!(other.state_checksum(), emu.state_checksum());
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let rom = TestRom::tetris(&[]);
+        let mut emu = EmulatorBuilder::new(rom.path())
+            .palettes(ManualPalette::Up.into())
+            .build()
+            .unwrap();
+        let start = emu.state_checksum();
+        emu.set_filter_opposing(true);
+        emu.set_buttons("left,right,a".parse().unwrap());
+        emu.run_frame();
+        assert_ne!(emu.state_checksum(), start);
+
+        emu.reset().unwrap();
+        assert_eq!(emu.cpu.program_counter(), 0x0100);
+        assert_eq!(emu.options.palettes, Some(ManualPalette::Up.into()));
+        // The input layer is kept, with the buttons held.
+        emu.run_frame();
+        assert_eq!(emu.buttons(), Buttons(0x10));
     }
 }
@@ -0,0 +1,417 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{emulator::Emulator, serial_data_transfer::SerialPeer};
+
+// First byte of a ping packet, the three after it are the status of the port.
+const PING_HEADER: u8 = 0xFE;
+// What players answer to the header and the byte after it to show they're there.
+const ACK: u8 = 0x88;
+// Sent by player 1 instead of the acks to start the transmission phase.
+const START: u8 = 0xAA;
+// Sent 4 times before the first transmission packet.
+const TRANSMISSION_HEADER: u8 = 0xCC;
+// A packet of player 1 filled with this goes back to the ping phase.
+const RESTART: u8 = 0xFF;
+
+// Cycles per bit: 8192 Hz, slowed down by the rate player 1 asks for.
+const BASE_BIT_CYCLES: u32 = 512;
+const RATE_BIT_CYCLES: u32 = 6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Phase {
+    // Byte of the ping packet, and whether player 1 asked to start.
+    Ping(usize, bool),
+    Starting(usize),
+    // Byte of the transmission packet.
+    Transmission(usize),
+}
+
+#[derive(Default)]
+struct Port {
+    // Cycles the emulator on this port ran.
+    now: u64,
+    // Bit of the adapter waiting for the game to clock it in.
+    pending: Option<bool>,
+    received: u8,
+}
+
+struct Hub {
+    ports: [Port; 4],
+    // Time of the next bit, for all ports at once as the adapter drives the clock.
+    next_bit: u64,
+    bit: u32,
+    outgoing: [u8; 4],
+
+    phase: Phase,
+    connected: [bool; 4],
+    rate: u8,
+    size: usize,
+    // Packets of the players being received, and the ones of the last round being relayed.
+    packets: [Vec<u8>; 4],
+    relayed: Vec<u8>,
+}
+
+impl Hub {
+    fn bit_cycles(&self) -> u64 {
+        u64::from(BASE_BIT_CYCLES + RATE_BIT_CYCLES * u32::from(self.rate))
+    }
+
+    // Upper nibble with the players there, lower bits with the one on the port.
+    fn status(&self, port: usize) -> u8 {
+        let connected = self
+            .connected
+            .iter()
+            .enumerate()
+            .fold(0, |status, (i, &c)| status | ((c as u8) << (4 + i)));
+        connected | (port as u8 + 1)
+    }
+
+    fn outgoing_byte(&self, port: usize) -> u8 {
+        match self.phase {
+            Phase::Ping(0, _) => PING_HEADER,
+            Phase::Ping(..) => self.status(port),
+            Phase::Starting(_) => TRANSMISSION_HEADER,
+            Phase::Transmission(i) => self.relayed.get(i).copied().unwrap_or(0x00),
+        }
+    }
+
+    // All ports reached the time of the next bit.
+    fn tick(&mut self) {
+        for port in &mut self.ports {
+            // A game not waiting for the clock misses it, the line reads high.
+            if port.pending.take().is_some() {
+                port.received = (port.received << 1) | 1;
+            }
+        }
+
+        if self.bit == 8 {
+            self.bit = 0;
+            let received = self.ports.each_ref().map(|p| p.received);
+            self.receive(received);
+        }
+        if self.bit == 0 {
+            self.outgoing = [0, 1, 2, 3].map(|i| self.outgoing_byte(i));
+        }
+        for (port, byte) in self.ports.iter_mut().zip(self.outgoing) {
+            port.pending = Some(byte & (0x80 >> self.bit) != 0);
+        }
+        self.bit += 1;
+        self.next_bit += self.bit_cycles();
+    }
+
+    fn receive(&mut self, received: [u8; 4]) {
+        self.phase = match self.phase {
+            Phase::Ping(i, start) => {
+                let start = start || (i < 2 && received[0] == START);
+                match i {
+                    0 => {
+                        self.connected =
+                            [0, 1, 2, 3].map(|p| received[p] == ACK || (p == 0 && start));
+                    }
+                    2 if !start => self.rate = received[0],
+                    3 if !start => self.size = usize::from(received[0]).max(1),
+                    _ => (),
+                }
+                match (i, start) {
+                    (3, true) => Phase::Starting(0),
+                    (3, false) => Phase::Ping(0, false),
+                    _ => Phase::Ping(i + 1, start),
+                }
+            }
+            Phase::Starting(3) => {
+                self.packets = Default::default();
+                self.relayed = vec![0x00; 4 * self.size];
+                Phase::Transmission(0)
+            }
+            Phase::Starting(i) => Phase::Starting(i + 1),
+            Phase::Transmission(i) => {
+                // Each player sends its packet in the first bytes of the round.
+                if i < self.size {
+                    for (packet, byte) in self.packets.iter_mut().zip(received) {
+                        packet.push(byte);
+                    }
+                }
+                if i + 1 < 4 * self.size {
+                    Phase::Transmission(i + 1)
+                } else if self.packets[0].iter().all(|&b| b == RESTART) {
+                    Phase::Ping(0, false)
+                } else {
+                    self.relayed = self.packets.concat();
+                    self.packets = Default::default();
+                    Phase::Transmission(0)
+                }
+            }
+        };
+    }
+}
+
+/// The DMG-07, relaying data between four emulators in the same process. It drives the
+/// clock of every port: players wait for it with the external clock.
+///
+/// In the ping phase it sends each port `FE` and its status three times, players answer
+/// `88 88` then the rate and packet size player 1 wants. Player 1 answering `AA` instead
+/// starts the transmission phase after four `CC`: every round each player sends its packet,
+/// then as many bytes as the three others, while getting the four packets of the last round.
+pub struct FourPlayerAdapter {
+    hub: Rc<RefCell<Hub>>,
+}
+
+/// One of the four plugs of a `FourPlayerAdapter`.
+pub struct AdapterPort {
+    hub: Rc<RefCell<Hub>>,
+    index: usize,
+}
+
+impl SerialPeer for AdapterPort {
+    // Players never drive the clock.
+    fn exchange_bit(&mut self, _bit: bool) -> bool {
+        true
+    }
+
+    fn clocked_bit(&mut self, bit: bool) -> Option<bool> {
+        let mut hub = self.hub.borrow_mut();
+        let port = &mut hub.ports[self.index];
+        let pending = port.pending.take()?;
+        port.received = (port.received << 1) | bit as u8;
+        Some(pending)
+    }
+
+    fn step(&mut self, cycles: u32) {
+        self.hub.borrow_mut().ports[self.index].now += u64::from(cycles);
+    }
+}
+
+impl Default for FourPlayerAdapter {
+    fn default() -> Self {
+        FourPlayerAdapter::new()
+    }
+}
+
+impl FourPlayerAdapter {
+    pub fn new() -> FourPlayerAdapter {
+        FourPlayerAdapter {
+            hub: Rc::new(RefCell::new(Hub {
+                ports: Default::default(),
+                next_bit: 0,
+                bit: 0,
+                outgoing: [0; 4],
+                phase: Phase::Ping(0, false),
+                connected: [false; 4],
+                rate: 0,
+                size: 1,
+                packets: Default::default(),
+                relayed: vec![],
+            })),
+        }
+    }
+
+    /// The plug for player `index`, 0-3, to connect to the serial port of an emulator.
+    pub fn port(&self, index: usize) -> Result<AdapterPort, std::io::Error> {
+        if index >= 4 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("the adapter has four ports, not {}", index + 1),
+            ));
+        }
+        Ok(AdapterPort {
+            hub: self.hub.clone(),
+            index,
+        })
+    }
+
+    fn behind(&self, index: usize) -> bool {
+        let hub = self.hub.borrow();
+        hub.ports[index].now < hub.next_bit
+    }
+
+    fn tick(&self) {
+        self.hub.borrow_mut().tick();
+    }
+
+    /// Runs the four players, each plugged to the port of its index, for `bits` bits.
+    pub fn run(&self, players: &mut [Emulator; 4], bits: u32) {
+        for _ in 0..bits {
+            for (index, emu) in players.iter_mut().enumerate() {
+                emu.step_until(|| !self.behind(index));
+            }
+            self.tick();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupt::InterruptFlag;
+    use crate::memory_device::ReadWrite;
+    use crate::serial_data_transfer::SerialDataTransfer;
+    use crate::test_rom::emulator;
+
+    // A game sending `script` one byte after the other with the external clock, and keeping
+    // the bytes received.
+    struct Player {
+        serial: SerialDataTransfer,
+        script: Vec<u8>,
+        received: Vec<u8>,
+    }
+
+    impl Player {
+        fn new(adapter: &FourPlayerAdapter, index: usize, script: Vec<u8>) -> Player {
+            let mut serial =
+                SerialDataTransfer::new(Rc::new(RefCell::new(InterruptFlag::default())), false);
+            serial.connect(Box::new(adapter.port(index).unwrap()));
+            let mut player = Player {
+                serial,
+                script,
+                received: vec![],
+            };
+            player.next_byte();
+            player
+        }
+
+        fn next_byte(&mut self) {
+            if self.script.is_empty() {
+                return;
+            }
+            let byte = self.script.remove(0);
+            self.serial.write_byte(0xFF01, byte).unwrap();
+            self.serial.write_byte(0xFF02, 0x80).unwrap();
+        }
+
+        fn step(&mut self) {
+            let busy = self.serial.read_byte(0xFF02).unwrap() & 0x80 != 0;
+            self.serial.step(4);
+            if busy && self.serial.read_byte(0xFF02).unwrap() & 0x80 == 0 {
+                self.received.push(self.serial.read_byte(0xFF01).unwrap());
+                self.next_byte();
+            }
+        }
+    }
+
+    // Runs `bytes` bytes, and up to the bit after so the players take in the last one.
+    fn run(adapter: &FourPlayerAdapter, players: &mut [Player], bytes: u32) {
+        for bit in 0..=bytes * 8 {
+            for (index, player) in players.iter_mut().enumerate() {
+                while adapter.behind(index) {
+                    player.step();
+                }
+            }
+            if bit < bytes * 8 {
+                adapter.tick();
+            }
+        }
+    }
+
+    #[test]
+    fn ping_then_relay() {
+        let adapter = FourPlayerAdapter::new();
+        let mut players = (0..4)
+            .map(|i| {
+                let mut script = match i {
+                    // Rate 0, 2 bytes packets, then start.
+                    0 => vec![ACK, ACK, 0x00, 0x02, START, START, START, START],
+                    _ => vec![ACK, ACK, 0x00, 0x00, ACK, ACK, 0x00, 0x00],
+                };
+                script.extend_from_slice(&[0x00; 4]);
+                for round in 0..2 {
+                    script.extend_from_slice(&[0x10 * (i + 1) + round, 0x10 * (i + 1) + 8]);
+                    script.extend_from_slice(&[0x00; 6]);
+                }
+                Player::new(&adapter, i as usize, script)
+            })
+            .collect::<Vec<_>>();
+
+        run(&adapter, &mut players, 8 + 4 + 16);
+
+        let received = &players[1].received;
+        assert_eq!(
+            received[..12],
+            [0xFE, 0xF2, 0xF2, 0xF2, 0xFE, 0xF2, 0xF2, 0xF2, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+        assert_eq!(received[12..20], [0x00; 8]);
+        assert_eq!(
+            received[20..28],
+            [0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x40, 0x48]
+        );
+        assert_eq!(players[3].received[20..28], received[20..28]);
+    }
+
+    #[test]
+    fn missing_players_are_not_connected() {
+        let adapter = FourPlayerAdapter::new();
+        let mut players = vec![Player::new(&adapter, 0, vec![ACK; 8])];
+        players.push(Player::new(&adapter, 1, vec![ACK; 8]));
+        // Nobody listens on the other ports, they read 0xFF.
+        players.push(Player::new(&adapter, 2, vec![]));
+        players.push(Player::new(&adapter, 3, vec![]));
+
+        run(&adapter, &mut players, 8);
+        assert_eq!(players[0].received[1..4], [0x31, 0x31, 0x31]);
+        assert_eq!(players[1].received[5..8], [0x32, 0x32, 0x32]);
+    }
+
+    // A game sending ACK forever with the external clock, and storing the bytes received
+    // from 0xC000 on.
+    const ACKING: [u8; 22] = [
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0x3E, ACK, // LD A, ACK
+        0xE0, 0x01, // LDH (SB), A
+        0x3E, 0x80, // LD A, 0x80
+        0xE0, 0x02, // LDH (SC), A
+        0xF0, 0x02, // LDH A, (SC)
+        0xE6, 0x80, // AND 0x80
+        0x20, 0xFA, // JR NZ, -6
+        0xF0, 0x01, // LDH A, (SB)
+        0x22, // LD (HL+), A
+        0x18, 0xED, // JR -19
+    ];
+
+    fn plug(adapter: &FourPlayerAdapter, players: &mut [Emulator; 4]) {
+        for (index, player) in players.iter_mut().enumerate() {
+            player.connect_serial(Box::new(adapter.port(index).unwrap()));
+        }
+    }
+
+    fn received(player: &Emulator) -> Vec<u8> {
+        (0xC000..0xC008)
+            .map(|address| player.read_memory(address))
+            .collect()
+    }
+
+    #[test]
+    fn runs_emulators() {
+        let adapter = FourPlayerAdapter::new();
+        let mut players = [0, 1, 2, 3].map(|_| emulator(&ACKING));
+        plug(&adapter, &mut players);
+        assert!(adapter.port(4).is_err());
+
+        adapter.run(&mut players, 8 * 8 + 1);
+        for (index, player) in players.iter().enumerate() {
+            let status = 0xF0 | (index as u8 + 1);
+            assert_eq!(
+                received(player),
+                [0xFE, status, status, status, 0xFE, status, status, status]
+            );
+        }
+    }
+
+    #[test]
+    fn stopped_player_is_left_behind() {
+        let adapter = FourPlayerAdapter::new();
+        let mut players = [0, 1, 2, 3].map(|i| match i {
+            // STOP
+            3 => emulator(&[0x10, 0x00]),
+            _ => emulator(&ACKING),
+        });
+        plug(&adapter, &mut players);
+
+        adapter.run(&mut players, 8 * 8 + 1);
+        assert!(players[3].is_stopped());
+        let status = 0x70 | 2;
+        assert_eq!(
+            received(&players[1]),
+            [0xFE, status, status, status, 0xFE, status, status, status]
+        );
+    }
+}
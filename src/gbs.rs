@@ -0,0 +1,375 @@
+use crate::{
+    audio_recorder::AudioRecorder,
+    boot_rom::BootRom,
+    cartridge::Cartridge,
+    cpu::CentralProcessingUnit,
+    memory_device::ReadWrite,
+    mmu::MemoryManagmentUnit,
+    model::Model,
+    register::Registers,
+    save_state::{SaveState, StateReader},
+};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+const HEADER_SIZE: usize = 0x70;
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+// Cycles of a whole frame, PLAY is called at every v-blank unless the timer is used.
+const FRAME_CYCLES: u32 = 70_224;
+// Routines are called as from here, the CPU reaching it means they returned.
+// Nothing can run from the unusable area next to OAM.
+const RETURN_ADDRESS: u16 = 0xFEB0;
+
+fn invalid_gbs(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Header of a GBS file, the sound driver of a game ripped with its music data.
+pub struct GbsHeader {
+    pub song_count: u8,
+    // 1-based.
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    // Bit 2 set: PLAY is called by the timer interrupt instead of v-blank.
+    // Bit 7 set: the timer runs at CGB double speed.
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|c| *c == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+impl GbsHeader {
+    pub fn new(data: &[u8]) -> Result<GbsHeader, std::io::Error> {
+        if data.len() < HEADER_SIZE || &data[0..3] != b"GBS" {
+            return Err(invalid_gbs("not a GBS file."));
+        }
+        if data[3] != 1 {
+            return Err(invalid_gbs("unsupported GBS version."));
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let header = GbsHeader {
+            song_count: data[0x04],
+            first_song: data[0x05],
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+            title: text(&data[0x10..0x30]),
+            author: text(&data[0x30..0x50]),
+            copyright: text(&data[0x50..0x70]),
+        };
+
+        if !(0x0400..=0x7FFF).contains(&header.load_address) {
+            return Err(invalid_gbs("GBS load address out of ROM."));
+        }
+        if header.song_count == 0 {
+            return Err(invalid_gbs("GBS without songs."));
+        }
+        Ok(header)
+    }
+
+    /// Cycles between two calls of PLAY, worked out from the header instead of waiting for
+    /// the interrupt: the v-blank rate, or the timer overflow rate of TMA and TAC. With the
+    /// double speed bit the timer period is only halved, the CPU keeps running at normal
+    /// speed, so drivers timing themselves with cycle counted loops play slower.
+    pub fn play_period(&self) -> u32 {
+        if self.timer_control & 0x04 == 0 {
+            return FRAME_CYCLES;
+        }
+
+        // Timer clock selected by TAC, 4096, 262144, 65536 or 16384 Hz.
+        let divider = [1024, 16, 64, 256][(self.timer_control & 0x03) as usize];
+        let period = divider * (256 - u32::from(self.timer_modulo));
+        if self.timer_control & 0x80 != 0 {
+            period / 2
+        } else {
+            period
+        }
+    }
+}
+
+/// The memory map of a GBS: its code and data loaded at the load address in a ROM banked
+/// like MBC1 through writes to 0x2000-0x3FFF, and 8KB of RAM at 0xA000-0xBFFF.
+/// RST vectors jump to the load address plus the vector, as GBS drivers expect.
+pub struct GbsCartridge {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    bank: usize,
+}
+
+impl GbsCartridge {
+    pub fn new(data: &[u8], header: &GbsHeader) -> GbsCartridge {
+        let load_address = header.load_address as usize;
+        let mut rom = vec![0xFF; load_address];
+        rom.extend_from_slice(&data[HEADER_SIZE..]);
+        rom.resize(rom.len().div_ceil(0x4000).max(2) * 0x4000, 0xFF);
+
+        // JP load_address + vector
+        for vector in (0x00..0x40).step_by(8) {
+            let [low, high] = (header.load_address + vector as u16).to_le_bytes();
+            rom[vector..vector + 3].copy_from_slice(&[0xC3, low, high]);
+        }
+
+        GbsCartridge {
+            rom,
+            ram: vec![0; 0x2000],
+            bank: 1,
+        }
+    }
+}
+
+impl ReadWrite for GbsCartridge {
+    fn contains(&self, address: usize) -> bool {
+        (0x0000..=0x7FFF).contains(&address) || (0xA000..=0xBFFF).contains(&address)
+    }
+
+    fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
+        match address {
+            0x0000..=0x3FFF => Ok(self.rom[address]),
+            0x4000..=0x7FFF => Ok(self
+                .rom
+                .get(self.bank * 0x4000 + address - 0x4000)
+                .copied()
+                .unwrap_or(0xFF)),
+            _ => Ok(self.ram[address - 0xA000]),
+        }
+    }
+
+    fn read_word(&self, address: usize) -> Result<u16, std::io::Error> {
+        let low = self.read_byte(address)?;
+        let high = self.read_byte(address + 1)?;
+        Ok(u16::from(low) | (u16::from(high) << 8))
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
+        match address {
+            0x2000..=0x3FFF => self.bank = usize::from(value).max(1),
+            0xA000..=0xBFFF => self.ram[address - 0xA000] = value,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn write_word(&mut self, address: usize, value: u16) -> Result<(), std::io::Error> {
+        let [low, high] = value.to_le_bytes();
+        self.write_byte(address, low)?;
+        self.write_byte(address + 1, high)
+    }
+}
+
+impl SaveState for GbsCartridge {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ram);
+        out.push(self.bank as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        state.fill(&mut self.ram)?;
+        self.bank = usize::from(state.u8()?);
+        Ok(())
+    }
+}
+
+impl Cartridge for GbsCartridge {}
+
+/// Plays a GBS file without any screen: INIT is called with the song index, then PLAY
+/// as often as the v-blank or timer interrupt named by the header would call it.
+///
+/// It's an approximation of a real player: PLAY is called on a fixed period, see
+/// `GbsHeader::play_period`, not by an interrupt, so a driver reprogramming the timer
+/// while playing keeps the rate of the header.
+pub struct GbsPlayer {
+    header: GbsHeader,
+    mmu: Rc<RefCell<MemoryManagmentUnit>>,
+    cpu: CentralProcessingUnit,
+}
+
+impl GbsPlayer {
+    pub fn new(data: &[u8]) -> Result<GbsPlayer, std::io::Error> {
+        let header = GbsHeader::new(data)?;
+        let cartridge = GbsCartridge::new(data, &header);
+        let mmu = Rc::new(RefCell::new(MemoryManagmentUnit::new(
+            Box::new(cartridge),
+            BootRom::default(),
+            Model::Dmg,
+        )));
+        let cpu = CentralProcessingUnit::new(mmu.clone());
+
+        Ok(GbsPlayer { header, mmu, cpu })
+    }
+
+    pub fn load(path: &Path) -> Result<GbsPlayer, std::io::Error> {
+        GbsPlayer::new(&std::fs::read(path)?)
+    }
+
+    pub fn header(&self) -> &GbsHeader {
+        &self.header
+    }
+
+    /// Plays `song` (0-based) for `seconds`, the sound output goes to the WAV file at `path`.
+    pub fn record(&mut self, song: u8, seconds: u32, path: &Path) -> Result<(), std::io::Error> {
+        let sample_rate = self.mmu.borrow_mut().sound_mut().sample_rate();
+        let recorder = AudioRecorder::create(path, sample_rate, false)?;
+        self.mmu.borrow_mut().sound_mut().start_recording(recorder);
+        self.play(song, seconds)?;
+        match self.mmu.borrow_mut().sound_mut().stop_recording() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `song` (0-based) for `seconds` of emulated time.
+    pub fn play(&mut self, song: u8, seconds: u32) -> Result<(), std::io::Error> {
+        if song >= self.header.song_count {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("no song {} of {}.", song + 1, self.header.song_count),
+            ));
+        }
+
+        {
+            let mut mmu = self.mmu.borrow_mut();
+            for (address, value) in [
+                (0xFF26, 0x80),
+                (0xFF25, 0xFF),
+                (0xFF24, 0x77),
+                (0xFF06, self.header.timer_modulo),
+                (0xFF07, self.header.timer_control & 0x07),
+            ] {
+                mmu.write_byte(address, value)?;
+            }
+        }
+
+        self.cpu.set_registers(Registers {
+            a: song,
+            stack_pointer: self.header.stack_pointer,
+            ..Registers::default()
+        });
+        self.cpu.call(self.header.init_address, RETURN_ADDRESS);
+
+        // PLAY is called again once it returned and a period went by, INIT may take longer.
+        let period = self.header.play_period();
+        let total = u64::from(seconds) * u64::from(CYCLES_PER_SECOND);
+        let mut elapsed = 0;
+        let mut idle = false;
+        while elapsed < total {
+            if idle {
+                self.cpu.call(self.header.play_address, RETURN_ADDRESS);
+            }
+
+            let mut used = 0;
+            while self.cpu.program_counter() != RETURN_ADDRESS && used < period {
+                // A stopped CPU takes no cycles, time still has to go by.
+                let cycles = self.cpu.step().max(4);
+                self.mmu.borrow_mut().step(cycles);
+                used += cycles;
+            }
+
+            idle = self.cpu.program_counter() == RETURN_ADDRESS;
+            if idle && used < period {
+                self.mmu.borrow_mut().step(period - used);
+                used = period;
+            }
+            elapsed += u64::from(used);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(player: &GbsPlayer, address: usize) -> u8 {
+        player.mmu.borrow().read_byte(address).unwrap()
+    }
+
+    // INIT stores the song in 0xC001, PLAY counts its calls in 0xC000.
+    fn gbs(timer_modulo: u8, timer_control: u8) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[0..4].copy_from_slice(b"GBS\x01");
+        data[0x04] = 3;
+        data[0x05] = 1;
+        data[0x06..0x08].copy_from_slice(&0x0400u16.to_le_bytes());
+        data[0x08..0x0A].copy_from_slice(&0x0400u16.to_le_bytes());
+        data[0x0A..0x0C].copy_from_slice(&0x0404u16.to_le_bytes());
+        data[0x0C..0x0E].copy_from_slice(&0xDFFFu16.to_le_bytes());
+        data[0x0E] = timer_modulo;
+        data[0x0F] = timer_control;
+        data[0x10..0x14].copy_from_slice(b"Test");
+        data.extend_from_slice(&[
+            // INIT: LD (0xC001),A; RET
+            0xEA, 0x01, 0xC0, 0xC9, //
+            // PLAY: LD HL,0xC000; INC (HL); RET
+            0x21, 0x00, 0xC0, 0x34, 0xC9,
+        ]);
+        data
+    }
+
+    #[test]
+    fn header_fields() {
+        let header = GbsHeader::new(&gbs(0, 0)).unwrap();
+        assert_eq!(header.song_count, 3);
+        assert_eq!(header.load_address, 0x0400);
+        assert_eq!(header.play_address, 0x0404);
+        assert_eq!(header.stack_pointer, 0xDFFF);
+        assert_eq!(header.title, "Test");
+        assert_eq!(header.play_period(), FRAME_CYCLES);
+
+        assert!(GbsHeader::new(b"GBX").is_err());
+    }
+
+    #[test]
+    fn timer_play_period() {
+        // 4096 Hz / (256 - 0xC0) = 64 Hz
+        let header = GbsHeader::new(&gbs(0xC0, 0x04)).unwrap();
+        assert_eq!(header.play_period(), 65_536);
+        let header = GbsHeader::new(&gbs(0xC0, 0x84)).unwrap();
+        assert_eq!(header.play_period(), 32_768);
+    }
+
+    #[test]
+    fn memory_map() {
+        let data = gbs(0, 0);
+        let header = GbsHeader::new(&data).unwrap();
+        let mut cartridge = GbsCartridge::new(&data, &header);
+        // RST 0x38 jumps to 0x0438.
+        assert_eq!(cartridge.read_byte(0x38).unwrap(), 0xC3);
+        assert_eq!(cartridge.read_word(0x39).unwrap(), 0x0438);
+        assert_eq!(cartridge.read_byte(0x0400).unwrap(), 0xEA);
+        cartridge.write_byte(0xA123, 0x42).unwrap();
+        assert_eq!(cartridge.read_byte(0xA123).unwrap(), 0x42);
+        // Bank 0 selects bank 1.
+        cartridge.write_byte(0x2000, 0x00).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn calls_init_and_play() {
+        let mut player = GbsPlayer::new(&gbs(0, 0)).unwrap();
+        player.play(2, 1).unwrap();
+        assert_eq!(read(&player, 0xC001), 2);
+        // About 59.7 frames per second, the first one runs INIT.
+        assert_eq!(read(&player, 0xC000), 59);
+        assert_eq!(read(&player, 0xFF26) & 0x80, 0x80);
+
+        let mut player = GbsPlayer::new(&gbs(0xC0, 0x04)).unwrap();
+        player.play(0, 1).unwrap();
+        assert_eq!(read(&player, 0xC000), 63);
+
+        assert!(GbsPlayer::new(&gbs(0, 0)).unwrap().play(3, 1).is_err());
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{background_palette_index::BackgroundPaletteIndex, memory_device::ReadWrite};
+use crate::{
+    background_palette_index::BackgroundPaletteIndex,
+    dmg_palette::DmgPalettes,
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 struct Palette {
@@ -25,11 +30,12 @@ impl From<u8> for Palette {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 enum Color {
     White = 0,
     LightGray = 1,
     DarkGray = 2,
+    #[default]
     Black = 3,
 }
 
@@ -45,14 +51,20 @@ impl From<u8> for Color {
     }
 }
 
-impl Default for Color {
-    fn default() -> Self {
-        Self::Black
-    }
-}
+/// Width of the screen in pixels.
+pub const SCREEN_W: usize = 160;
+/// Height of the screen in pixels.
+pub const SCREEN_H: usize = 144;
 
-const SCREEN_W: usize = 160;
-const SCREEN_H: usize = 144;
+// Dots spent in each mode of a visible line, 456 in total.
+const OAM_SEARCH_CYCLES: u32 = 80;
+const TRANSFER_CYCLES: u32 = 172;
+const H_BLANK_CYCLES: u32 = 204;
+const LINE_CYCLES: u32 = 456;
+const LINES: u8 = 154;
+
+// Only ten sprites are shown on each line, the first ten found in OAM.
+const SPRITES_PER_LINE: usize = 10;
 
 #[allow(dead_code)]
 pub struct GraphicsProcessingUnit {
@@ -146,6 +158,18 @@ pub struct GraphicsProcessingUnit {
     // The LY can take on any value between 0 through 153. The values between 144 and 153 indicate the V-Blank period.
     current_y: u8,
 
+    // LY Compare 0xFF45: the coincidence flag of STAT is set while LY has the same value.
+    ly_compare: u8,
+
+    // Window Y 0xFF4A and Window X minus 7 0xFF4B: upper left corner of the window on screen.
+    window_y: u8,
+    window_x: u8,
+    // The window keeps its own line counter, it only advances on lines where the window was drawn.
+    window_line: u8,
+
+    // Dots spent in the current mode.
+    mode_clock: u32,
+
     // This register assigns gray shades to the color indexes of the BG and Window tiles.
     // Bit 7-6 - Color for index 3
     // Bit 5-4 - Color for index 2
@@ -163,6 +187,9 @@ pub struct GraphicsProcessingUnit {
     bgj_pallete_1: Palette,
 
     bpi: BackgroundPaletteIndex,
+
+    // RGB colors of the four shades, gray on DMG and colorized by the CGB boot ROM.
+    colors: DmgPalettes,
 }
 
 impl GraphicsProcessingUnit {
@@ -179,32 +206,248 @@ impl GraphicsProcessingUnit {
             scroll_x: 0,
             control: 0,
             current_y: 0,
+            ly_compare: 0,
+            window_y: 0,
+            window_x: 0,
+            window_line: 0,
+            mode_clock: 0,
             bg_pallete: Palette::default(),
             bgj_pallete_0: Palette::default(),
             bgj_pallete_1: Palette::from(1),
             bpi: BackgroundPaletteIndex::default(),
+            colors: DmgPalettes::default(),
         }
     }
 
+    pub fn set_dmg_palettes(&mut self, colors: DmgPalettes) {
+        self.colors = colors;
+    }
+
+    fn mode(&self) -> u8 {
+        self.status & 0x03
+    }
+
+    fn set_mode(&mut self, mode: u8) {
+        self.status = (self.status & !0x03) | mode;
+    }
+
+    fn set_current_y(&mut self, line: u8) {
+        self.current_y = line;
+        if self.current_y == self.ly_compare {
+            self.status |= 0x04;
+        } else {
+            self.status &= !0x04;
+        }
+    }
+
+    /// The last frame drawn, RGB pixels row after row.
+    pub fn frame(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn step(&mut self, cycles: u32) -> u8 {
         self.h_blank = false;
-        let _lol = cycles;
+        self.v_blank = false;
+
+        if self.control & 0x80 == 0 {
+            return 0;
+        }
+
+        self.mode_clock += cycles;
+        loop {
+            match self.mode() {
+                2 if self.mode_clock >= OAM_SEARCH_CYCLES => {
+                    self.mode_clock -= OAM_SEARCH_CYCLES;
+                    self.set_mode(3);
+                }
+                3 if self.mode_clock >= TRANSFER_CYCLES => {
+                    self.mode_clock -= TRANSFER_CYCLES;
+                    self.render_line();
+                    self.h_blank = true;
+                    self.set_mode(0);
+                }
+                0 if self.mode_clock >= H_BLANK_CYCLES => {
+                    self.mode_clock -= H_BLANK_CYCLES;
+                    self.set_current_y(self.current_y + 1);
+                    if self.current_y as usize == SCREEN_H {
+                        self.v_blank = true;
+                        self.set_mode(1);
+                    } else {
+                        self.set_mode(2);
+                    }
+                }
+                1 if self.mode_clock >= LINE_CYCLES => {
+                    self.mode_clock -= LINE_CYCLES;
+                    if self.current_y + 1 == LINES {
+                        self.window_line = 0;
+                        self.set_current_y(0);
+                        self.set_mode(2);
+                    } else {
+                        self.set_current_y(self.current_y + 1);
+                    }
+                }
+                _ => break,
+            }
+        }
+
         0
     }
+
+    // Tile data for BG and window: 0x8000 with unsigned numbers, or 0x9000 with signed ones.
+    fn tile_row_address(&self, tile: u8, row: usize) -> usize {
+        let base = if self.control & 0x10 != 0 {
+            0x8000 + tile as usize * 16
+        } else {
+            (0x9000 + i32::from(tile as i8) * 16) as usize
+        };
+        base + row * 2
+    }
+
+    // Color index 0-3 of pixel `x` (0 is leftmost) in the tile row at `address` of VRAM bank 0.
+    fn pixel(&self, address: usize, x: usize) -> u8 {
+        let low = self.vram[address - 0x8000];
+        let high = self.vram[address - 0x8000 + 1];
+        let bit = 7 - x;
+        ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01)
+    }
+
+    fn render_line(&mut self) {
+        let line = self.current_y as usize;
+        if line >= SCREEN_H {
+            return;
+        }
+
+        // Color indexes of BG and window, sprites with priority go behind indexes 1-3.
+        let mut bg = [0u8; SCREEN_W];
+        if self.control & 0x01 != 0 {
+            self.render_background(line, &mut bg);
+        }
+
+        let mut shades = [0u8; SCREEN_W];
+        for (x, shade) in shades.iter_mut().enumerate() {
+            *shade = match bg[x] {
+                0 => self.bg_pallete.index_0,
+                1 => self.bg_pallete.index_1,
+                2 => self.bg_pallete.index_2,
+                _ => self.bg_pallete.index_3,
+            } as u8;
+        }
+        let mut rgb = shades.map(|s| self.colors.bg[s as usize]);
+
+        if self.control & 0x02 != 0 {
+            self.render_sprites(line, &bg, &mut rgb);
+        }
+
+        for (x, color) in rgb.iter().enumerate() {
+            let offset = (line * SCREEN_W + x) * 3;
+            self.data[offset..offset + 3].copy_from_slice(color);
+        }
+    }
+
+    fn render_background(&mut self, line: usize, bg: &mut [u8; SCREEN_W]) {
+        let bg_map = if self.control & 0x08 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let y = (line + self.scroll_y as usize) & 0xFF;
+        for (x, index) in bg.iter_mut().enumerate() {
+            let bg_x = (x + self.scroll_x as usize) & 0xFF;
+            let tile = self.vram[bg_map - 0x8000 + (y / 8) * 32 + bg_x / 8];
+            *index = self.pixel(self.tile_row_address(tile, y % 8), bg_x % 8);
+        }
+
+        let window_x = self.window_x as usize;
+        if self.control & 0x20 == 0 || line < self.window_y as usize || window_x > 166 {
+            return;
+        }
+
+        let window_map = if self.control & 0x40 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let y = self.window_line as usize;
+        for (x, index) in bg.iter_mut().enumerate() {
+            if x + 7 < window_x {
+                continue;
+            }
+            let win_x = x + 7 - window_x;
+            let tile = self.vram[window_map - 0x8000 + (y / 8) * 32 + win_x / 8];
+            *index = self.pixel(self.tile_row_address(tile, y % 8), win_x % 8);
+        }
+        self.window_line += 1;
+    }
+
+    fn render_sprites(&self, line: usize, bg: &[u8; SCREEN_W], rgb: &mut [[u8; 3]; SCREEN_W]) {
+        let height = if self.control & 0x04 != 0 { 16 } else { 8 };
+        let mut sprites = self
+            .oam
+            .chunks(4)
+            .filter(|s| {
+                let top = s[0] as usize;
+                line + 16 >= top && line + 16 < top + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect::<Vec<_>>();
+
+        // On DMG the sprite with the smaller X is on top, OAM order breaks ties.
+        // Drawing from the lowest priority lets the higher ones overwrite it.
+        sprites.sort_by_key(|s| s[1]);
+        for sprite in sprites.iter().rev() {
+            let flags = sprite[3];
+            let mut row = line + 16 - sprite[0] as usize;
+            if flags & 0x40 != 0 {
+                row = height - 1 - row;
+            }
+            let tile = if height == 16 {
+                sprite[2] & 0xFE
+            } else {
+                sprite[2]
+            };
+            let address = 0x8000 + tile as usize * 16 + row * 2;
+            let (palette, colors) = if flags & 0x10 != 0 {
+                (self.bgj_pallete_1, &self.colors.obj1)
+            } else {
+                (self.bgj_pallete_0, &self.colors.obj0)
+            };
+
+            for dx in 0..8 {
+                let x = sprite[1] as usize + dx;
+                if !(8..SCREEN_W + 8).contains(&x) {
+                    continue;
+                }
+                let x = x - 8;
+                let index = self.pixel(address, if flags & 0x20 != 0 { 7 - dx } else { dx });
+                if index == 0 || (flags & 0x80 != 0 && bg[x] != 0) {
+                    continue;
+                }
+                let shade = match index {
+                    1 => palette.index_1,
+                    2 => palette.index_2,
+                    _ => palette.index_3,
+                };
+                rgb[x] = colors[shade as usize];
+            }
+        }
+    }
 }
 
 impl ReadWrite for GraphicsProcessingUnit {
     fn contains(&self, address: usize) -> bool {
         (0x8000..=0x9FFF).contains(&address)
-            || (0xFE00..=0xFE9F).contains(&address)
+            || (0xFE00..=0xFEFF).contains(&address)
             || 0xFF40 == address
             || 0xFF41 == address
             || 0xFF42 == address
             || 0xFF43 == address
             || 0xFF44 == address
+            || 0xFF45 == address
             || 0xFF47 == address
             || 0xFF48 == address
             || 0xFF49 == address
+            || 0xFF4A == address
+            || 0xFF4B == address
             || 0xFF4F == address
             || self.bpi.contains(address)
             || address == 0xFF69
@@ -220,14 +463,19 @@ impl ReadWrite for GraphicsProcessingUnit {
                 Ok(self.vram[self.bank as usize * 0x2000_usize + address - 0x8000_usize])
             }
             0xFE00..=0xFE9F => Ok(self.oam[address - 0xFE00]),
+            // Not usable area after OAM, DMG reads it as 0x00.
+            0xFEA0..=0xFEFF => Ok(0x00),
             0xFF40 => Ok(self.control),
-            0xFF41 => Ok(self.status),
+            0xFF41 => Ok(self.status | 0x80),
             0xFF42 => Ok(self.scroll_y),
             0xFF43 => Ok(self.scroll_x),
             0xFF44 => Ok(self.current_y),
+            0xFF45 => Ok(self.ly_compare),
             0xFF47 => Ok(self.bg_pallete.into()),
             0xFF48 => Ok(self.bgj_pallete_0.into()),
             0xFF49 => Ok(self.bgj_pallete_1.into()),
+            0xFF4A => Ok(self.window_y),
+            0xFF4B => Ok(self.window_x),
             0xFF4F => Ok(0xFE | self.bank),
             0xFF69 => {
                 let _r = self.bpi.value() >> 3;
@@ -256,14 +504,32 @@ impl ReadWrite for GraphicsProcessingUnit {
                 self.vram[self.bank as usize * 0x2000_usize + address - 0x8000_usize] = value
             }
             0xFE00..=0xFE9F => self.oam[address - 0xFE00] = value,
-            0xFF40 => self.control = value,
-            0xFF41 => self.status = value,
+            // Games clearing OAM often run into it, writes are lost.
+            0xFEA0..=0xFEFF => {}
+            0xFF40 => {
+                // Turning the LCD off resets LY and leaves it in H-Blank until turned back on.
+                if value & 0x80 == 0 && self.control & 0x80 != 0 {
+                    self.mode_clock = 0;
+                    self.window_line = 0;
+                    self.set_current_y(0);
+                    self.set_mode(0);
+                } else if value & 0x80 != 0 && self.control & 0x80 == 0 {
+                    self.set_mode(2);
+                }
+                self.control = value
+            }
+            // Mode and coincidence flag are read only.
+            0xFF41 => self.status = (value & 0x78) | (self.status & 0x07),
             0xFF42 => self.scroll_y = value,
             0xFF43 => self.scroll_x = value,
-            0xFF44 => self.current_y = value,
+            // LY is read only.
+            0xFF44 => {}
+            0xFF45 => self.ly_compare = value,
             0xFF47 => self.bg_pallete = value.into(),
             0xFF48 => self.bgj_pallete_0 = value.into(),
             0xFF49 => self.bgj_pallete_1 = value.into(),
+            0xFF4A => self.window_y = value,
+            0xFF4B => self.window_x = value,
             0xFF4F => self.bank = value & 0x01,
             0xFF69 => {
                 // TODO: do something here
@@ -284,9 +550,63 @@ impl ReadWrite for GraphicsProcessingUnit {
     }
 }
 
+// The colors of the shades are a setting of the emulator, not state.
+impl SaveState for GraphicsProcessingUnit {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.vram);
+        out.push(self.bank);
+        out.extend_from_slice(&self.data);
+        out.extend_from_slice(&[self.h_blank as u8, self.v_blank as u8]);
+        out.extend_from_slice(&self.oam);
+        out.extend_from_slice(&[
+            self.status,
+            self.scroll_y,
+            self.scroll_x,
+            self.control,
+            self.current_y,
+            self.ly_compare,
+            self.window_y,
+            self.window_x,
+            self.window_line,
+        ]);
+        out.extend_from_slice(&self.mode_clock.to_le_bytes());
+        out.extend_from_slice(&[
+            self.bg_pallete.into(),
+            self.bgj_pallete_0.into(),
+            self.bgj_pallete_1.into(),
+        ]);
+        self.bpi.save_state(out);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        state.fill(&mut self.vram)?;
+        self.bank = state.u8()?;
+        state.fill(&mut self.data)?;
+        self.h_blank = state.bool()?;
+        self.v_blank = state.bool()?;
+        state.fill(&mut self.oam)?;
+        self.status = state.u8()?;
+        self.scroll_y = state.u8()?;
+        self.scroll_x = state.u8()?;
+        self.control = state.u8()?;
+        self.current_y = state.u8()?;
+        self.ly_compare = state.u8()?;
+        self.window_y = state.u8()?;
+        self.window_x = state.u8()?;
+        self.window_line = state.u8()?;
+        self.mode_clock = state.u32()?;
+        self.bg_pallete = state.u8()?.into();
+        self.bgj_pallete_0 = state.u8()?.into();
+        self.bgj_pallete_1 = state.u8()?.into();
+        self.bpi.load_state(state)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::gpu::{Color, Palette};
+    use crate::dmg_palette::DmgPalettes;
+    use crate::gpu::{Color, GraphicsProcessingUnit, Palette, SCREEN_W};
+    use crate::memory_device::ReadWrite;
 
     #[test]
     fn palette_from_u8() {
@@ -312,4 +632,77 @@ mod tests {
         };
         assert_eq!(Into::<u8>::into(palette), 0b00_01_10_11);
     }
+
+    #[test]
+    fn line_and_mode_timing() {
+        let mut gpu = GraphicsProcessingUnit::new();
+        gpu.write_byte(0xFF45, 0x01).unwrap();
+        gpu.write_byte(0xFF40, 0x91).unwrap();
+        assert_eq!(gpu.read_byte(0xFF41).unwrap() & 0x03, 2);
+
+        gpu.step(80);
+        assert_eq!(gpu.read_byte(0xFF41).unwrap() & 0x03, 3);
+        gpu.step(172);
+        assert!(gpu.h_blank);
+        assert_eq!(gpu.read_byte(0xFF41).unwrap() & 0x03, 0);
+        gpu.step(204);
+        assert_eq!(gpu.read_byte(0xFF44).unwrap(), 1);
+        assert_eq!(gpu.read_byte(0xFF41).unwrap() & 0x07, 0x06);
+
+        gpu.step(456 * 143);
+        assert!(gpu.v_blank);
+        assert_eq!(gpu.read_byte(0xFF44).unwrap(), 144);
+        assert_eq!(gpu.read_byte(0xFF41).unwrap() & 0x03, 1);
+
+        gpu.step(456 * 10);
+        assert_eq!(gpu.read_byte(0xFF44).unwrap(), 0);
+        assert_eq!(gpu.read_byte(0xFF41).unwrap() & 0x03, 2);
+    }
+
+    #[test]
+    fn background_uses_rgb_palette() {
+        let mut gpu = GraphicsProcessingUnit::new();
+        let green = DmgPalettes::from_config("bg = 9BBC0F 8BAC0F 306230 0F380F").unwrap();
+        gpu.set_dmg_palettes(green);
+
+        // Tile 1 has color index 3 on its first row, the map shows it in the top left corner.
+        gpu.write_byte(0x8010, 0xFF).unwrap();
+        gpu.write_byte(0x8011, 0xFF).unwrap();
+        gpu.write_byte(0x9800, 0x01).unwrap();
+        gpu.write_byte(0xFF47, 0xE4).unwrap();
+        gpu.write_byte(0xFF40, 0x91).unwrap();
+        gpu.step(456);
+
+        assert_eq!(gpu.data[0..3], [0x0F, 0x38, 0x0F]);
+        assert_eq!(gpu.data[8 * 3..8 * 3 + 3], [0x9B, 0xBC, 0x0F]);
+        // Only the first line has been drawn.
+        assert_eq!(gpu.data[SCREEN_W * 3..SCREEN_W * 3 + 3], [0xFF; 3]);
+    }
+
+    #[test]
+    fn not_usable_area() {
+        let mut gpu = GraphicsProcessingUnit::new();
+        assert!(gpu.contains(0xFEFF));
+        gpu.write_byte(0xFEA0, 0x42).unwrap();
+        assert_eq!(gpu.read_byte(0xFEA0).unwrap(), 0x00);
+        assert!(!gpu.contains(0xFF00));
+    }
+
+    #[test]
+    fn sprite_over_background() {
+        let mut gpu = GraphicsProcessingUnit::new();
+        gpu.write_byte(0x8010, 0xFF).unwrap();
+        gpu.write_byte(0x8011, 0x00).unwrap();
+        // Sprite 0 at the top left corner, using tile 1 and OBP1.
+        for (i, v) in [16, 8, 0x01, 0x10].iter().enumerate() {
+            gpu.write_byte(0xFE00 + i, *v).unwrap();
+        }
+        gpu.write_byte(0xFF47, 0xE4).unwrap();
+        gpu.write_byte(0xFF49, 0x0C).unwrap();
+        gpu.write_byte(0xFF40, 0x83).unwrap();
+        gpu.step(456);
+
+        assert_eq!(gpu.data[0..3], [0x00; 3]);
+        assert_eq!(gpu.data[8 * 3..8 * 3 + 3], [0xFF; 3]);
+    }
 }
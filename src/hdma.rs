@@ -1,4 +1,7 @@
-use crate::memory_device::ReadWrite;
+use crate::{
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum HdmaMode {
@@ -81,15 +84,13 @@ impl ReadWrite for Hdma {
             0xFF53 => Ok((self.destination >> 8) as u8),
             0xFF54 => Ok(self.destination as u8),
             0xFF55 => Ok(self.remain | if self.active { 0x00 } else { 0x80 }),
-            _ => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::OutOfMemory,
-                    format!(
-                        "hdma don't know where read byte for this address {:#04x}",
-                        address
-                    ),
-                ))
-            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::OutOfMemory,
+                format!(
+                    "hdma don't know where read byte for this address {:#04x}",
+                    address
+                ),
+            )),
         }
     }
 
@@ -140,6 +141,30 @@ impl ReadWrite for Hdma {
     }
 }
 
+impl SaveState for Hdma {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.source.to_le_bytes());
+        out.extend_from_slice(&self.destination.to_le_bytes());
+        out.extend_from_slice(&[
+            self.active as u8,
+            (self.mode == HdmaMode::Hdma) as u8,
+            self.remain,
+        ]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.source = state.u16()?;
+        self.destination = state.u16()?;
+        self.active = state.bool()?;
+        self.mode = match state.bool()? {
+            true => HdmaMode::Hdma,
+            false => HdmaMode::Gdma,
+        };
+        self.remain = state.u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+
+use crate::emulator::Emulator;
+
+/// Something to wait for in a headless run, the first one met stops it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopCondition {
+    /// The CPU is about to run the instruction at this address.
+    Pc(u16),
+    /// The game sent this text over the serial port, like test roms printing `Passed`.
+    Serial(String),
+    /// The byte at `address` holds `value`. Nothing answers at some addresses, like the
+    /// unused ones around the I/O registers: they read 0xFF, which is met right away.
+    Memory { address: u16, value: u8 },
+}
+
+impl StopCondition {
+    fn met(&self, emu: &Emulator, serial: &[u8]) -> bool {
+        match self {
+            StopCondition::Pc(pc) => emu.program_counter() == *pc,
+            // Bytes come one at a time, the text is there as soon as it ends the output.
+            StopCondition::Serial(text) => serial.ends_with(text.as_bytes()),
+            StopCondition::Memory { address, value } => emu.read_memory(*address) == *value,
+        }
+    }
+}
+
+/// Why a headless run stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopReason {
+    Condition(StopCondition),
+    FrameLimit,
+    CycleLimit,
+    /// The CPU ran STOP, nothing wakes it up.
+    CpuStopped,
+    /// The emulator panicked, with this message.
+    Crashed(String),
+}
+
+impl StopReason {
+    fn name(&self) -> &'static str {
+        match self {
+            StopReason::Condition(StopCondition::Pc(_)) => "pc",
+            StopReason::Condition(StopCondition::Serial(_)) => "serial",
+            StopReason::Condition(StopCondition::Memory { .. }) => "memory",
+            StopReason::FrameLimit => "frames",
+            StopReason::CycleLimit => "cycles",
+            StopReason::CpuStopped => "stopped",
+            StopReason::Crashed(_) => "crashed",
+        }
+    }
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Condition(StopCondition::Pc(pc)) => write!(f, "PC reached {:#06X}", pc),
+            StopReason::Condition(StopCondition::Serial(text)) => {
+                write!(f, "serial output {:?}", text)
+            }
+            StopReason::Condition(StopCondition::Memory { address, value }) => {
+                write!(f, "{:#06X} holds {:#04X}", address, value)
+            }
+            StopReason::FrameLimit => write!(f, "frame limit"),
+            StopReason::CycleLimit => write!(f, "cycle limit"),
+            StopReason::CpuStopped => write!(f, "CPU stopped"),
+            StopReason::Crashed(message) => write!(f, "crashed: {}", message),
+        }
+    }
+}
+
+// Keeps what the game sends over the serial port, and passes it on to stdout if asked.
+struct SerialCapture {
+    seen: Rc<RefCell<Vec<u8>>>,
+    echo: bool,
+}
+
+impl Write for SerialCapture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.seen.borrow_mut().extend_from_slice(buf);
+        if self.echo {
+            std::io::stdout().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.echo {
+            std::io::stdout().flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs an emulator with nobody watching, for scripts and build servers: up to a number of
+/// frames or cycles, or until one of the conditions is met.
+#[derive(Clone, Debug, Default)]
+pub struct HeadlessRun {
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    conditions: Vec<StopCondition>,
+    echo_serial: bool,
+}
+
+/// How a headless run went.
+#[derive(Debug)]
+pub struct HeadlessReport {
+    pub reason: StopReason,
+    /// Frames run to the end.
+    pub frames: u64,
+    pub cycles: u64,
+    /// Everything sent over the serial port.
+    pub serial: Vec<u8>,
+    // A limit reached while waiting for a condition is a timeout.
+    waiting: bool,
+}
+
+impl HeadlessRun {
+    pub fn new() -> HeadlessRun {
+        HeadlessRun::default()
+    }
+
+    pub fn frames(mut self, frames: u64) -> HeadlessRun {
+        self.frames = Some(frames);
+        self
+    }
+
+    pub fn cycles(mut self, cycles: u64) -> HeadlessRun {
+        self.cycles = Some(cycles);
+        self
+    }
+
+    pub fn until(mut self, condition: StopCondition) -> HeadlessRun {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Copies the serial output to stdout as it comes.
+    pub fn echo_serial(mut self, echo: bool) -> HeadlessRun {
+        self.echo_serial = echo;
+        self
+    }
+
+    /// False when nothing would ever stop the run.
+    pub fn is_bounded(&self) -> bool {
+        self.frames.is_some() || self.cycles.is_some() || !self.conditions.is_empty()
+    }
+
+    /// Runs `emu` until a limit, a condition, a STOP or a panic. The serial port sink of
+    /// `emu` is replaced to watch the output. A panic is caught and reported, the panic hook
+    /// is left alone: it's up to the program to keep it quiet.
+    pub fn run(&self, emu: &mut Emulator) -> HeadlessReport {
+        let seen = Rc::new(RefCell::new(vec![]));
+        emu.set_serial_sink(Box::new(SerialCapture {
+            seen: seen.clone(),
+            echo: self.echo_serial,
+        }));
+
+        let (mut frames, mut cycles) = (0, 0);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            self.run_frames(emu, &seen, &mut frames, &mut cycles)
+        }));
+        let reason = result.unwrap_or_else(|payload| {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            StopReason::Crashed(message)
+        });
+
+        let serial = seen.borrow().clone();
+        HeadlessReport {
+            reason,
+            frames,
+            cycles,
+            serial,
+            waiting: !self.conditions.is_empty(),
+        }
+    }
+
+    fn run_frames(
+        &self,
+        emu: &mut Emulator,
+        seen: &RefCell<Vec<u8>>,
+        frames: &mut u64,
+        cycles: &mut u64,
+    ) -> StopReason {
+        // Conditions that hold before the first instruction, like the entry point without a
+        // boot ROM.
+        if let Some(condition) = self.met_condition(emu, seen) {
+            return StopReason::Condition(condition);
+        }
+        loop {
+            if self.frames.is_some_and(|limit| *frames >= limit) {
+                return StopReason::FrameLimit;
+            }
+
+            let mut reason = None;
+            emu.run_frame_until(|emu, step| {
+                *cycles += u64::from(step);
+                reason = if emu.is_stopped() {
+                    Some(StopReason::CpuStopped)
+                } else if let Some(condition) = self.met_condition(emu, seen) {
+                    Some(StopReason::Condition(condition))
+                } else if self.cycles.is_some_and(|limit| *cycles >= limit) {
+                    Some(StopReason::CycleLimit)
+                } else {
+                    None
+                };
+                reason.is_some()
+            });
+            if let Some(reason) = reason {
+                return reason;
+            }
+            *frames += 1;
+        }
+    }
+
+    fn met_condition(&self, emu: &Emulator, seen: &RefCell<Vec<u8>>) -> Option<StopCondition> {
+        let serial = seen.borrow();
+        self.conditions
+            .iter()
+            .find(|c| c.met(emu, &serial))
+            .cloned()
+    }
+}
+
+// The text as a JSON string, quotes included.
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl HeadlessReport {
+    /// 0 when a condition was met, or a limit was reached with no condition to wait for.
+    /// 2 when a limit was reached first, 3 when the CPU stopped and 4 when the emulator
+    /// crashed. Errors running it at all are left 1.
+    pub fn exit_code(&self) -> i32 {
+        match self.reason {
+            StopReason::Condition(_) => 0,
+            StopReason::FrameLimit | StopReason::CycleLimit if !self.waiting => 0,
+            StopReason::FrameLimit | StopReason::CycleLimit => 2,
+            StopReason::CpuStopped => 3,
+            StopReason::Crashed(_) => 4,
+        }
+    }
+
+    /// Writes the report and the registers of `emu` as a JSON object.
+    pub fn write_json(&self, emu: &Emulator, mut out: impl Write) -> Result<(), std::io::Error> {
+        let registers = emu.registers();
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"reason\": \"{}\",", self.reason.name())?;
+        writeln!(
+            out,
+            "  \"detail\": {},",
+            json_string(&self.reason.to_string())
+        )?;
+        writeln!(out, "  \"exit_code\": {},", self.exit_code())?;
+        writeln!(out, "  \"frames\": {},", self.frames)?;
+        writeln!(out, "  \"cycles\": {},", self.cycles)?;
+        writeln!(out, "  \"registers\": {{")?;
+        for (name, value) in [
+            ("a", registers.a),
+            ("f", registers.af() as u8),
+            ("b", registers.b),
+            ("c", registers.c),
+            ("d", registers.d),
+            ("e", registers.e),
+            ("h", registers.h),
+            ("l", registers.l),
+        ] {
+            writeln!(out, "    \"{}\": {},", name, value)?;
+        }
+        writeln!(out, "    \"sp\": {},", registers.stack_pointer)?;
+        writeln!(out, "    \"pc\": {}", registers.program_counter)?;
+        writeln!(out, "  }},")?;
+        writeln!(
+            out,
+            "  \"serial\": {}",
+            json_string(&String::from_utf8_lossy(&self.serial))
+        )?;
+        writeln!(out, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::emulator;
+
+    #[test]
+    fn limits() {
+        let mut emu = emulator(&[]);
+        let report = HeadlessRun::new().frames(2).run(&mut emu);
+        assert_eq!(report.reason, StopReason::FrameLimit);
+        assert_eq!(report.frames, 2);
+        assert_eq!(report.exit_code(), 0);
+
+        let mut emu = emulator(&[]);
+        let report = HeadlessRun::new().cycles(1000).frames(2).run(&mut emu);
+        assert_eq!(report.reason, StopReason::CycleLimit);
+        assert_eq!(report.frames, 0);
+        assert!((1000..1100).contains(&report.cycles));
+        assert!(!HeadlessRun::new().is_bounded());
+    }
+
+    #[test]
+    fn conditions() {
+        // Tetris jumps from the entry point to 0x0150.
+        let mut emu = emulator(&[]);
+        let report = HeadlessRun::new()
+            .until(StopCondition::Pc(0x0150))
+            .run(&mut emu);
+        assert_eq!(
+            report.reason,
+            StopReason::Condition(StopCondition::Pc(0x0150))
+        );
+        assert_eq!(report.cycles, 20);
+        assert_eq!(report.exit_code(), 0);
+
+        // Already there before the first instruction.
+        let mut emu = emulator(&[]);
+        let report = HeadlessRun::new()
+            .until(StopCondition::Pc(0x0100))
+            .run(&mut emu);
+        assert_eq!(
+            report.reason,
+            StopReason::Condition(StopCondition::Pc(0x0100))
+        );
+        assert_eq!(report.cycles, 0);
+
+        let mut emu = emulator(&[]);
+        let report = HeadlessRun::new()
+            .until(StopCondition::Serial("Passed".into()))
+            .cycles(100)
+            .run(&mut emu);
+        assert_eq!(report.reason, StopReason::CycleLimit);
+        assert_eq!(report.exit_code(), 2);
+    }
+
+    #[test]
+    fn halt_and_stop() {
+        // HALT, JR -3: nothing wakes it up, but time goes on.
+        let mut emu = emulator(&[0x76, 0x18, 0xFD]);
+        let report = HeadlessRun::new().frames(3).run(&mut emu);
+        assert_eq!(report.reason, StopReason::FrameLimit);
+        assert_eq!(report.frames, 3);
+
+        let mut emu = emulator(&[0x10, 0x00]);
+        let report = HeadlessRun::new().frames(3).run(&mut emu);
+        assert_eq!(report.reason, StopReason::CpuStopped);
+        assert_eq!(report.exit_code(), 3);
+    }
+
+    #[test]
+    fn crash_is_reported() {
+        // 0xD3 isn't an instruction.
+        let mut emu = emulator(&[0xD3]);
+        let report = HeadlessRun::new().frames(1).run(&mut emu);
+        assert!(matches!(report.reason, StopReason::Crashed(_)));
+        assert_eq!(report.exit_code(), 4);
+    }
+
+    #[test]
+    fn json_report() {
+        let emu = emulator(&[]);
+        let report = HeadlessReport {
+            reason: StopReason::Crashed("bad \"opcode\"\n".into()),
+            frames: 3,
+            cycles: 12,
+            serial: b"ok\t".to_vec(),
+            waiting: false,
+        };
+        let mut json = vec![];
+        report.write_json(&emu, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"reason\": \"crashed\",\n"));
+        assert!(json.contains("\"detail\": \"crashed: bad \\\"opcode\\\"\\n\",\n"));
+        assert!(json.contains("\"exit_code\": 4,\n"));
+        assert!(json.contains("\"pc\": 256\n"));
+        assert!(json.ends_with("\"serial\": \"ok\\t\"\n}\n"));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    cartridge_header::{check_logo, valid_checksum},
+    memory_device::ReadWrite,
+    register::Registers,
+    save_state::{SaveState, StateReader},
+};
+
+// The sequence moves on a scanline at a time, 456 dots, and a frame is 154 of them.
+const LINE_CYCLES: u32 = 456;
+const LINES_PER_FRAME: u32 = 154;
+
+// The logo scrolls down one line every two frames for 0x64 lines,
+// then stays still for another 0x20 steps before handing over to the cartridge.
+const FRAMES_PER_STEP: u32 = 2;
+const SCROLL_STEPS: u8 = 0x64;
+const PAUSE_STEPS: u8 = 0x20;
+
+// Steps at which the two notes of the chime are played, with the low byte of their frequency.
+const FIRST_NOTE: (u8, u8) = (0x62, 0x83);
+const SECOND_NOTE: (u8, u8) = (0x64, 0xC1);
+
+// The ® symbol shown next to the logo, one bit plane only.
+const REGISTERED_TILE: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+/// High level emulation of the boot ROM.
+/// It doesn't run any proprietary code, instead it reproduces what the DMG boot ROM does
+/// frame by frame: logo decompression into VRAM, scroll, chime and header checks,
+/// then it hands over to the cartridge with the register state of the model.
+#[derive(Default)]
+pub struct HleBoot {
+    lines: u32,
+    // Scroll counter, register H in the original boot ROM.
+    steps: u8,
+    registers: Registers,
+}
+
+impl HleBoot {
+    /// Verifies the cartridge header and prepares VRAM and the LCD for the animation,
+    /// `registers` are the ones left to the cartridge when the sequence ends.
+    pub fn new(mmu: &mut dyn ReadWrite, registers: Registers) -> Result<HleBoot, std::io::Error> {
+        let header = (0x0000..0x0150)
+            .map(|a| mmu.read_byte(a))
+            .collect::<Result<Vec<u8>, _>>()?;
+
+        // The real boot ROM locks up when one of these fails.
+        check_logo(&header)?;
+        valid_checksum(&header)?;
+
+        for address in 0x8000..=0x9FFF {
+            mmu.write_byte(address, 0x00)?;
+        }
+
+        for (i, tile) in decompress_logo(&header[0x104..0x134]).iter().enumerate() {
+            for (j, row) in tile.iter().enumerate() {
+                mmu.write_byte(0x8010 + i * 16 + j * 2, *row)?;
+            }
+        }
+
+        for (j, row) in REGISTERED_TILE.iter().enumerate() {
+            mmu.write_byte(0x8190 + j * 2, *row)?;
+        }
+
+        // Tiles 0x01-0x0C are the upper half of the logo, 0x0D-0x18 the lower one.
+        for i in 0..12 {
+            mmu.write_byte(0x9904 + i, 0x01 + i as u8)?;
+            mmu.write_byte(0x9924 + i, 0x0D + i as u8)?;
+        }
+        mmu.write_byte(0x9910, 0x19)?;
+
+        // Sound circuits on, channel 1 with 50% duty and a decreasing envelope,
+        // both terminals at full volume, channel 1 panned center.
+        mmu.write_byte(0xFF26, 0x80)?;
+        mmu.write_byte(0xFF11, 0x80)?;
+        mmu.write_byte(0xFF12, 0xF3)?;
+        mmu.write_byte(0xFF25, 0xF3)?;
+        mmu.write_byte(0xFF24, 0x77)?;
+
+        mmu.write_byte(0xFF42, SCROLL_STEPS)?;
+        mmu.write_byte(0xFF47, 0xFC)?;
+        mmu.write_byte(0xFF40, 0x91)?;
+
+        Ok(HleBoot {
+            lines: 0,
+            steps: 0,
+            registers,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.steps >= SCROLL_STEPS + PAUSE_STEPS
+    }
+
+    /// Runs one scanline of the sequence, returning the master clock cycles it took.
+    /// The logo only moves at the end of a frame.
+    pub fn step(&mut self, mmu: &mut dyn ReadWrite) -> Result<u32, std::io::Error> {
+        self.lines += 1;
+        if !self.lines.is_multiple_of(LINES_PER_FRAME * FRAMES_PER_STEP) || self.is_done() {
+            return Ok(LINE_CYCLES);
+        }
+
+        self.steps += 1;
+        match self.steps {
+            s if s == FIRST_NOTE.0 => self.chime(mmu, FIRST_NOTE.1)?,
+            s if s == SECOND_NOTE.0 => self.chime(mmu, SECOND_NOTE.1)?,
+            _ => {}
+        }
+
+        if self.steps <= SCROLL_STEPS {
+            let scroll = mmu.read_byte(0xFF42)?;
+            mmu.write_byte(0xFF42, scroll.wrapping_sub(1))?;
+        }
+
+        Ok(LINE_CYCLES)
+    }
+
+    /// Registers to load in the CPU when the sequence is over.
+    pub fn into_registers(self) -> Registers {
+        self.registers
+    }
+
+    // Triggers channel 1 with frequency 0x700 | `frequency_low`.
+    fn chime(&self, mmu: &mut dyn ReadWrite, frequency_low: u8) -> Result<(), std::io::Error> {
+        mmu.write_byte(0xFF13, frequency_low)?;
+        mmu.write_byte(0xFF14, 0x87)
+    }
+}
+
+// Each bit of the 48 bytes logo in the header becomes a 2x2 block of pixels:
+// one byte is a 8x4 pixels area, two bytes make up a tile of the 24 used by the logo.
+fn decompress_logo(logo: &[u8]) -> Vec<[u8; 8]> {
+    logo.chunks(2)
+        .map(|pair| {
+            let mut tile = [0; 8];
+            for (i, nibble) in [pair[0] >> 4, pair[0] & 0x0F, pair[1] >> 4, pair[1] & 0x0F]
+                .iter()
+                .enumerate()
+            {
+                let row = double_bits(*nibble);
+                tile[i * 2] = row;
+                tile[i * 2 + 1] = row;
+            }
+            tile
+        })
+        .collect()
+}
+
+fn double_bits(nibble: u8) -> u8 {
+    (0..4).fold(0, |acc, bit| {
+        if nibble & (1 << bit) != 0 {
+            acc | (0b11 << (bit * 2))
+        } else {
+            acc
+        }
+    })
+}
+
+impl SaveState for HleBoot {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.lines.to_le_bytes());
+        out.push(self.steps);
+        self.registers.save_state(out);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.lines = state.u32()?;
+        self.steps = state.u8()?;
+        self.registers.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use super::*;
+
+    struct MockDevice {
+        bytes: HashMap<usize, u8>,
+    }
+
+    impl ReadWrite for MockDevice {
+        fn contains(&self, _address: usize) -> bool {
+            true
+        }
+
+        fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
+            Ok(*self.bytes.get(&address).unwrap_or(&0))
+        }
+
+        fn read_word(&self, _address: usize) -> Result<u16, std::io::Error> {
+            unimplemented!()
+        }
+
+        fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
+            self.bytes.insert(address, value);
+            Ok(())
+        }
+
+        fn write_word(&mut self, _address: usize, _value: u16) -> Result<(), std::io::Error> {
+            unimplemented!()
+        }
+    }
+
+    fn tetris() -> MockDevice {
+        let data = fs::read_to_string("./testdata/tetris")
+            .expect("file not found!")
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect::<Vec<u8>>();
+        MockDevice {
+            bytes: data.into_iter().enumerate().collect(),
+        }
+    }
+
+    #[test]
+    fn double_bits_of_nibble() {
+        assert_eq!(double_bits(0b0000), 0b0000_0000);
+        assert_eq!(double_bits(0b1100), 0b1111_0000);
+        assert_eq!(double_bits(0b1110), 0b1111_1100);
+        assert_eq!(double_bits(0b0101), 0b0011_0011);
+    }
+
+    #[test]
+    fn first_logo_tile() {
+        let tiles = decompress_logo(&[0xCE, 0xED]);
+        assert_eq!(tiles[0], [0xF0, 0xF0, 0xFC, 0xFC, 0xFC, 0xFC, 0xF3, 0xF3]);
+    }
+
+    #[test]
+    fn logo_in_vram() {
+        let mut mmu = tetris();
+        HleBoot::new(&mut mmu, Registers::default()).unwrap();
+        assert_eq!(mmu.read_byte(0x8010).unwrap(), 0xF0);
+        assert_eq!(mmu.read_byte(0x8011).unwrap(), 0x00);
+        assert_eq!(mmu.read_byte(0x8190).unwrap(), 0x3C);
+        assert_eq!(mmu.read_byte(0x9904).unwrap(), 0x01);
+        assert_eq!(mmu.read_byte(0x990F).unwrap(), 0x0C);
+        assert_eq!(mmu.read_byte(0x9910).unwrap(), 0x19);
+        assert_eq!(mmu.read_byte(0x9924).unwrap(), 0x0D);
+        assert_eq!(mmu.read_byte(0x992F).unwrap(), 0x18);
+        assert_eq!(mmu.read_byte(0xFF42).unwrap(), 0x64);
+    }
+
+    #[test]
+    fn bad_logo_locks_up() {
+        let mut mmu = tetris();
+        mmu.write_byte(0x104, 0x00).unwrap();
+        assert!(HleBoot::new(&mut mmu, Registers::default()).is_err());
+    }
+
+    #[test]
+    fn scroll_and_hand_over() {
+        let mut mmu = tetris();
+        let mut boot = HleBoot::new(&mut mmu, Registers::default()).unwrap();
+        let mut lines = 0;
+        while !boot.is_done() {
+            assert_eq!(boot.step(&mut mmu).unwrap(), LINE_CYCLES);
+            lines += 1;
+        }
+
+        assert_eq!(
+            lines,
+            (SCROLL_STEPS + PAUSE_STEPS) as u32 * FRAMES_PER_STEP * LINES_PER_FRAME
+        );
+        assert_eq!(mmu.read_byte(0xFF42).unwrap(), 0x00);
+        assert_eq!(mmu.read_byte(0xFF13).unwrap(), SECOND_NOTE.1);
+        assert_eq!(mmu.read_byte(0xFF14).unwrap(), 0x87);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{
+    emulator::Emulator,
+    lockstep::{Lockstep, LockstepEnd, Pulses, Side},
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
+
+/// What the infrared sensor of a console or cartridge faces: the CGB port takes one with
+/// `Emulator::connect_infrared`, cartridges like HuC1 and HuC3 with
+/// `Emulator::connect_cartridge_infrared`.
+pub trait InfraredPeer {
+    /// The LED of this side turns on or off, now.
+    fn set_led(&mut self, on: bool);
+
+    /// True while light reaches the sensor.
+    fn light(&self) -> bool;
+
+    /// Called as `cycles` go by, before the LED and the sensor are used in that time.
+    fn step(&mut self, _cycles: u32) {}
+}
+
+/// A steady light source in front of the sensor, like a lamp, or darkness.
+pub struct AmbientLight {
+    pub on: bool,
+}
+
+impl InfraredPeer for AmbientLight {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn light(&self) -> bool {
+        self.on
+    }
+}
+
+/// RP, 0xFF56, the infrared port of the CGB.
+pub struct Infrared {
+    // Bit 0 - LED (0=Off, 1=On)
+    // Bit 1 - Read only, received signal (0=Receiving, 1=Normal)
+    // Bit 6-7 - Data Read Enable (0=Disable, 3=Enable)
+    control: u8,
+    cgb: bool,
+    // Without anything in front of it the sensor sees nothing.
+    peer: Option<Box<dyn InfraredPeer>>,
+}
+
+impl Infrared {
+    pub fn new(cgb: bool) -> Infrared {
+        Infrared {
+            control: 0,
+            cgb,
+            peer: None,
+        }
+    }
+
+    pub fn connect(&mut self, peer: Box<dyn InfraredPeer>) {
+        self.peer = Some(peer);
+        self.peer_led();
+    }
+
+    fn peer_led(&mut self) {
+        let on = self.control & 0x01 != 0;
+        if let Some(peer) = &mut self.peer {
+            peer.set_led(on);
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if let Some(peer) = &mut self.peer {
+            peer.step(cycles);
+        }
+    }
+
+    fn receiving(&self) -> bool {
+        self.peer.as_ref().is_some_and(|p| p.light())
+    }
+}
+
+impl ReadWrite for Infrared {
+    fn contains(&self, address: usize) -> bool {
+        self.cgb && address == 0xFF56
+    }
+
+    fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
+        match address {
+            0xFF56 => {
+                let enabled = self.control & 0xC0 == 0xC0;
+                let signal = if enabled && self.receiving() {
+                    0x00
+                } else {
+                    0x02
+                };
+                // Bits 2-5 are unused.
+                Ok(self.control | 0x3C | signal)
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn read_word(&self, _address: usize) -> Result<u16, std::io::Error> {
+        unimplemented!()
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
+        match address {
+            0xFF56 => {
+                self.control = value & 0xC1;
+                self.peer_led();
+            }
+            _ => unimplemented!(),
+        }
+
+        Ok(())
+    }
+
+    fn write_word(&mut self, _address: usize, _value: u16) -> Result<(), std::io::Error> {
+        unimplemented!()
+    }
+}
+
+// One side of an in-process pairing: the light it sees follows the LED of the other side
+// a quantum late, so the width of the pulses is kept.
+#[derive(Default)]
+struct InfraredEnd {
+    pulses: Pulses,
+    led: bool,
+    light: bool,
+}
+
+impl InfraredEnd {
+    fn step(&mut self, cycles: u32) {
+        self.pulses.step(cycles);
+        while let Some(on) = self.pulses.receive() {
+            self.light = on;
+        }
+    }
+
+    fn set_led(&mut self, on: bool) {
+        if on != self.led {
+            self.led = on;
+            self.pulses.send(on);
+        }
+    }
+
+    fn receive(&mut self, pulses: Vec<(u32, bool)>) {
+        // Whatever was left didn't happen yet on the other side either.
+        for (_, on) in self.pulses.next_quantum(pulses) {
+            self.light = on;
+        }
+    }
+}
+
+impl LockstepEnd for InfraredEnd {
+    fn at_boundary(&self) -> bool {
+        self.pulses.at_boundary()
+    }
+
+    fn exchange(ends: &mut [InfraredEnd; 2]) {
+        let first = ends[0].pulses.take_sent();
+        let second = ends[1].pulses.take_sent();
+        ends[0].receive(second);
+        ends[1].receive(first);
+    }
+}
+
+/// Two emulators facing each other in the same process, stepped in lockstep by `run`.
+pub struct InfraredPairing {
+    lockstep: Lockstep<InfraredEnd>,
+}
+
+/// The infrared side of one of the emulators of an `InfraredPairing`.
+pub struct InfraredPort {
+    side: Side<InfraredEnd>,
+}
+
+impl InfraredPeer for InfraredPort {
+    fn set_led(&mut self, on: bool) {
+        self.side.end_mut().set_led(on);
+    }
+
+    fn light(&self) -> bool {
+        self.side.end().light
+    }
+
+    fn step(&mut self, cycles: u32) {
+        self.side.end_mut().step(cycles);
+    }
+}
+
+impl Default for InfraredPairing {
+    fn default() -> Self {
+        InfraredPairing::new()
+    }
+}
+
+impl InfraredPairing {
+    pub fn new() -> InfraredPairing {
+        InfraredPairing {
+            lockstep: Lockstep::new("a pairing"),
+        }
+    }
+
+    /// The port for `side`, 0 or 1, to connect to the infrared port of an emulator.
+    pub fn port(&self, side: usize) -> Result<InfraredPort, std::io::Error> {
+        Ok(InfraredPort {
+            side: self.lockstep.side(side)?,
+        })
+    }
+
+    /// Runs `a`, on side 0, and `b`, on side 1, for `quanta` quanta.
+    pub fn run(&self, a: &mut Emulator, b: &mut Emulator, quanta: u32) {
+        self.lockstep.run(a, b, quanta);
+    }
+}
+
+impl SaveState for Infrared {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.control);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.control = state.u8()?;
+        self.peer_led();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::lockstep::QUANTUM_CYCLES;
+
+    #[test]
+    fn register() {
+        let mut ir = Infrared::new(true);
+        assert_eq!(ir.read_byte(0xFF56).unwrap(), 0x3E);
+        ir.write_byte(0xFF56, 0xFF).unwrap();
+        assert_eq!(ir.read_byte(0xFF56).unwrap(), 0xFF);
+        assert!(!Infrared::new(false).contains(0xFF56));
+    }
+
+    #[test]
+    fn ambient_light_needs_read_enable() {
+        let mut ir = Infrared::new(true);
+        ir.connect(Box::new(AmbientLight { on: true }));
+        assert_eq!(ir.read_byte(0xFF56).unwrap() & 0x02, 0x02);
+        ir.write_byte(0xFF56, 0xC0).unwrap();
+        assert_eq!(ir.read_byte(0xFF56).unwrap() & 0x02, 0x00);
+    }
+
+    #[test]
+    fn pulses_keep_their_width() {
+        let pairing = InfraredPairing::new();
+        let mut sides = [Infrared::new(true), Infrared::new(true)];
+        sides[0].connect(Box::new(pairing.port(0).unwrap()));
+        sides[1].connect(Box::new(pairing.port(1).unwrap()));
+        sides[1].write_byte(0xFF56, 0xC0).unwrap();
+
+        // Side 0 flashes from cycle 100 to 300, side 1 looks every 4 cycles.
+        let mut seen = vec![];
+        for quantum in 0..3 {
+            for cycle in (0..QUANTUM_CYCLES).step_by(4) {
+                let time = quantum * QUANTUM_CYCLES + cycle;
+                sides[0]
+                    .write_byte(0xFF56, (100..300).contains(&time) as u8)
+                    .unwrap();
+                sides[0].step(4);
+            }
+            for cycle in (0..QUANTUM_CYCLES).step_by(4) {
+                sides[1].step(4);
+                if sides[1].read_byte(0xFF56).unwrap() & 0x02 == 0 {
+                    seen.push(quantum * QUANTUM_CYCLES + cycle + 4);
+                }
+            }
+            pairing.lockstep.exchange();
+        }
+        assert_eq!(seen.len(), 200 / 4);
+        assert_eq!(seen[0], QUANTUM_CYCLES + 100);
+    }
+
+    // The infrared register of a HuC1 at 0xA000, on its own: bit 0 is the LED and reads
+    // back 0xC1 while light comes in.
+    struct InfraredCartridge {
+        peer: Option<Box<dyn InfraredPeer>>,
+    }
+
+    impl ReadWrite for InfraredCartridge {
+        fn contains(&self, address: usize) -> bool {
+            address == 0xA000
+        }
+
+        fn read_byte(&self, _address: usize) -> Result<u8, std::io::Error> {
+            Ok(0xC0 | self.peer.as_ref().is_some_and(|p| p.light()) as u8)
+        }
+
+        fn read_word(&self, _address: usize) -> Result<u16, std::io::Error> {
+            unimplemented!()
+        }
+
+        fn write_byte(&mut self, _address: usize, value: u8) -> Result<(), std::io::Error> {
+            if let Some(peer) = &mut self.peer {
+                peer.set_led(value & 0x01 != 0);
+            }
+            Ok(())
+        }
+
+        fn write_word(&mut self, _address: usize, _value: u16) -> Result<(), std::io::Error> {
+            unimplemented!()
+        }
+    }
+
+    impl SaveState for InfraredCartridge {
+        fn save_state(&self, _out: &mut Vec<u8>) {}
+
+        fn load_state(&mut self, _state: &mut StateReader) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    impl Cartridge for InfraredCartridge {
+        fn step(&mut self, cycles: u32) {
+            if let Some(peer) = &mut self.peer {
+                peer.step(cycles);
+            }
+        }
+
+        fn connect_infrared(&mut self, peer: Box<dyn InfraredPeer>) -> Result<(), std::io::Error> {
+            self.peer = Some(peer);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cartridge_faces_a_console() {
+        let pairing = InfraredPairing::new();
+        assert!(pairing.port(2).is_err());
+        let mut cartridge: Box<dyn Cartridge> = Box::new(InfraredCartridge { peer: None });
+        cartridge
+            .connect_infrared(Box::new(pairing.port(0).unwrap()))
+            .unwrap();
+        let mut console = Infrared::new(true);
+        console.connect(Box::new(pairing.port(1).unwrap()));
+
+        let run = |cartridge: &mut Box<dyn Cartridge>, console: &mut Infrared| {
+            for _ in 0..2 {
+                while !pairing.lockstep.at_boundary(0) {
+                    cartridge.step(4);
+                }
+                while !pairing.lockstep.at_boundary(1) {
+                    console.step(4);
+                }
+                pairing.lockstep.exchange();
+            }
+        };
+
+        cartridge.write_byte(0xA000, 0x01).unwrap();
+        console.write_byte(0xFF56, 0xC0).unwrap();
+        run(&mut cartridge, &mut console);
+        assert_eq!(console.read_byte(0xFF56).unwrap() & 0x02, 0x00);
+
+        cartridge.write_byte(0xA000, 0x00).unwrap();
+        console.write_byte(0xFF56, 0xC1).unwrap();
+        run(&mut cartridge, &mut console);
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xC1);
+        assert_eq!(console.read_byte(0xFF56).unwrap() & 0x02, 0x02);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::input_output_registers::{Button, Buttons};
+
+const LEFT_RIGHT: u8 = (1 << Button::Left as u8) | (1 << Button::Right as u8);
+const UP_DOWN: u8 = (1 << Button::Up as u8) | (1 << Button::Down as u8);
+
+/// Buttons held for a number of frames, one after the other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macro(pub Vec<(Buttons, u32)>);
+
+impl FromStr for Macro {
+    type Err = std::io::Error;
+
+    /// Steps separated by commas, each with the buttons joined by `+` and the frames it lasts,
+    /// like `a+right:10,:5,start:1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|step| {
+                let (buttons, frames) = step.split_once(':').ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("macro step {} is not buttons:frames", step),
+                    )
+                })?;
+                let frames = frames.parse::<u32>().map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+                })?;
+                Ok((buttons.replace('+', ",").parse()?, frames))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Macro)
+    }
+}
+
+/// Sits between the host and the joypad: the buttons the host holds go through turbo,
+/// the macro playing and the opposing direction filter once a frame.
+#[derive(Default)]
+pub struct InputLayer {
+    held: Buttons,
+    // Frames each turbo button stays pressed, then released, and how long it's been held.
+    turbo: [Option<u32>; 8],
+    turbo_frames: [u32; 8],
+    macros: HashMap<String, Macro>,
+    // Steps left of the macro playing and frames left in the first one.
+    playing: Vec<(Buttons, u32)>,
+    filter_opposing: bool,
+}
+
+impl InputLayer {
+    pub fn set_held(&mut self, buttons: Buttons) {
+        self.held = buttons;
+    }
+
+    /// Makes `button` alternate between pressed and released every `frames` frames while
+    /// it's held, or turns that off with `None`.
+    pub fn set_turbo(&mut self, button: Button, frames: Option<u32>) {
+        self.turbo[button as usize] = frames.filter(|&f| f > 0);
+    }
+
+    /// Keeps Left+Right and Up+Down from being pressed together, as a real d-pad can't.
+    pub fn set_filter_opposing(&mut self, filter: bool) {
+        self.filter_opposing = filter;
+    }
+
+    pub fn add_macro(&mut self, name: &str, steps: Macro) {
+        self.macros.insert(name.to_string(), steps);
+    }
+
+    /// Starts the macro called `name` from its first frame, on top of the buttons held.
+    pub fn play_macro(&mut self, name: &str) -> Result<(), std::io::Error> {
+        let steps = self.macros.get(name).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no input macro {}", name),
+            )
+        })?;
+        self.playing = steps.0.iter().rev().copied().collect();
+        Ok(())
+    }
+
+    /// The buttons pressed in the frame about to run, the layer moves on to the next one.
+    pub fn next_frame(&mut self) -> Buttons {
+        while self.playing.last().is_some_and(|&(_, frames)| frames == 0) {
+            self.playing.pop();
+        }
+        let mut buttons = self.held.0;
+        if let Some((step, frames)) = self.playing.last_mut() {
+            buttons |= step.0;
+            *frames -= 1;
+        }
+
+        for (i, turbo) in self.turbo.iter().enumerate() {
+            if self.held.0 & (1 << i) == 0 {
+                self.turbo_frames[i] = 0;
+                continue;
+            }
+            if let Some(frames) = turbo {
+                if (self.turbo_frames[i] / frames) % 2 == 1 {
+                    buttons &= !(1 << i);
+                }
+                self.turbo_frames[i] += 1;
+            }
+        }
+
+        if self.filter_opposing {
+            for pair in [LEFT_RIGHT, UP_DOWN] {
+                if buttons & pair == pair {
+                    buttons &= !pair;
+                }
+            }
+        }
+        Buttons(buttons)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(input: &mut InputLayer, count: usize) -> Vec<u8> {
+        (0..count).map(|_| input.next_frame().0).collect()
+    }
+
+    #[test]
+    fn turbo_alternates_while_held() {
+        let mut input = InputLayer::default();
+        input.set_turbo(Button::A, Some(2));
+        input.set_held("a,up".parse().unwrap());
+        assert_eq!(frames(&mut input, 5), [0x14, 0x14, 0x04, 0x04, 0x14]);
+
+        // Pressing again starts from a pressed frame.
+        input.set_held(Buttons::default());
+        input.next_frame();
+        input.set_held("a".parse().unwrap());
+        assert_eq!(frames(&mut input, 3), [0x10, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn macros_play_once() {
+        let mut input = InputLayer::default();
+        input.add_macro("jump", "a+right:2,:1,start:1".parse().unwrap());
+        assert!(input.play_macro("dash").is_err());
+        input.play_macro("jump").unwrap();
+        input.set_held("b".parse().unwrap());
+        assert_eq!(frames(&mut input, 5), [0x31, 0x31, 0x20, 0xA0, 0x20]);
+        assert!(input.playing.is_empty());
+    }
+
+    #[test]
+    fn opposing_directions_filtered() {
+        let mut input = InputLayer::default();
+        input.set_held("left,right,up,a".parse().unwrap());
+        assert_eq!(input.next_frame(), Buttons(0x17));
+        input.set_filter_opposing(true);
+        assert_eq!(input.next_frame(), Buttons(0x14));
+        input.set_held("up,down,right".parse().unwrap());
+        assert_eq!(input.next_frame(), Buttons(0x01));
+    }
+
+    #[test]
+    fn parse_macro() {
+        assert_eq!(
+            "a+b:3,:1".parse::<Macro>().unwrap(),
+            Macro(vec![(Buttons(0x30), 3), (Buttons(0x00), 1)])
+        );
+        assert!("a".parse::<Macro>().is_err());
+        assert!("a:x".parse::<Macro>().is_err());
+    }
+}
@@ -1,6 +1,78 @@
-use crate::memory_device::ReadWrite;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::{
+    interrupt::{InterruptFlag, InterruptKind},
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
+
+/// The eight buttons of the console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
+
+/// Which buttons are held down, one bit for each `Button`: the d-pad in the lower nibble and
+/// the other buttons in the upper one, in the order of the joypad lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Buttons(pub u8);
+
+impl Buttons {
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.0 |= 1 << button as u8;
+        } else {
+            self.0 &= !(1 << button as u8);
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "right" => Ok(Button::Right),
+            "left" => Ok(Button::Left),
+            "up" => Ok(Button::Up),
+            "down" => Ok(Button::Down),
+            "a" => Ok(Button::A),
+            "b" => Ok(Button::B),
+            "select" => Ok(Button::Select),
+            "start" => Ok(Button::Start),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown button {}, use right, left, up, down, a, b, select, start.",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+impl FromStr for Buttons {
+    type Err = std::io::Error;
+
+    /// Names of the buttons held, separated by commas, like `a,start`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buttons = Buttons::default();
+        for name in s.split(',').filter(|n| !n.is_empty()) {
+            buttons.set(name.parse()?, true);
+        }
+        Ok(buttons)
+    }
+}
 
-#[derive(Default)]
 pub struct InputOutputRegisters {
     /// Mask that holds input comes from gameboy button: 0xFF00.
     /// Bit 0 - P10 Input Right or Button A (0=Pressed) (Read Only)
@@ -10,8 +82,52 @@ pub struct InputOutputRegisters {
     /// Bit 4 - P14 Select Direction Keys   (0=Select)
     /// Bit 5 - P15 Select Button Keys      (0=Select)
     /// Bit 6 and 7 unused.
-    /// TODO: protect write on read-only register.
-    buttons: u8,
+    /// Only the select bits are stored, the input lines come from `pressed`.
+    select: u8,
+    pressed: Buttons,
+
+    interrupt_flag: Rc<RefCell<InterruptFlag>>,
+}
+
+impl InputOutputRegisters {
+    pub fn new(interrupt_flag: Rc<RefCell<InterruptFlag>>) -> InputOutputRegisters {
+        InputOutputRegisters {
+            select: 0x30,
+            pressed: Buttons::default(),
+            interrupt_flag,
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.pressed
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.update(|io| io.pressed = buttons);
+    }
+
+    // Input lines P10-P13, pulled low by the buttons pressed on the selected rows.
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & 0x10 == 0 {
+            low |= self.pressed.0 & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            low |= self.pressed.0 >> 4;
+        }
+        !low & 0x0F
+    }
+
+    // The joypad interrupt is requested when an input line goes from high to low.
+    fn update(&mut self, change: impl FnOnce(&mut Self)) {
+        let before = self.lines();
+        change(self);
+        if before & !self.lines() != 0 {
+            self.interrupt_flag
+                .borrow_mut()
+                .request(InterruptKind::Joypad);
+        }
+    }
 }
 
 impl ReadWrite for InputOutputRegisters {
@@ -21,7 +137,7 @@ impl ReadWrite for InputOutputRegisters {
 
     fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
         match address {
-            0xFF00 => Ok(self.buttons),
+            0xFF00 => Ok(0xC0 | self.select | self.lines()),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "can't read byte here",
@@ -36,7 +152,7 @@ impl ReadWrite for InputOutputRegisters {
     fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
         match address {
             0xFF00 => {
-                self.buttons = value;
+                self.update(|io| io.select = value & 0x30);
                 Ok(())
             }
             _ => Err(std::io::Error::new(
@@ -50,3 +166,75 @@ impl ReadWrite for InputOutputRegisters {
         todo!("implement this func")
     }
 }
+
+impl SaveState for InputOutputRegisters {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.select, self.pressed.0]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.select = state.u8()?;
+        self.pressed = Buttons(state.u8()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joypad() -> (InputOutputRegisters, Rc<RefCell<InterruptFlag>>) {
+        let interrupt_flag = Rc::new(RefCell::new(InterruptFlag::default()));
+        (
+            InputOutputRegisters::new(interrupt_flag.clone()),
+            interrupt_flag,
+        )
+    }
+
+    #[test]
+    fn rows_are_selected() {
+        let (mut joypad, _) = joypad();
+        joypad.set_buttons("left,start".parse().unwrap());
+
+        assert_eq!(joypad.read_byte(0xFF00).unwrap(), 0xFF);
+        joypad.write_byte(0xFF00, 0x20).unwrap();
+        assert_eq!(joypad.read_byte(0xFF00).unwrap(), 0xED);
+        joypad.write_byte(0xFF00, 0x10).unwrap();
+        assert_eq!(joypad.read_byte(0xFF00).unwrap(), 0xD7);
+        joypad.write_byte(0xFF00, 0x00).unwrap();
+        assert_eq!(joypad.read_byte(0xFF00).unwrap(), 0xC5);
+        // The lower bits are read only.
+        joypad.write_byte(0xFF00, 0xFF).unwrap();
+        assert_eq!(joypad.read_byte(0xFF00).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn interrupt_on_high_to_low() {
+        let (mut joypad, interrupt_flag) = joypad();
+        let mut buttons = Buttons::default();
+        buttons.set(Button::A, true);
+        // Buttons row not selected.
+        joypad.write_byte(0xFF00, 0x20).unwrap();
+        joypad.set_buttons(buttons);
+        assert_eq!(interrupt_flag.borrow().data, 0x00);
+
+        // Selecting it pulls P10 low.
+        joypad.write_byte(0xFF00, 0x10).unwrap();
+        assert_eq!(interrupt_flag.borrow().data, 0x10);
+
+        interrupt_flag.borrow_mut().data = 0;
+        joypad.set_buttons(Buttons::default());
+        assert_eq!(interrupt_flag.borrow().data, 0x00);
+        buttons.set(Button::B, true);
+        joypad.set_buttons(buttons);
+        assert_eq!(interrupt_flag.borrow().data, 0x10);
+        assert_eq!(joypad.pressed, Buttons(0x30));
+    }
+
+    #[test]
+    fn parse_buttons() {
+        assert_eq!("A,select,Up".parse::<Buttons>().unwrap(), Buttons(0x54));
+        assert_eq!("".parse::<Buttons>().unwrap(), Buttons(0x00));
+        assert!("a,turbo".parse::<Buttons>().is_err());
+    }
+}
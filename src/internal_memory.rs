@@ -1,4 +1,11 @@
-use crate::memory_device::ReadWrite;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{
+    interrupt::InterruptFlag,
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
 
 /// InternalMemory holds all memory banks for internal handling of the emulating job, not GPU or
 /// cartridge related, just internal stuff to read and write during execution.
@@ -14,18 +21,19 @@ pub struct InternalMemory {
     // Bit 2: Timer    Interrupt Request (INT 50h)  (1=Request)
     // Bit 3: Serial   Interrupt Request (INT 58h)  (1=Request)
     // Bit 4: Joypad   Interrupt Request (INT 60h)  (1=Request)
-    interrupt_flag: u8,
+    // Shared with the devices requesting interrupts.
+    interrupt_flag: Rc<RefCell<InterruptFlag>>,
     // interrupt flag enable: 0xFFFF
     interrupt_enable: u8,
 }
 
 impl InternalMemory {
-    pub fn new() -> InternalMemory {
+    pub fn new(interrupt_flag: Rc<RefCell<InterruptFlag>>) -> InternalMemory {
         InternalMemory {
             wram: [0; 0x8000],
             wram_bank: 1,
             hram: [0; 0x007F],
-            interrupt_flag: 0,
+            interrupt_flag,
             interrupt_enable: 0,
         }
     }
@@ -51,7 +59,8 @@ impl ReadWrite for InternalMemory {
             0xF000..=0xFDFF => Ok(self.wram[address - 0xF000 + 0x1000 * self.wram_bank as usize]),
             0xFF80..=0xFFFE => Ok(self.hram[address - 0xFF80]),
             0xFF70 => Ok(self.wram_bank),
-            0xFF0F => Ok(self.interrupt_flag),
+            // The upper 3 bits are unused and read as 1.
+            0xFF0F => Ok(self.interrupt_flag.borrow().data | 0xE0),
             0xFFFF => Ok(self.interrupt_enable),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -83,7 +92,7 @@ impl ReadWrite for InternalMemory {
                     n => n,
                 }
             }
-            0xFF0F => self.interrupt_flag = value,
+            0xFF0F => self.interrupt_flag.borrow_mut().data = value & 0x1F,
             0xFFFF => self.interrupt_enable = value,
             _ => {
                 return Err(std::io::Error::new(
@@ -101,3 +110,23 @@ impl ReadWrite for InternalMemory {
         Ok(())
     }
 }
+
+impl SaveState for InternalMemory {
+    // The interrupt flag is shared with the devices, it's saved once here.
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.wram);
+        out.push(self.wram_bank);
+        out.extend_from_slice(&self.hram);
+        out.push(self.interrupt_flag.borrow().data);
+        out.push(self.interrupt_enable);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        state.fill(&mut self.wram)?;
+        self.wram_bank = state.u8()?;
+        state.fill(&mut self.hram)?;
+        self.interrupt_flag.borrow_mut().data = state.u8()?;
+        self.interrupt_enable = state.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::save_state::{SaveState, StateReader};
+
+/// Length counter shared by all the sound channels.
+/// When enabled it is clocked at 256 Hz by the frame sequencer and turns the channel off
+/// once it reaches zero. It counts 64 steps, 256 for the wave channel.
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Length load from NRx1, the counter runs for `max - value` steps.
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - u16::from(value);
+    }
+
+    /// Returns true when the counter expires and the channel has to be disabled.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    /// Handles the length enable and trigger bits of NRx4, returning true when the channel
+    /// has to be disabled. `length_step_next` tells if the next frame sequencer step clocks
+    /// the length counters: when it doesn't, enabling the counter clocks it once more, and
+    /// so does a trigger reloading an expired counter with length enabled.
+    pub fn write_control(&mut self, enable: bool, trigger: bool, length_step_next: bool) -> bool {
+        let extra_clock = !length_step_next;
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut disable = false;
+        if extra_clock && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && extra_clock {
+                self.counter -= 1;
+            }
+        }
+
+        disable
+    }
+}
+
+impl SaveState for LengthCounter {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.counter.to_le_bytes());
+        out.push(self.enabled as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.counter = state.u16()?;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_after_loaded_steps() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        length.write_control(true, true, true);
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn disabled_counter_does_not_clock() {
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn enabling_on_odd_step_clocks_once() {
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(length.write_control(true, false, false));
+    }
+
+    #[test]
+    fn trigger_reloads_expired_counter() {
+        let mut length = LengthCounter::new(256);
+        assert!(!length.write_control(true, true, false));
+        assert_eq!(length.counter, 255);
+        let mut length = LengthCounter::new(256);
+        length.write_control(false, true, true);
+        assert_eq!(length.counter, 256);
+    }
+}
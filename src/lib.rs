@@ -0,0 +1,96 @@
+//! GameBoy emulator, to embed in other programs.
+//!
+//! An `Emulator` is started from a rom with `EmulatorBuilder`, then driven a frame at a
+//! time: set the buttons held, run the frame, take the picture and the sound it made.
+//!
+//! ```no_run
+//! use yobemag::{Button, Buttons, EmulatorBuilder, Model};
+//!
+//! let mut emu = EmulatorBuilder::new("tetris.gb")
+//!     .model(Model::Cgb)
+//!     .sample_rate(48_000)
+//!     .build()?;
+//!
+//! let mut buttons = Buttons::default();
+//! buttons.set(Button::Start, true);
+//! emu.set_buttons(buttons);
+//! for _ in 0..60 {
+//!     emu.run_frame();
+//!     let rgb = emu.framebuffer();
+//!     let samples = emu.drain_audio();
+//!     assert_eq!(rgb.len(), yobemag::SCREEN_WIDTH * yobemag::SCREEN_HEIGHT * 3);
+//!     # let _ = samples;
+//! }
+//! emu.reset()?;
+//! // Writes the `.sav` file, and says if it can't.
+//! emu.close()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! `HeadlessRun` runs one with nobody watching, until a limit or a condition is met.
+//!
+//! Emulators aren't `Send`: each one belongs to the thread that built it.
+
+mod audio_recorder;
+mod background_palette_index;
+mod blip_buffer;
+mod boot_rom;
+mod cartridge;
+mod cartridge_header;
+mod cpu;
+mod dmg_palette;
+mod emulator;
+mod four_player_adapter;
+mod gbs;
+mod gpu;
+mod hdma;
+mod headless;
+mod hle_boot;
+mod infrared;
+mod input;
+mod input_output_registers;
+mod internal_memory;
+mod interrupt;
+mod length_counter;
+mod link_cable;
+mod lockstep;
+mod memory_device;
+mod mmu;
+mod model;
+mod movie;
+mod noise_channel;
+mod opcodes;
+mod png_writer;
+mod prefix_opcodes;
+mod printer;
+mod pulse_channel;
+mod register;
+mod rtc;
+mod save_state;
+mod serial_data_transfer;
+mod sound;
+#[cfg(test)]
+mod test_rom;
+mod timer;
+mod vgm_logger;
+mod volume_envelope;
+mod wav_writer;
+mod wave_channel;
+
+pub use crate::{
+    dmg_palette::{DmgPalettes, ManualPalette},
+    emulator::{Boot, Emulator, EmulatorBuilder},
+    four_player_adapter::{AdapterPort, FourPlayerAdapter},
+    gbs::{GbsHeader, GbsPlayer},
+    gpu::{SCREEN_H as SCREEN_HEIGHT, SCREEN_W as SCREEN_WIDTH},
+    headless::{HeadlessReport, HeadlessRun, StopCondition, StopReason},
+    infrared::{AmbientLight, InfraredPairing, InfraredPeer, InfraredPort},
+    input::Macro,
+    input_output_registers::{Button, Buttons},
+    link_cable::{LinkCable, LinkPort, SocketLink},
+    model::Model,
+    movie::{Movie, MovieFrame, MovieHeader, MovieRecorder},
+    png_writer::{crc32, write_png},
+    printer::Printer,
+    serial_data_transfer::SerialPeer,
+};
@@ -0,0 +1,449 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::{
+    emulator::Emulator,
+    lockstep::{Lockstep, LockstepEnd, Pulses, Side},
+    serial_data_transfer::SerialPeer,
+};
+
+// What an end tells the other one at the end of a quantum: whether it waits for the external
+// clock, with SB and the bits left, and the pulses it sent as (cycle in the quantum, bit).
+#[derive(Debug, Default, PartialEq)]
+struct LinkMessage {
+    listening: Option<(u8, u8)>,
+    pulses: Vec<(u32, bool)>,
+}
+
+impl LinkMessage {
+    fn write_to(&self, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        let (listening, data, bits_left) = match self.listening {
+            Some((data, bits_left)) => (1, data, bits_left),
+            None => (0, 0, 0),
+        };
+        let mut bytes = vec![listening, data, bits_left];
+        bytes.extend_from_slice(&(self.pulses.len() as u16).to_le_bytes());
+        for (cycle, bit) in &self.pulses {
+            bytes.extend_from_slice(&cycle.to_le_bytes());
+            bytes.push(*bit as u8);
+        }
+        out.write_all(&bytes)?;
+        out.flush()
+    }
+
+    fn read_from(input: &mut dyn Read) -> Result<LinkMessage, std::io::Error> {
+        let mut head = [0u8; 5];
+        input.read_exact(&mut head)?;
+        let listening = match head[0] {
+            0 => None,
+            _ => Some((head[1], head[2])),
+        };
+        let count = u16::from_le_bytes([head[3], head[4]]);
+        let mut pulses = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut pulse = [0u8; 5];
+            input.read_exact(&mut pulse)?;
+            let cycle = u32::from_le_bytes([pulse[0], pulse[1], pulse[2], pulse[3]]);
+            pulses.push((cycle, pulse[4] != 0));
+        }
+        Ok(LinkMessage { listening, pulses })
+    }
+}
+
+// SB of a side waiting for the external clock, after one more bit comes in.
+fn shift_in(listening: Option<(u8, u8)>, bit: bool) -> Option<(u8, u8)> {
+    match listening {
+        Some((data, bits_left)) if bits_left > 1 => Some(((data << 1) | bit as u8, bits_left - 1)),
+        _ => None,
+    }
+}
+
+// The state one end keeps, whatever carries the messages.
+//
+// The side driving the clock doesn't wait for the other one to get its bits: it reads what
+// the other side would send from a copy of its SB, as it was at the end of the last quantum
+// and shifted by the bits sent since. The pulses reach the other side one quantum later, at
+// the same cycle in the quantum, so both sides see the same bits whatever the transport.
+#[derive(Default)]
+struct LinkEnd {
+    pulses: Pulses,
+    listening: Option<(u8, u8)>,
+    remote: Option<(u8, u8)>,
+}
+
+impl LinkEnd {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        match self.remote {
+            Some((data, _)) => {
+                self.remote = shift_in(self.remote, bit);
+                self.pulses.send(bit);
+                data & 0x80 != 0
+            }
+            // Nobody listens on the other side, the line stays high.
+            None => true,
+        }
+    }
+
+    fn message(&self) -> LinkMessage {
+        LinkMessage {
+            listening: self.listening,
+            pulses: self.pulses.sent().to_vec(),
+        }
+    }
+
+    fn receive(&mut self, message: LinkMessage) {
+        // The other side didn't get the pulses sent in this quantum yet.
+        self.remote = self
+            .pulses
+            .take_sent()
+            .into_iter()
+            .fold(message.listening, |remote, (_, bit)| shift_in(remote, bit));
+        self.pulses.next_quantum(message.pulses);
+    }
+}
+
+impl LockstepEnd for LinkEnd {
+    fn at_boundary(&self) -> bool {
+        self.pulses.at_boundary()
+    }
+
+    fn exchange(ends: &mut [LinkEnd; 2]) {
+        let [first, second] = [ends[0].message(), ends[1].message()];
+        ends[0].receive(second);
+        ends[1].receive(first);
+    }
+}
+
+/// A cable between two emulators in the same process, stepped in lockstep by `run`.
+pub struct LinkCable {
+    lockstep: Lockstep<LinkEnd>,
+}
+
+/// One of the two plugs of a `LinkCable`.
+pub struct LinkPort {
+    side: Side<LinkEnd>,
+}
+
+impl SerialPeer for LinkPort {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        self.side.end_mut().exchange_bit(bit)
+    }
+
+    fn clocked_bit(&mut self, _bit: bool) -> Option<bool> {
+        self.side.end_mut().pulses.receive()
+    }
+
+    fn step(&mut self, cycles: u32) {
+        self.side.end_mut().pulses.step(cycles);
+    }
+
+    fn set_listening(&mut self, listening: Option<(u8, u8)>) {
+        self.side.end_mut().listening = listening;
+    }
+}
+
+impl Default for LinkCable {
+    fn default() -> Self {
+        LinkCable::new()
+    }
+}
+
+impl LinkCable {
+    pub fn new() -> LinkCable {
+        LinkCable {
+            lockstep: Lockstep::new("a cable"),
+        }
+    }
+
+    /// The plug for `side`, 0 or 1, to connect to the serial port of an emulator.
+    pub fn port(&self, side: usize) -> Result<LinkPort, std::io::Error> {
+        Ok(LinkPort {
+            side: self.lockstep.side(side)?,
+        })
+    }
+
+    /// Runs `a`, plugged to side 0, and `b`, plugged to side 1, for `quanta` quanta.
+    pub fn run(&self, a: &mut Emulator, b: &mut Emulator, quanta: u32) {
+        self.lockstep.run(a, b, quanta);
+    }
+}
+
+/// A cable to an emulator in another process, over a TCP or Unix socket. Each end blocks at
+/// the end of every quantum until the other one gets there.
+pub struct SocketLink {
+    end: LinkEnd,
+    reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+    // Once the other end goes away the cable acts as unplugged.
+    broken: bool,
+    on_unplug: Option<Box<dyn FnMut(std::io::Error)>>,
+}
+
+impl SocketLink {
+    fn new(reader: Box<dyn Read>, writer: Box<dyn Write>) -> SocketLink {
+        SocketLink {
+            end: LinkEnd::default(),
+            reader,
+            writer,
+            broken: false,
+            on_unplug: None,
+        }
+    }
+
+    /// Calls `on_unplug` with the error that broke the connection, the cable is unplugged
+    /// from then on. Without it the other console just seems to go away.
+    pub fn on_unplug(mut self, on_unplug: impl FnMut(std::io::Error) + 'static) -> SocketLink {
+        self.on_unplug = Some(Box::new(on_unplug));
+        self
+    }
+
+    fn tcp(stream: TcpStream) -> Result<SocketLink, std::io::Error> {
+        // Messages are small and each one waits for an answer.
+        stream.set_nodelay(true)?;
+        Ok(SocketLink::new(
+            Box::new(stream.try_clone()?),
+            Box::new(stream),
+        ))
+    }
+
+    #[cfg(unix)]
+    fn unix(stream: UnixStream) -> Result<SocketLink, std::io::Error> {
+        Ok(SocketLink::new(
+            Box::new(stream.try_clone()?),
+            Box::new(stream),
+        ))
+    }
+
+    /// Waits for the other end to connect to `address`, `tcp:host:port` or `unix:path`.
+    pub fn listen(address: &str) -> Result<SocketLink, std::io::Error> {
+        match parse_address(address)? {
+            Address::Tcp(address) => SocketLink::tcp(TcpListener::bind(address)?.accept()?.0),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                // Left behind by an earlier run.
+                let _ = std::fs::remove_file(path);
+                SocketLink::unix(UnixListener::bind(path)?.accept()?.0)
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    /// Connects to the other end listening on `address`, `tcp:host:port` or `unix:path`.
+    pub fn connect(address: &str) -> Result<SocketLink, std::io::Error> {
+        match parse_address(address)? {
+            Address::Tcp(address) => SocketLink::tcp(TcpStream::connect(address)?),
+            #[cfg(unix)]
+            Address::Unix(path) => SocketLink::unix(UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    fn exchange(&mut self) -> Result<LinkMessage, std::io::Error> {
+        self.end.message().write_to(&mut *self.writer)?;
+        LinkMessage::read_from(&mut *self.reader)
+    }
+}
+
+impl SerialPeer for SocketLink {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        self.end.exchange_bit(bit)
+    }
+
+    fn clocked_bit(&mut self, _bit: bool) -> Option<bool> {
+        self.end.pulses.receive()
+    }
+
+    fn step(&mut self, cycles: u32) {
+        // The quantum is over once the last step got past it and the listening state at its
+        // end is known.
+        if self.end.pulses.at_boundary() {
+            let message = if self.broken {
+                Ok(LinkMessage::default())
+            } else {
+                self.exchange()
+            };
+            let message = message.unwrap_or_else(|e| {
+                self.broken = true;
+                if let Some(on_unplug) = &mut self.on_unplug {
+                    on_unplug(e);
+                }
+                LinkMessage::default()
+            });
+            self.end.receive(message);
+        }
+        self.end.pulses.step(cycles);
+    }
+
+    fn set_listening(&mut self, listening: Option<(u8, u8)>) {
+        self.end.listening = listening;
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "unix sockets are not available on this platform",
+    )
+}
+
+enum Address<'a> {
+    Tcp(&'a str),
+    Unix(&'a str),
+}
+
+fn parse_address(address: &str) -> Result<Address<'_>, std::io::Error> {
+    if let Some(address) = address.strip_prefix("tcp:") {
+        Ok(Address::Tcp(address))
+    } else if let Some(path) = address.strip_prefix("unix:") {
+        Ok(Address::Unix(path))
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("link address {} is not tcp:host:port or unix:path", address),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::interrupt::InterruptFlag;
+    use crate::lockstep::QUANTUM_CYCLES;
+    use crate::memory_device::ReadWrite;
+    use crate::serial_data_transfer::SerialDataTransfer;
+
+    fn serial() -> SerialDataTransfer {
+        SerialDataTransfer::new(Rc::new(RefCell::new(InterruptFlag::default())), true)
+    }
+
+    fn run(cable: &LinkCable, sides: &mut [SerialDataTransfer; 2], quanta: u32) {
+        for _ in 0..quanta {
+            for (side, serial) in sides.iter_mut().enumerate() {
+                while !cable.lockstep.at_boundary(side) {
+                    serial.step(4);
+                }
+            }
+            cable.lockstep.exchange();
+        }
+    }
+
+    fn received(sides: &[SerialDataTransfer; 2]) -> [u8; 2] {
+        [
+            sides[0].read_byte(0xFF01).unwrap(),
+            sides[1].read_byte(0xFF01).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn in_process_transfer() {
+        for control in [0x81, 0x83] {
+            let cable = LinkCable::new();
+            let mut sides = [serial(), serial()];
+            sides[0].connect(Box::new(cable.port(0).unwrap()));
+            sides[1].connect(Box::new(cable.port(1).unwrap()));
+
+            // The side driving the clock only learns the other one listens at the end of
+            // a quantum.
+            sides[1].write_byte(0xFF01, 0xA5).unwrap();
+            sides[1].write_byte(0xFF02, 0x80).unwrap();
+            run(&cable, &mut sides, 1);
+            sides[0].write_byte(0xFF01, 0x3C).unwrap();
+            sides[0].write_byte(0xFF02, control).unwrap();
+            run(&cable, &mut sides, 12);
+            assert_eq!(received(&sides), [0xA5, 0x3C]);
+            assert_eq!(sides[0].read_byte(0xFF02).unwrap() & 0x80, 0x00);
+            assert_eq!(sides[1].read_byte(0xFF02).unwrap() & 0x80, 0x00);
+        }
+    }
+
+    #[test]
+    fn nobody_listening_reads_ones() {
+        let cable = LinkCable::new();
+        let mut sides = [serial(), serial()];
+        sides[0].connect(Box::new(cable.port(0).unwrap()));
+        sides[1].connect(Box::new(cable.port(1).unwrap()));
+
+        sides[0].write_byte(0xFF01, 0x3C).unwrap();
+        sides[0].write_byte(0xFF02, 0x81).unwrap();
+        run(&cable, &mut sides, 10);
+        assert_eq!(received(&sides), [0xFF, 0x00]);
+    }
+
+    #[test]
+    fn only_two_sides() {
+        assert!(LinkCable::new().port(1).is_ok());
+        assert!(LinkCable::new().port(2).is_err());
+    }
+
+    #[test]
+    fn unplug_is_reported() {
+        let unplugged = Rc::new(RefCell::new(None));
+        let report = unplugged.clone();
+        let link = SocketLink::new(Box::new(std::io::empty()), Box::new(std::io::sink()))
+            .on_unplug(move |e| *report.borrow_mut() = Some(e.kind()));
+        let mut serial = serial();
+        serial.connect(Box::new(link));
+        serial.write_byte(0xFF01, 0x3C).unwrap();
+        serial.write_byte(0xFF02, 0x81).unwrap();
+        for _ in 0..QUANTUM_CYCLES * 10 / 4 {
+            serial.step(4);
+        }
+        assert_eq!(*unplugged.borrow(), Some(std::io::ErrorKind::UnexpectedEof));
+        assert_eq!(serial.read_byte(0xFF01).unwrap(), 0xFF);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_matches_in_process() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let other = std::thread::spawn(move || {
+            let mut serial = serial();
+            serial.connect(Box::new(SocketLink::unix(right).unwrap()));
+            serial.write_byte(0xFF01, 0xA5).unwrap();
+            serial.write_byte(0xFF02, 0x80).unwrap();
+            for _ in 0..QUANTUM_CYCLES * 13 / 4 {
+                serial.step(4);
+            }
+            serial.read_byte(0xFF01).unwrap()
+        });
+
+        let mut serial = serial();
+        serial.connect(Box::new(SocketLink::unix(left).unwrap()));
+        for _ in 0..QUANTUM_CYCLES / 4 {
+            serial.step(4);
+        }
+        serial.write_byte(0xFF01, 0x3C).unwrap();
+        serial.write_byte(0xFF02, 0x81).unwrap();
+        for _ in 0..QUANTUM_CYCLES * 12 / 4 {
+            serial.step(4);
+        }
+        let received = serial.read_byte(0xFF01).unwrap();
+        // Unblocks the other end if it waits on one more quantum.
+        drop(serial);
+
+        assert_eq!(received, 0xA5);
+        assert_eq!(other.join().unwrap(), 0x3C);
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let message = LinkMessage {
+            listening: Some((0x12, 5)),
+            pulses: vec![(0, true), (511, false)],
+        };
+        let mut bytes = vec![];
+        message.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            LinkMessage::read_from(&mut bytes.as_slice()).unwrap(),
+            message
+        );
+    }
+}
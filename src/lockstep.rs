@@ -0,0 +1,161 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::emulator::Emulator;
+
+/// Both sides of a connection run this many cycles, then swap what happened in the meantime.
+/// Shorter than a bit with the normal serial clock, long enough to keep a socket from being
+/// the bottleneck.
+pub const QUANTUM_CYCLES: u32 = 512;
+
+/// The levels one side of a connection sends and receives, stamped with the cycle in the
+/// quantum they changed at. Each pulse reaches the other side a quantum late, at the same
+/// cycle in the quantum, so both sides see the same timing whatever carries them.
+#[derive(Default)]
+pub struct Pulses {
+    // Cycles into the current quantum.
+    now: u32,
+    incoming: VecDeque<(u32, bool)>,
+    outgoing: Vec<(u32, bool)>,
+}
+
+impl Pulses {
+    pub fn step(&mut self, cycles: u32) {
+        self.now += cycles;
+    }
+
+    /// True once the current quantum is over.
+    pub fn at_boundary(&self) -> bool {
+        self.now >= QUANTUM_CYCLES
+    }
+
+    pub fn send(&mut self, level: bool) {
+        self.outgoing.push((self.now, level));
+    }
+
+    /// What was sent since the start of the quantum.
+    pub fn sent(&self) -> &[(u32, bool)] {
+        &self.outgoing
+    }
+
+    pub fn take_sent(&mut self) -> Vec<(u32, bool)> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// The next level received, once its time has come.
+    pub fn receive(&mut self) -> Option<bool> {
+        match self.incoming.front() {
+            Some(&(cycle, level)) if cycle <= self.now => {
+                self.incoming.pop_front();
+                Some(level)
+            }
+            _ => None,
+        }
+    }
+
+    /// Starts the next quantum with the pulses the other side sent in the last one, returns
+    /// the ones received before that and never read.
+    pub fn next_quantum(&mut self, received: Vec<(u32, bool)>) -> Vec<(u32, bool)> {
+        self.now = self.now.saturating_sub(QUANTUM_CYCLES);
+        std::mem::replace(&mut self.incoming, received.into()).into()
+    }
+}
+
+/// The state of one side of a connection between two emulators in the same process.
+pub trait LockstepEnd: Default {
+    fn at_boundary(&self) -> bool;
+
+    /// Hands each side what the other one did in the quantum that just ended.
+    fn exchange(ends: &mut [Self; 2]);
+}
+
+/// Two emulators connected in the same process, stepped in lockstep by `run`.
+pub struct Lockstep<E> {
+    ends: Rc<RefCell<[E; 2]>>,
+    // What the connection is called in errors, like "a cable".
+    name: &'static str,
+}
+
+impl<E: LockstepEnd> Lockstep<E> {
+    pub fn new(name: &'static str) -> Lockstep<E> {
+        Lockstep {
+            ends: Rc::new(RefCell::new(Default::default())),
+            name,
+        }
+    }
+
+    /// Side `index`, 0 or 1.
+    pub fn side(&self, index: usize) -> Result<Side<E>, std::io::Error> {
+        if index >= 2 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} has two sides, not {}", self.name, index + 1),
+            ));
+        }
+        Ok(Side {
+            ends: self.ends.clone(),
+            index,
+        })
+    }
+
+    pub fn at_boundary(&self, side: usize) -> bool {
+        self.ends.borrow()[side].at_boundary()
+    }
+
+    pub fn exchange(&self) {
+        E::exchange(&mut self.ends.borrow_mut());
+    }
+
+    /// Runs `a`, on side 0, and `b`, on side 1, for `quanta` quanta.
+    pub fn run(&self, a: &mut Emulator, b: &mut Emulator, quanta: u32) {
+        for _ in 0..quanta {
+            for (side, emu) in [(0, &mut *a), (1, &mut *b)] {
+                emu.step_until(|| self.at_boundary(side));
+            }
+            self.exchange();
+        }
+    }
+}
+
+/// One side of a `Lockstep`, for the emulator plugged there.
+pub struct Side<E> {
+    ends: Rc<RefCell<[E; 2]>>,
+    index: usize,
+}
+
+impl<E> Side<E> {
+    pub fn end(&self) -> Ref<'_, E> {
+        Ref::map(self.ends.borrow(), |ends| &ends[self.index])
+    }
+
+    pub fn end_mut(&self) -> RefMut<'_, E> {
+        RefMut::map(self.ends.borrow_mut(), |ends| &mut ends[self.index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulses_arrive_a_quantum_late() {
+        let mut pulses = Pulses::default();
+        pulses.step(100);
+        pulses.send(true);
+        pulses.step(QUANTUM_CYCLES);
+        assert!(pulses.at_boundary());
+        assert_eq!(pulses.take_sent(), [(100, true)]);
+
+        assert!(pulses
+            .next_quantum(vec![(50, true), (60, false)])
+            .is_empty());
+        // 100 cycles into the new quantum, the first one is due.
+        assert_eq!(pulses.receive(), Some(true));
+        assert_eq!(pulses.receive(), Some(false));
+        assert_eq!(pulses.receive(), None);
+
+        assert_eq!(pulses.next_quantum(vec![(600, true)]), []);
+        assert_eq!(pulses.next_quantum(vec![]), [(600, true)]);
+    }
+}
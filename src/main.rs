@@ -1,29 +1,211 @@
 use std::env;
+use std::io::Write;
 
-mod background_palette_index;
-mod cartridge;
-mod cartridge_header;
-mod clock;
-mod cpu;
-mod emulator;
-mod gpu;
-mod hdma;
-mod input_output_registers;
-mod internal_memory;
-mod interrupt;
-mod memory_device;
-mod mmu;
-mod opcodes;
-mod prefix_opcodes;
-mod register;
-mod serial_data_transfer;
-mod sound;
-mod timer;
+use yobemag::{
+    crc32, write_png, AmbientLight, Boot, DmgPalettes, Emulator, EmulatorBuilder,
+    FourPlayerAdapter, GbsPlayer, HeadlessRun, InfraredPairing, LinkCable, ManualPalette, Model,
+    Movie, MovieFrame, MovieHeader, MovieRecorder, Printer, SocketLink, StopCondition,
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+// Value following `name` in the command line, like `--model cgb`.
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|v| v.as_str())
+}
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+fn report_unplug(e: std::io::Error) {
+    eprintln!("link cable unplugged: {}", e);
+}
+
+// What the emulator kept for the caller since the last call.
+fn report_status(emu: &mut Emulator) {
+    match emu.take_vgm_log_done() {
+        Some(Ok(path)) => println!("VGM log written to {}", path.display()),
+        Some(Err(e)) => eprintln!("can't write VGM log: {}", e),
+        None => {}
+    }
+    if let Some(e) = emu.take_hle_boot_error() {
+        eprintln!("boot sequence cut short: {}", e);
+    }
+}
+
+// yobemag gbs file.gbs --track N --seconds S -o out.wav
+fn play_gbs(args: &[String]) -> Result<(), std::io::Error> {
+    let file = args
+        .get(2)
+        .ok_or_else(|| invalid_input("missing gbs file.".to_string()))?;
+    let output =
+        flag_value(args, "-o").ok_or_else(|| invalid_input("missing -o out.wav.".into()))?;
+    let mut player = GbsPlayer::load(file.as_ref())?;
+
+    let header = player.header();
+    println!(
+        "{} - {} ({})",
+        header.title, header.author, header.copyright
+    );
+    let track = match flag_value(args, "--track") {
+        Some(t) => t
+            .parse::<u8>()
+            .map_err(|e| invalid_input(format!("--track: {}", e)))?,
+        None => header.first_song,
+    };
+    let seconds = match flag_value(args, "--seconds") {
+        Some(s) => s
+            .parse::<u32>()
+            .map_err(|e| invalid_input(format!("--seconds: {}", e)))?,
+        None => 60,
+    };
+    if !(1..=header.song_count).contains(&track) {
+        return Err(invalid_input(format!(
+            "--track must be 1-{}.",
+            header.song_count
+        )));
+    }
+
+    println!("track {} of {}, {} s", track, header.song_count, seconds);
+    player.record(track - 1, seconds, output.as_ref())
+}
+
+// yobemag rom.gb --play movie.ymv
+fn play_movie(rom: &str, path: &str) -> Result<(), std::io::Error> {
+    let movie = Movie::load(path.as_ref())?;
+    let header = &movie.header;
+    if crc32(&std::fs::read(rom)?) != header.rom_crc32 {
+        return Err(invalid_input(format!(
+            "{} was recorded with another rom, CRC-32 {:08X}.",
+            path, header.rom_crc32
+        )));
+    }
+
+    let mut emu = EmulatorBuilder::new(rom)
+        .model(header.model)
+        .boot(header.boot.clone())
+        .saving(false)
+        .build()?;
+    if let Some(data) = &header.save_data {
+        emu.load_save_data(data)?;
+    }
+    if let Some(state) = &header.state {
+        emu.load_state(state)?;
+    }
+    for (i, frame) in movie.frames.iter().enumerate() {
+        emu.set_buttons(frame.buttons);
+        emu.run_frame();
+        let checksum = emu.state_checksum();
+        match frame.checksum {
+            Some(expected) if expected != checksum => {
+                return Err(std::io::Error::other(format!(
+                    "desync at frame {}: state checksum {:08X} instead of {:08X}.",
+                    i, checksum, expected
+                )));
+            }
+            _ => (),
+        }
+    }
+
+    println!("movie played, {} frames", movie.frames.len());
+    Ok(())
+}
+
+// Decimal, or hexadecimal with 0x in front, like addresses are usually written.
+fn parse_number(name: &str, value: &str) -> Result<u64, std::io::Error> {
+    let number = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    number.map_err(|e| invalid_input(format!("{}: {} {}", name, value, e)))
+}
+
+fn parse_address(name: &str, value: &str) -> Result<u16, std::io::Error> {
+    u16::try_from(parse_number(name, value)?)
+        .map_err(|_| invalid_input(format!("{}: {} is not an address.", name, value)))
+}
+
+// yobemag run rom.gb --headless [--frames N] [--cycles M] [--until-pc 0x0150]
+//     [--until-serial Passed] [--until-memory 0xC000=0x42]
+//     [--screenshot out.png] [--dump-ram ram.bin] [--dump-registers registers.json]
+//     [--save-state start.state]
+// Exits with the code of the stop reason, see `HeadlessReport::exit_code`.
+fn run_headless(args: &[String], mut emu: Emulator) -> Result<i32, std::io::Error> {
+    let mut run = HeadlessRun::new().echo_serial(args.iter().any(|a| a == "--serial-stdout"));
+    if let Some(frames) = flag_value(args, "--frames") {
+        run = run.frames(parse_number("--frames", frames)?);
+    }
+    if let Some(cycles) = flag_value(args, "--cycles") {
+        run = run.cycles(parse_number("--cycles", cycles)?);
+    }
+    if let Some(pc) = flag_value(args, "--until-pc") {
+        run = run.until(StopCondition::Pc(parse_address("--until-pc", pc)?));
+    }
+    if let Some(text) = flag_value(args, "--until-serial") {
+        run = run.until(StopCondition::Serial(text.to_string()));
+    }
+    if let Some(condition) = flag_value(args, "--until-memory") {
+        let (address, value) = condition
+            .split_once('=')
+            .ok_or_else(|| invalid_input("--until-memory takes address=value.".to_string()))?;
+        let value = parse_number("--until-memory", value)?;
+        run = run.until(StopCondition::Memory {
+            address: parse_address("--until-memory", address)?,
+            value: u8::try_from(value)
+                .map_err(|_| invalid_input(format!("--until-memory: {} is not a byte.", value)))?,
+        });
+    }
+    if !run.is_bounded() {
+        return Err(invalid_input(
+            "--headless needs --frames, --cycles or an --until-* condition.".to_string(),
+        ));
+    }
+
+    // A crash ends up in the report, the panic message would only say it twice.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let report = run.run(&mut emu);
+    std::panic::set_hook(hook);
+    report_status(&mut emu);
+    println!(
+        "stopped after {} frames, {} cycles: {}",
+        report.frames, report.cycles, report.reason
+    );
+
+    if let Some(path) = flag_value(args, "--screenshot") {
+        let out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write_png(out, SCREEN_WIDTH, SCREEN_HEIGHT, &emu.framebuffer())?;
+    }
+    if let Some(path) = flag_value(args, "--dump-ram") {
+        // Work RAM as the CPU sees it, with the bank switched in on CGB.
+        let ram = (0xC000..=0xDFFF)
+            .map(|a| emu.read_memory(a))
+            .collect::<Vec<_>>();
+        std::fs::write(path, ram)?;
+    }
+    if let Some(path) = flag_value(args, "--save-state") {
+        std::fs::write(path, emu.save_state())?;
+    }
+    if let Some(path) = flag_value(args, "--dump-registers") {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        report.write_json(&emu, &mut out)?;
+        out.flush()?;
+    }
+    emu.close()?;
+    Ok(report.exit_code())
+}
 
 fn main() -> Result<(), std::io::Error> {
     println!("starting yobemag...");
 
-    let args = env::args().collect::<Vec<_>>();
+    let mut args = env::args().collect::<Vec<_>>();
+    // `yobemag run rom.gb` is the same as `yobemag rom.gb`.
+    if args.get(1).is_some_and(|a| a == "run") {
+        args.remove(1);
+    }
     if args.len() < 2 {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
@@ -31,14 +213,216 @@ fn main() -> Result<(), std::io::Error> {
         ));
     }
 
+    if args[1] == "gbs" {
+        return play_gbs(&args);
+    }
+
     let rom = &args[1];
     println!("load of {}", &rom);
+    if let Some(path) = flag_value(&args, "--play") {
+        return play_movie(rom, path);
+    }
 
-    let mut emu = emulator::Emulator::new(rom)?;
+    // yobemag rom.gb --record movie.ymv --frames N
+    // A movie is the buttons of this one console frame after frame, up to an end.
+    let record = match flag_value(&args, "--record") {
+        Some(path) => {
+            let others = [
+                "--headless",
+                "--link-listen",
+                "--link-connect",
+                "--printer",
+                "--four-player",
+                "--infrared-local",
+                "--link-local",
+            ];
+            if let Some(other) = others.iter().find(|o| args.iter().any(|a| a == *o)) {
+                return Err(invalid_input(format!("--record can't go with {}.", other)));
+            }
+            let frames = flag_value(&args, "--frames")
+                .ok_or_else(|| invalid_input("--record needs --frames N.".to_string()))?;
+            Some((path, parse_number("--frames", frames)?))
+        }
+        None => None,
+    };
 
-    loop {
-        emu.step();
+    let boot = match flag_value(&args, "--boot-rom") {
+        Some(path) => Boot::Rom(path.into()),
+        None if args.iter().any(|a| a == "--hle-boot") => Boot::Hle,
+        None => Boot::Skip,
+    };
+    let model = match flag_value(&args, "--model") {
+        Some(m) => m.parse()?,
+        None => Model::default(),
+    };
+    println!("model {}", model);
+
+    // --trace prints every opcode executed.
+    let headless = args.iter().any(|a| a == "--headless");
+    let trace = args.iter().any(|a| a == "--trace");
+    let start = |rom: &str| EmulatorBuilder::new(rom).model(model).trace(trace);
+    let mut emu = start(rom).boot(boot.clone()).build()?;
+    if let Some(title) = emu.title() {
+        println!("Title {}", title);
+    }
+    // A state saved by a headless run, a movie recorded from here starts with it.
+    let state = match flag_value(&args, "--load-state") {
+        Some(path) => {
+            let state = std::fs::read(path)?;
+            emu.load_state(&state)?;
+            Some(state)
+        }
+        None => None,
+    };
+    if let Some(path) = flag_value(&args, "--palette") {
+        emu.set_dmg_palettes(DmgPalettes::load(path)?);
+    } else if let Some(combo) = flag_value(&args, "--cgb-palette") {
+        let combo = combo.parse::<ManualPalette>()?;
+        emu.set_dmg_palettes(combo.into());
+    }
+    if let Some(buttons) = flag_value(&args, "--hold") {
+        emu.set_buttons(buttons.parse()?);
+    }
+    // --turbo a:2,b:4 makes A alternate every 2 frames and B every 4 while held.
+    for turbo in flag_value(&args, "--turbo").unwrap_or("").split(',') {
+        if let Some((button, frames)) = turbo.split_once(':') {
+            let frames = frames
+                .parse::<u32>()
+                .map_err(|e| invalid_input(format!("--turbo: {}", e)))?;
+            emu.set_turbo(button.parse()?, Some(frames));
+        }
+    }
+    if args.iter().any(|a| a == "--filter-opposing") {
+        emu.set_filter_opposing(true);
+    }
+    if let Some(steps) = flag_value(&args, "--macro") {
+        emu.add_macro("start", steps.parse()?);
+        emu.play_macro("start")?;
+    }
+    if args.iter().any(|a| a == "--serial-stdout") {
+        emu.set_serial_sink(Box::new(std::io::stdout()));
+    }
+    if let Some(path) = flag_value(&args, "--record-audio") {
+        let stems = args.iter().any(|a| a == "--record-stems");
+        emu.start_audio_recording(path.as_ref(), stems)?;
+    }
+    if let Some(path) = flag_value(&args, "--log-vgm") {
+        let frame = |name| match flag_value(&args, name) {
+            Some(f) => f.parse::<u64>().map(Some).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{}: {}", name, e))
+            }),
+            None => Ok(None),
+        };
+        // Only a headless run comes to an end by itself.
+        let stop_frame = frame("--vgm-stop-frame")?;
+        if stop_frame.is_none() && !headless {
+            return Err(invalid_input(
+                "--log-vgm needs --vgm-stop-frame, or --headless.".to_string(),
+            ));
+        }
+        emu.start_vgm_logging(path.as_ref(), frame("--vgm-loop-frame")?, stop_frame)?;
+    }
+    // --mute 1,3 leaves channels 1 and 3 out.
+    for channel in flag_value(&args, "--mute").unwrap_or("").split(',') {
+        if !channel.is_empty() {
+            let channel = channel
+                .parse::<usize>()
+                .map_err(|e| invalid_input(format!("--mute: {}", e)))?;
+            emu.set_channel_muted(channel, true)?;
+        }
+    }
+
+    if args.iter().any(|a| a == "--infrared-ambient") {
+        emu.connect_infrared(Box::new(AmbientLight { on: true }));
     }
 
-    // Ok(())
+    if headless {
+        let code = run_headless(&args, emu)?;
+        std::process::exit(code);
+    }
+
+    if let Some(address) = flag_value(&args, "--link-listen") {
+        println!("waiting for the link cable on {}", address);
+        let link = SocketLink::listen(address)?.on_unplug(report_unplug);
+        emu.connect_serial(Box::new(link));
+    } else if let Some(address) = flag_value(&args, "--link-connect") {
+        let link = SocketLink::connect(address)?.on_unplug(report_unplug);
+        emu.connect_serial(Box::new(link));
+    } else if let Some(directory) = flag_value(&args, "--printer") {
+        std::fs::create_dir_all(directory)?;
+        let printer = Printer::new(directory.into(), |sheet| match sheet {
+            Ok(path) => println!("printed {}", path.display()),
+            Err(e) => eprintln!("{}", e),
+        });
+        emu.connect_serial(Box::new(printer));
+    } else if let Some(others) = flag_value(&args, "--four-player") {
+        // Players 2 to 4 on the other ports of the adapter, in the same process.
+        let others = others.split(',').collect::<Vec<_>>();
+        if others.len() != 3 {
+            return Err(invalid_input(
+                "--four-player needs 3 roms, separated by commas.".to_string(),
+            ));
+        }
+        let adapter = FourPlayerAdapter::new();
+        let mut players = [
+            emu,
+            start(others[0]).build()?,
+            start(others[1]).build()?,
+            start(others[2]).build()?,
+        ];
+        for (i, player) in players.iter_mut().enumerate() {
+            player.connect_serial(Box::new(adapter.port(i)?));
+        }
+        loop {
+            adapter.run(&mut players, 8);
+            report_status(&mut players[0]);
+        }
+    } else if let Some(other) = flag_value(&args, "--infrared-local") {
+        // A second console facing this one, in the same process.
+        let mut other = start(other).build()?;
+        let pairing = InfraredPairing::new();
+        emu.connect_infrared(Box::new(pairing.port(0)?));
+        other.connect_infrared(Box::new(pairing.port(1)?));
+        loop {
+            pairing.run(&mut emu, &mut other, 1);
+            report_status(&mut emu);
+        }
+    } else if let Some(other) = flag_value(&args, "--link-local") {
+        // A second console on the other side of the cable, in the same process.
+        let mut other = start(other).build()?;
+        let cable = LinkCable::new();
+        emu.connect_serial(Box::new(cable.port(0)?));
+        other.connect_serial(Box::new(cable.port(1)?));
+        loop {
+            cable.run(&mut emu, &mut other, 1);
+            report_status(&mut emu);
+        }
+    }
+
+    if let Some((path, frames)) = record {
+        let header = MovieHeader {
+            rom_crc32: crc32(&std::fs::read(rom)?),
+            model,
+            boot,
+            save_data: emu.save_data(),
+            state,
+        };
+        let mut recorder = MovieRecorder::create(path.as_ref(), &header)?;
+        for _ in 0..frames {
+            emu.run_frame();
+            report_status(&mut emu);
+            let buttons = emu.buttons();
+            recorder.record(MovieFrame {
+                buttons,
+                checksum: Some(emu.state_checksum()),
+            })?;
+        }
+        println!("movie recorded, {} frames", frames);
+        return emu.close();
+    }
+
+    loop {
+        emu.run_frame();
+        report_status(&mut emu);
+    }
 }
@@ -1,15 +1,22 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::boot_rom::BootRom;
+use crate::cartridge::Cartridge;
+use crate::dmg_palette::DmgPalettes;
 use crate::gpu::GraphicsProcessingUnit;
 use crate::hdma::{Hdma, HdmaMode};
-use crate::input_output_registers::InputOutputRegisters;
+use crate::infrared::{Infrared, InfraredPeer};
+use crate::input_output_registers::{Buttons, InputOutputRegisters};
 use crate::internal_memory::InternalMemory;
 use crate::interrupt::InterruptFlag;
 use crate::memory_device::ReadWrite;
-use crate::serial_data_transfer::SerialDataTransfer;
+use crate::model::Model;
+use crate::save_state::{SaveState, StateReader};
+use crate::serial_data_transfer::{SerialDataTransfer, SerialPeer};
 use crate::sound::Sound;
 use crate::timer::Timer;
+use crate::vgm_logger::VgmLogger;
 
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum Speed {
@@ -28,12 +35,18 @@ impl From<Speed> for u32 {
 
 // Holds all memory space addressable for emulation.
 pub struct MemoryManagmentUnit {
-    cartridge: Box<dyn ReadWrite>,
+    model: Model,
+    // Overlaid on the cartridge until 0xFF50 is written.
+    boot_rom: BootRom,
+    cartridge: Box<dyn Cartridge>,
     gpu: GraphicsProcessingUnit,
     internal: InternalMemory,
     serial: SerialDataTransfer,
+    infrared: Infrared,
     timer: Timer,
     sound: Sound,
+    // Writes reaching the sound registers are logged here while ripping music.
+    vgm: Option<VgmLogger>,
 
     // Bit 7: Current Speed     (0=Normal, 1=Double) (Read Only)
     // Bit 0: Prepare Speed Switch (0=No, 1=Prepare) (Read/Write)
@@ -47,29 +60,132 @@ pub struct MemoryManagmentUnit {
 }
 
 impl MemoryManagmentUnit {
-    pub fn new(cartridge: Box<dyn ReadWrite>) -> MemoryManagmentUnit {
+    pub fn new(
+        cartridge: Box<dyn Cartridge>,
+        boot_rom: BootRom,
+        model: Model,
+    ) -> MemoryManagmentUnit {
+        let interrupt_flag = Rc::new(RefCell::new(InterruptFlag::default()));
         MemoryManagmentUnit {
+            model,
+            boot_rom,
             cartridge,
             gpu: GraphicsProcessingUnit::new(),
-            internal: InternalMemory::new(),
-            serial: SerialDataTransfer::default(),
-            timer: Timer::new(Rc::new(RefCell::new(InterruptFlag::default()))),
-            sound: Sound::new(),
+            internal: InternalMemory::new(interrupt_flag.clone()),
+            serial: SerialDataTransfer::new(interrupt_flag.clone(), model.is_cgb()),
+            infrared: Infrared::new(model.is_cgb()),
+            timer: Timer::new(interrupt_flag.clone()),
+            sound: Sound::new(model),
+            vgm: None,
             speed: Speed::Normal,
             toggle_speed_request: false,
-            io_reg: InputOutputRegisters::default(),
+            io_reg: InputOutputRegisters::new(interrupt_flag),
             hdma: Hdma::default(),
         }
     }
 
     pub fn step(&mut self, cycles: u32) {
-        self.serial.print_serial_debug();
         let cpu_divider: u32 = self.speed.into();
         let vram_cycles = self.run_dma();
         let gpu_cycles = cycles / cpu_divider + vram_cycles;
         let cpu_cycles = cycles + vram_cycles * cpu_divider;
         self.timer.step(cpu_cycles);
+        self.serial.step(cpu_cycles);
+        self.infrared.step(cpu_cycles);
+        for _ in 0..self.timer.take_frame_sequencer_clocks() {
+            self.sound.clock_frame_sequencer();
+        }
         self.gpu.step(gpu_cycles);
+        self.sound.step(gpu_cycles);
+        self.cartridge.step(gpu_cycles);
+
+        if let Some(vgm) = &mut self.vgm {
+            vgm.step(gpu_cycles);
+        }
+    }
+
+    /// Seeds hardware registers with the values the boot ROM of the model leaves behind.
+    pub fn apply_post_boot_state(&mut self) -> Result<(), std::io::Error> {
+        for (address, value) in self.model.post_boot_io() {
+            self.write_byte(address, value)?;
+        }
+
+        self.timer.set_divider(self.model.post_boot_divider());
+        Ok(())
+    }
+
+    pub fn set_dmg_palettes(&mut self, colors: DmgPalettes) {
+        self.gpu.set_dmg_palettes(colors);
+    }
+
+    /// Bytes sent over the serial port are copied to `sink`.
+    pub fn set_serial_sink(&mut self, sink: Box<dyn std::io::Write>) {
+        self.serial.set_sink(sink);
+    }
+
+    pub fn connect_serial(&mut self, peer: Box<dyn SerialPeer>) {
+        self.serial.connect(peer);
+    }
+
+    pub fn connect_infrared(&mut self, peer: Box<dyn InfraredPeer>) {
+        self.infrared.connect(peer);
+    }
+
+    pub fn connect_cartridge_infrared(
+        &mut self,
+        peer: Box<dyn InfraredPeer>,
+    ) -> Result<(), std::io::Error> {
+        self.cartridge.connect_infrared(peer)
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.io_reg.buttons()
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.io_reg.set_buttons(buttons);
+    }
+
+    pub fn sound_mut(&mut self) -> &mut Sound {
+        &mut self.sound
+    }
+
+    /// Logs sound register writes to `vgm`, starting with the current state of the registers.
+    pub fn start_vgm_logging(&mut self, mut vgm: VgmLogger) {
+        for (address, value) in self.sound.register_writes() {
+            vgm.write(address, value);
+        }
+        self.vgm = Some(vgm);
+    }
+
+    pub fn stop_vgm_logging(&mut self) -> Option<VgmLogger> {
+        self.vgm.take()
+    }
+
+    /// True when the log reached its last frame.
+    pub fn vgm_logging_done(&self) -> bool {
+        self.vgm.as_ref().is_some_and(|v| v.is_done())
+    }
+
+    pub fn cartridge_title(&self) -> Option<String> {
+        self.cartridge.header().map(|h| h.title.clone())
+    }
+
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.cartridge.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        self.cartridge.load_save_data(data)
+    }
+
+    /// True during the step where the v-blank started.
+    pub fn v_blank(&self) -> bool {
+        self.gpu.v_blank
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.gpu.frame()
     }
 
     //     run_dma_hrampart:
@@ -138,6 +254,7 @@ impl MemoryManagmentUnit {
             } else {
                 self.speed = Speed::Double;
             }
+            self.timer.set_double_speed(self.speed == Speed::Double);
         }
 
         self.toggle_speed_request = false;
@@ -150,6 +267,10 @@ impl ReadWrite for MemoryManagmentUnit {
     }
 
     fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
+        if self.boot_rom.contains(address) {
+            return self.boot_rom.read_byte(address);
+        }
+
         if self.gpu.contains(address) {
             // TODO: refactor this generic func in array of memory devices I think or somethig generic
             return self.gpu.read_byte(address);
@@ -167,6 +288,10 @@ impl ReadWrite for MemoryManagmentUnit {
             return self.serial.read_byte(address);
         }
 
+        if self.infrared.contains(address) {
+            return self.infrared.read_byte(address);
+        }
+
         if self.timer.contains(address) {
             return self.timer.read_byte(address);
         }
@@ -197,19 +322,25 @@ impl ReadWrite for MemoryManagmentUnit {
                 };
                 Ok(s | t)
             }
-            _ => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::OutOfMemory,
-                    format!(
-                        "MMU don't know where read byte for this address {:#04x}",
-                        address
-                    ),
-                ))
-            }
+            // Unused, Tetris runs into it clearing HRAM.
+            0xFF7F => Ok(0xFF),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::OutOfMemory,
+                format!(
+                    "MMU don't know where read byte for this address {:#04x}",
+                    address
+                ),
+            )),
         }
     }
 
     fn read_word(&self, address: usize) -> Result<u16, std::io::Error> {
+        if self.boot_rom.contains(address) || self.boot_rom.contains(address + 1) {
+            let low = self.read_byte(address)?;
+            let high = self.read_byte(address + 1)?;
+            return Ok(u16::from(low) | (u16::from(high) << 8));
+        }
+
         if self.gpu.contains(address) {
             // TODO: refactor this generic func in array of memory devices I think or somethig generic
             return self.gpu.read_word(address);
@@ -227,6 +358,10 @@ impl ReadWrite for MemoryManagmentUnit {
             return self.serial.read_word(address);
         }
 
+        if self.infrared.contains(address) {
+            return self.infrared.read_word(address);
+        }
+
         if self.timer.contains(address) {
             return self.timer.read_word(address);
         }
@@ -270,11 +405,18 @@ impl ReadWrite for MemoryManagmentUnit {
             return self.serial.write_byte(address, value);
         }
 
+        if self.infrared.contains(address) {
+            return self.infrared.write_byte(address, value);
+        }
+
         if self.timer.contains(address) {
             return self.timer.write_byte(address, value);
         }
 
         if self.sound.contains(address) {
+            if let Some(vgm) = &mut self.vgm {
+                vgm.write(address, value);
+            }
             return self.sound.write_byte(address, value);
         }
 
@@ -288,6 +430,8 @@ impl ReadWrite for MemoryManagmentUnit {
 
         match address {
             0xFF4D => self.toggle_speed_request = (value & 0x01) == 0x01,
+            0xFF50 => self.boot_rom.write_byte(address, value)?,
+            0xFF7F => {}
             _ => {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::OutOfMemory,
@@ -319,6 +463,10 @@ impl ReadWrite for MemoryManagmentUnit {
             return self.serial.write_word(address, value);
         }
 
+        if self.infrared.contains(address) {
+            return self.infrared.write_word(address, value);
+        }
+
         if self.timer.contains(address) {
             return self.timer.write_word(address, value);
         }
@@ -334,3 +482,41 @@ impl ReadWrite for MemoryManagmentUnit {
         unimplemented!()
     }
 }
+
+// A VGM log in progress isn't part of the console.
+impl SaveState for MemoryManagmentUnit {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.boot_rom.save_state(out);
+        self.cartridge.save_state(out);
+        self.gpu.save_state(out);
+        self.internal.save_state(out);
+        self.serial.save_state(out);
+        self.infrared.save_state(out);
+        self.timer.save_state(out);
+        self.sound.save_state(out);
+        out.extend_from_slice(&[
+            (self.speed == Speed::Double) as u8,
+            self.toggle_speed_request as u8,
+        ]);
+        self.io_reg.save_state(out);
+        self.hdma.save_state(out);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.boot_rom.load_state(state)?;
+        self.cartridge.load_state(state)?;
+        self.gpu.load_state(state)?;
+        self.internal.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.infrared.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.sound.load_state(state)?;
+        self.speed = match state.bool()? {
+            true => Speed::Double,
+            false => Speed::Normal,
+        };
+        self.toggle_speed_request = state.bool()?;
+        self.io_reg.load_state(state)?;
+        self.hdma.load_state(state)
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fmt, str::FromStr};
+
+use crate::register::{CpuFlag, Registers};
+
+/// Hardware revision being emulated.
+/// The boot ROM of each model leaves a different state behind and games
+/// look at register A to find out on which console they are running.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Original Game Boy.
+    Dmg,
+    /// Game Boy Pocket and Light.
+    Mgb,
+    /// Super Game Boy.
+    Sgb,
+    /// Game Boy Color.
+    #[default]
+    Cgb,
+    /// Game Boy Advance running Game Boy games.
+    Agb,
+}
+
+// Wave RAM is not cleared at power on, these are the patterns commonly found on hardware.
+const DMG_WAVE_RAM: [u8; 16] = [
+    0x84, 0x40, 0x43, 0xAA, 0x2D, 0x78, 0x92, 0x3C, 0x60, 0x59, 0x59, 0xB0, 0x34, 0xB8, 0x2E, 0xDA,
+];
+const CGB_WAVE_RAM: [u8; 16] = [
+    0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF,
+];
+
+impl Model {
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    /// CPU registers when the boot ROM jumps to 0x0100.
+    /// `header_checksum`, `cgb_game` and `title_hash` come from the cartridge header since
+    /// the boot ROM leaves flags and registers depending on them. `title_hash` is the sum of
+    /// the title bytes for Nintendo games and 0 otherwise, the CGB boot ROM leaves it in B
+    /// when running DMG games.
+    pub fn post_boot_registers(
+        &self,
+        header_checksum: u8,
+        cgb_game: bool,
+        title_hash: u8,
+    ) -> Registers {
+        // On DMG and MGB the flags are the result of the header checksum computation.
+        let dmg_flags = if header_checksum == 0x00 { 0x80 } else { 0xB0 };
+        let (a, f, b, c, d, e, h, l) = match (self, cgb_game) {
+            (Model::Dmg, _) => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            (Model::Mgb, _) => (0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            (Model::Sgb, _) => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            (Model::Cgb, true) => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            (Model::Cgb, false) => (0x11, 0x80, title_hash, 0x00, 0x00, 0x08, 0x00, 0x7C),
+            (Model::Agb, true) => (0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            (Model::Agb, false) => {
+                let b = title_hash.wrapping_add(1);
+                (0x11, 0x00, b, 0x00, 0x00, 0x08, 0x00, 0x7C)
+            }
+        };
+
+        Registers {
+            a,
+            flags: CpuFlag::from_u8(f),
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            program_counter: 0x0100,
+            stack_pointer: 0xFFFE,
+        }
+    }
+
+    /// Value of DIV (0xFF04) when the boot ROM hands over.
+    /// It is only documented for DMG and MGB, on the other models it depends on how long
+    /// the boot animation took, so we use the value measured on a CGB running a DMG cartridge.
+    pub fn post_boot_divider(&self) -> u8 {
+        match self {
+            Model::Dmg | Model::Mgb => 0xAB,
+            Model::Sgb | Model::Cgb | Model::Agb => 0xAC,
+        }
+    }
+
+    /// Hardware registers left by the boot ROM, as (address, value).
+    pub fn post_boot_io(&self) -> Vec<(usize, u8)> {
+        let mut io = vec![
+            (0xFF00, 0xCF),
+            (0xFF01, 0x00),
+            (0xFF02, if self.is_cgb() { 0x7F } else { 0x7E }),
+            (0xFF05, 0x00),
+            (0xFF06, 0x00),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE1),
+            // Sound has to be on before writing the channel registers.
+            (0xFF26, 0x80),
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            // Channel 1 is still playing the chime, except on SGB which reads 0xF0 from NR52.
+            (0xFF14, if *self == Model::Sgb { 0x3F } else { 0xBF }),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF18, 0xFF),
+            (0xFF19, 0xBF),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (0xFF1E, 0xBF),
+            (0xFF20, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0xBF),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+            (0xFF40, 0x91),
+            (0xFF41, 0x85),
+            (0xFF42, 0x00),
+            (0xFF43, 0x00),
+            (0xFF47, 0xFC),
+            (0xFF48, 0xFF),
+            (0xFF49, 0xFF),
+            (0xFFFF, 0x00),
+        ];
+
+        io.extend(
+            self.wave_ram()
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (0xFF30 + i, *v)),
+        );
+
+        io
+    }
+
+    pub fn wave_ram(&self) -> [u8; 16] {
+        if self.is_cgb() {
+            CGB_WAVE_RAM
+        } else {
+            DMG_WAVE_RAM
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            "agb" => Ok(Model::Agb),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unknown model {}, use one of dmg, mgb, sgb, cgb, agb.", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Model::Dmg => write!(f, "DMG"),
+            Model::Mgb => write!(f, "MGB"),
+            Model::Sgb => write!(f, "SGB"),
+            Model::Cgb => write!(f, "CGB"),
+            Model::Agb => write!(f, "AGB"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_a_identifies_console() {
+        assert_eq!(Model::Dmg.post_boot_registers(0x0A, false, 0x00).a, 0x01);
+        assert_eq!(Model::Mgb.post_boot_registers(0x0A, false, 0x00).a, 0xFF);
+        assert_eq!(Model::Sgb.post_boot_registers(0x0A, false, 0x00).a, 0x01);
+        assert_eq!(Model::Cgb.post_boot_registers(0x0A, true, 0x00).a, 0x11);
+        assert_eq!(Model::Agb.post_boot_registers(0x0A, true, 0x00).a, 0x11);
+        // Games tell a GBA from a GBC looking at bit 0 of B.
+        assert_eq!(
+            Model::Agb.post_boot_registers(0x0A, true, 0x00).b & 0x01,
+            0x01
+        );
+        assert_eq!(
+            Model::Cgb.post_boot_registers(0x0A, true, 0x00).b & 0x01,
+            0x00
+        );
+    }
+
+    #[test]
+    fn dmg_flags_depend_on_header_checksum() {
+        assert_eq!(
+            Model::Dmg.post_boot_registers(0x0A, false, 0x00).af(),
+            0x01B0
+        );
+        assert_eq!(
+            Model::Dmg.post_boot_registers(0x00, false, 0x00).af(),
+            0x0180
+        );
+    }
+
+    #[test]
+    fn cgb_registers_depend_on_game() {
+        let r = Model::Cgb.post_boot_registers(0x0A, true, 0x00);
+        assert_eq!(r.de(), 0xFF56);
+        assert_eq!(r.hl(), 0x000D);
+        let r = Model::Cgb.post_boot_registers(0x0A, false, 0xDB);
+        assert_eq!(r.b, 0xDB);
+        assert_eq!(r.de(), 0x0008);
+        assert_eq!(r.hl(), 0x007C);
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("DMG".parse::<Model>().unwrap(), Model::Dmg);
+        assert_eq!("agb".parse::<Model>().unwrap(), Model::Agb);
+        assert!("gba".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn wave_ram_pattern() {
+        assert_eq!(Model::Dmg.wave_ram()[0], 0x84);
+        assert_eq!(Model::Cgb.wave_ram()[1], 0xFF);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{emulator::Boot, input_output_registers::Buttons, model::Model};
+
+const MAGIC: &str = "yobemag movie 1";
+
+/// Buttons held during a frame, and the state checksum once it's over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovieFrame {
+    pub buttons: Buttons,
+    pub checksum: Option<u32>,
+}
+
+/// How a movie starts: the ROM and console it runs on, the battery RAM it begins with and,
+/// for a movie that doesn't start at power on, the save state it starts from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovieHeader {
+    pub rom_crc32: u32,
+    pub model: Model,
+    pub boot: Boot,
+    pub save_data: Option<Vec<u8>>,
+    /// Made by `Emulator::save_state`, loaded before the first frame.
+    pub state: Option<Vec<u8>>,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn parse_hex_bytes(value: &str) -> Result<Vec<u8>, std::io::Error> {
+    (0..value.len())
+        .step_by(2)
+        .map(|i| parse_hex(value.get(i..i + 2).unwrap_or(""), u8::from_str_radix))
+        .collect()
+}
+
+/// Joypad input recorded frame by frame, replayed to get the same run again.
+///
+/// It's a text file: the header, one `key value` per line, then a `frames` line followed by
+/// a line per frame with the buttons as two hex digits and, optionally, the checksum.
+#[derive(Debug, PartialEq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<MovieFrame>,
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn parse_hex<T>(
+    s: &str,
+    parse: fn(&str, u32) -> Result<T, std::num::ParseIntError>,
+) -> Result<T, std::io::Error> {
+    parse(s, 16).map_err(|e| invalid_data(format!("movie: bad number {}: {}", s, e)))
+}
+
+impl MovieHeader {
+    fn write_to(&self, out: &mut impl Write) -> Result<(), std::io::Error> {
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "rom {:08X}", self.rom_crc32)?;
+        writeln!(out, "model {}", self.model)?;
+        match &self.boot {
+            Boot::Skip => writeln!(out, "boot skip")?,
+            Boot::Hle => writeln!(out, "boot hle")?,
+            Boot::Rom(path) => writeln!(out, "boot rom {}", path.display())?,
+        }
+        if let Some(data) = &self.save_data {
+            writeln!(out, "save {}", hex(data))?;
+        }
+        if let Some(state) = &self.state {
+            writeln!(out, "state {}", hex(state))?;
+        }
+        writeln!(out, "frames")
+    }
+}
+
+impl MovieFrame {
+    fn write_to(&self, out: &mut impl Write) -> Result<(), std::io::Error> {
+        match self.checksum {
+            Some(checksum) => writeln!(out, "{:02X} {:08X}", self.buttons.0, checksum),
+            None => writeln!(out, "{:02X}", self.buttons.0),
+        }
+    }
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Movie, std::io::Error> {
+        Movie::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Movie, std::io::Error> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid_data("not a yobemag movie.".to_string()));
+        }
+
+        let (mut rom_crc32, mut model, mut boot) = (None, None, Boot::Skip);
+        let (mut save_data, mut state) = (None, None);
+        for line in lines.by_ref() {
+            if line == "frames" {
+                break;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "rom" => rom_crc32 = Some(parse_hex(value, u32::from_str_radix)?),
+                "model" => model = Some(value.parse()?),
+                "boot" => {
+                    boot = match value.split_once(' ') {
+                        Some(("rom", path)) => Boot::Rom(path.into()),
+                        _ if value == "hle" => Boot::Hle,
+                        _ if value == "skip" => Boot::Skip,
+                        _ => return Err(invalid_data(format!("movie: unknown boot {}", value))),
+                    }
+                }
+                "save" => save_data = Some(parse_hex_bytes(value)?),
+                "state" => state = Some(parse_hex_bytes(value)?),
+                // Left for newer versions.
+                _ => (),
+            }
+        }
+
+        let frames = lines
+            .filter(|l| !l.is_empty())
+            .map(|line| {
+                let mut fields = line.split(' ');
+                let buttons = Buttons(parse_hex(fields.next().unwrap_or(""), u8::from_str_radix)?);
+                let checksum = match fields.next() {
+                    Some(c) => Some(parse_hex(c, u32::from_str_radix)?),
+                    None => None,
+                };
+                Ok(MovieFrame { buttons, checksum })
+            })
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+
+        Ok(Movie {
+            header: MovieHeader {
+                rom_crc32: rom_crc32.ok_or_else(|| invalid_data("movie: missing rom.".into()))?,
+                model: model.ok_or_else(|| invalid_data("movie: missing model.".into()))?,
+                boot,
+                save_data,
+                state,
+            },
+            frames,
+        })
+    }
+}
+
+/// Writes a movie as it's recorded, a frame at a time, so a run cut short still leaves the
+/// frames played so far.
+pub struct MovieRecorder {
+    out: BufWriter<File>,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &Path, header: &MovieHeader) -> Result<MovieRecorder, std::io::Error> {
+        let mut out = BufWriter::new(File::create(path)?);
+        header.write_to(&mut out)?;
+        Ok(MovieRecorder { out })
+    }
+
+    pub fn record(&mut self, frame: MovieFrame) -> Result<(), std::io::Error> {
+        frame.write_to(&mut self.out)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_load() {
+        let path = std::env::temp_dir().join("yobemag-movie-test.ymv");
+        let header = MovieHeader {
+            rom_crc32: 0x46DBCE4E,
+            model: Model::Cgb,
+            boot: Boot::Rom("cgb_boot.bin".into()),
+            save_data: Some(vec![0x00, 0xA5, 0xFF]),
+            state: Some(vec![0x79, 0x0A]),
+        };
+        let frames = vec![
+            MovieFrame {
+                buttons: Buttons(0x81),
+                checksum: Some(0x1234ABCD),
+            },
+            MovieFrame {
+                buttons: Buttons(0x00),
+                checksum: None,
+            },
+        ];
+
+        let mut recorder = MovieRecorder::create(&path, &header).unwrap();
+        for frame in &frames {
+            recorder.record(*frame).unwrap();
+        }
+        drop(recorder);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text,
+            "yobemag movie 1\nrom 46DBCE4E\nmodel CGB\nboot rom cgb_boot.bin\nsave 00A5FF\nstate 790A\nframes\n81 1234ABCD\n00\n"
+        );
+        assert_eq!(Movie::load(&path).unwrap(), Movie { header, frames });
+    }
+
+    #[test]
+    fn bad_movies() {
+        assert!(Movie::parse("something else\n").is_err());
+        assert!(Movie::parse("yobemag movie 1\nmodel dmg\nframes\n").is_err());
+        assert!(Movie::parse("yobemag movie 1\nrom 0\nmodel dmg\nframes\nZZ\n").is_err());
+    }
+}
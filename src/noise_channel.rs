@@ -0,0 +1,228 @@
+use crate::{
+    length_counter::LengthCounter,
+    save_state::{SaveState, StateReader},
+    volume_envelope::VolumeEnvelope,
+};
+
+// Base divisors selected by bits 2-0 of NR43, in cycles.
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Channel 4, white noise from a linear feedback shift register: 0xFF20-0xFF23.
+pub struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: VolumeEnvelope,
+
+    // NR43 Polynomial Counter
+    // Bit 7-4 - Shift Clock Frequency (s)
+    // Bit 3   - Counter Step/Width (0=15 bits, 1=7 bits)
+    // Bit 2-0 - Dividing Ratio of Frequencies (r)
+    // The LFSR is clocked every DIVISORS[r] << s cycles.
+    polynomial: u8,
+    timer: u32,
+
+    // 15 bits, the output is the inverted bit 0.
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: VolumeEnvelope::default(),
+            polynomial: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.polynomial & 0x07) as usize] << (self.polynomial >> 4)
+    }
+
+    /// Reads NR41-NR44, `register` being 0-3. Write-only bits read back as 1.
+    pub fn read_register(&self, register: usize) -> u8 {
+        match register {
+            1 => self.envelope.read(),
+            2 => self.polynomial,
+            3 => ((self.length.is_enabled() as u8) << 6) | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes NR41-NR44, `frame_step` is the next step of the frame sequencer.
+    pub fn write_register(&mut self, register: usize, value: u8, frame_step: u8) {
+        match register {
+            0 => self.length.load(value & 0x3F),
+            1 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            2 => self.polynomial = value,
+            3 => {
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, frame_step & 0x01 == 0)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.polynomial & 0x08 != 0 {
+            self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+        }
+    }
+
+    /// Cycles until the output of the channel can next change.
+    pub fn cycles_until_step(&self) -> u32 {
+        if !self.enabled || self.polynomial >> 4 >= 14 {
+            u32::MAX
+        } else {
+            self.timer
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        // Shift clock frequencies 14 and 15 leave the LFSR without clock.
+        if !self.enabled || self.polynomial >> 4 >= 14 {
+            return;
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.clock_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Digital output of the channel, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        (!self.lfsr & 0x01) as u8 * self.envelope.volume()
+    }
+}
+
+impl SaveState for NoiseChannel {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.enabled as u8);
+        self.length.save_state(out);
+        self.envelope.save_state(out);
+        out.push(self.polynomial);
+        out.extend_from_slice(&self.timer.to_le_bytes());
+        out.extend_from_slice(&self.lfsr.to_le_bytes());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.enabled = state.bool()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.polynomial = state.u8()?;
+        self.timer = state.u32()?;
+        self.lfsr = state.u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(nr43: u8) -> NoiseChannel {
+        let mut channel = NoiseChannel::new();
+        channel.write_register(1, 0xF0, 0);
+        channel.write_register(2, nr43, 0);
+        channel.write_register(3, 0x80, 0);
+        channel
+    }
+
+    #[test]
+    fn lfsr_sequence() {
+        let mut channel = playing(0x00);
+        // All ones shift in zeros: 0x7FFF -> 0x3FFF.
+        channel.step(8);
+        assert_eq!(channel.lfsr, 0x3FFF);
+        assert_eq!(channel.output(), 0);
+        channel.step(8 * 13);
+        assert_eq!(channel.lfsr, 0x0001);
+        channel.step(8);
+        assert_eq!(channel.lfsr, 0x4000);
+        assert_eq!(channel.output(), 15);
+    }
+
+    #[test]
+    fn width_mode_period_127() {
+        let mut channel = playing(0x08);
+        let start = channel.lfsr & 0x7F;
+        channel.step(8 * 127);
+        assert_eq!(channel.lfsr & 0x7F, start & 0x7F);
+        channel.step(8);
+        assert_ne!(channel.lfsr & 0x7F, start & 0x7F);
+    }
+
+    #[test]
+    fn clock_divider() {
+        // r=3 and s=2: 48 << 2 cycles.
+        let mut channel = playing(0x23);
+        channel.step(191);
+        assert_eq!(channel.lfsr, 0x7FFF);
+        channel.step(1);
+        assert_eq!(channel.lfsr, 0x3FFF);
+
+        let mut channel = playing(0xE0);
+        channel.step(1 << 20);
+        assert_eq!(channel.lfsr, 0x7FFF);
+    }
+
+    #[test]
+    fn envelope_and_length() {
+        let mut channel = NoiseChannel::new();
+        channel.write_register(0, 0x3F, 0);
+        channel.write_register(1, 0x08, 0);
+        assert_eq!(channel.read_register(1), 0x08);
+        channel.write_register(3, 0xC0, 0);
+        assert!(channel.is_enabled());
+        assert_eq!(channel.read_register(3), 0xFF);
+        channel.clock_length();
+        assert!(!channel.is_enabled());
+        assert_eq!(channel.read_register(0), 0xFF);
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::Write;
+
+// Deflate blocks stored as they are can't be longer than this.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// The CRC-32 of zlib and PNG chunks, also the usual way to identify a ROM.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> Result<(), std::io::Error> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut crc_input = kind.to_vec();
+    crc_input.extend_from_slice(data);
+    out.write_all(&crc_input)?;
+    out.write_all(&crc32(&crc_input).to_be_bytes())
+}
+
+// A zlib stream of stored blocks: images are small, it isn't worth compressing them.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Writes an 8-bit RGB image, `rgb` holds `width * height` pixels row after row.
+pub fn write_png(
+    mut out: impl Write,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> Result<(), std::io::Error> {
+    assert_eq!(rgb.len(), width * height * 3, "image size doesn't match");
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filtering beyond the per-row byte, not interlaced.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header)?;
+
+    let mut rows = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3).take(height) {
+        // Filter type none.
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&rows))?;
+    write_chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn two_pixels() {
+        let mut bytes = vec![];
+        write_png(&mut bytes, 2, 1, &[0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF]).unwrap();
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&bytes[12..16], b"IHDR");
+        assert_eq!(&bytes[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+        // Length of IDAT: zlib header, block header, the row and the checksum.
+        let idat = 8 + 4 + 4 + 13 + 4;
+        assert_eq!(
+            &bytes[idat..idat + 8],
+            &[0, 0, 0, 18, b'I', b'D', b'A', b'T']
+        );
+        assert_eq!(
+            &bytes[idat + 8..idat + 8 + 18],
+            &[
+                0x78, 0x01, 0x01, 0x07, 0x00, 0xF8, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF,
+                0x07, 0x00, 0x01, 0xFF
+            ]
+        );
+        assert!(bytes.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+}
@@ -45,6 +45,86 @@ pub enum PrefixOpCode {
     SwapH,
     SwapL,
     SwapA,
+
+    /// RL r
+    /// The contents of the register r are rotated left by 1 bit position through the carry flag.
+    /// Register r may be any of B, C, D, E, H, L or A.
+    /// Clock cycles: 8
+    RlB,
+    RlC,
+    RlD,
+    RlE,
+    RlH,
+    RlL,
+    RlA,
+
+    /// BIT b, r
+    /// Tests bit b in register r and sets the zero flag accordingly, carry is not affected.
+    /// Register r may be any of B, C, D, E, H, L or A.
+    /// Clock cycles: 8
+    Bit0B,
+    Bit0C,
+    Bit0D,
+    Bit0E,
+    Bit0H,
+    Bit0L,
+    Bit0A,
+
+    Bit1B,
+    Bit1C,
+    Bit1D,
+    Bit1E,
+    Bit1H,
+    Bit1L,
+    Bit1A,
+
+    Bit2B,
+    Bit2C,
+    Bit2D,
+    Bit2E,
+    Bit2H,
+    Bit2L,
+    Bit2A,
+
+    Bit3B,
+    Bit3C,
+    Bit3D,
+    Bit3E,
+    Bit3H,
+    Bit3L,
+    Bit3A,
+
+    Bit4B,
+    Bit4C,
+    Bit4D,
+    Bit4E,
+    Bit4H,
+    Bit4L,
+    Bit4A,
+
+    Bit5B,
+    Bit5C,
+    Bit5D,
+    Bit5E,
+    Bit5H,
+    Bit5L,
+    Bit5A,
+
+    Bit6B,
+    Bit6C,
+    Bit6D,
+    Bit6E,
+    Bit6H,
+    Bit6L,
+    Bit6A,
+
+    Bit7B,
+    Bit7C,
+    Bit7D,
+    Bit7E,
+    Bit7H,
+    Bit7L,
+    Bit7A,
 }
 
 impl From<u8> for PrefixOpCode {
@@ -57,6 +137,13 @@ impl From<u8> for PrefixOpCode {
             0x04 => PrefixOpCode::RlcH,
             0x05 => PrefixOpCode::RlcL,
             0x07 => PrefixOpCode::RlcA,
+            0x10 => PrefixOpCode::RlB,
+            0x11 => PrefixOpCode::RlC,
+            0x12 => PrefixOpCode::RlD,
+            0x13 => PrefixOpCode::RlE,
+            0x14 => PrefixOpCode::RlH,
+            0x15 => PrefixOpCode::RlL,
+            0x17 => PrefixOpCode::RlA,
             0x18 => PrefixOpCode::RrB,
             0x19 => PrefixOpCode::RrC,
             0x1A => PrefixOpCode::RrD,
@@ -78,6 +165,62 @@ impl From<u8> for PrefixOpCode {
             0x3C => PrefixOpCode::SrlH,
             0x3D => PrefixOpCode::SrlL,
             0x3F => PrefixOpCode::SrlA,
+            0x40 => PrefixOpCode::Bit0B,
+            0x41 => PrefixOpCode::Bit0C,
+            0x42 => PrefixOpCode::Bit0D,
+            0x43 => PrefixOpCode::Bit0E,
+            0x44 => PrefixOpCode::Bit0H,
+            0x45 => PrefixOpCode::Bit0L,
+            0x47 => PrefixOpCode::Bit0A,
+            0x48 => PrefixOpCode::Bit1B,
+            0x49 => PrefixOpCode::Bit1C,
+            0x4A => PrefixOpCode::Bit1D,
+            0x4B => PrefixOpCode::Bit1E,
+            0x4C => PrefixOpCode::Bit1H,
+            0x4D => PrefixOpCode::Bit1L,
+            0x4F => PrefixOpCode::Bit1A,
+            0x50 => PrefixOpCode::Bit2B,
+            0x51 => PrefixOpCode::Bit2C,
+            0x52 => PrefixOpCode::Bit2D,
+            0x53 => PrefixOpCode::Bit2E,
+            0x54 => PrefixOpCode::Bit2H,
+            0x55 => PrefixOpCode::Bit2L,
+            0x57 => PrefixOpCode::Bit2A,
+            0x58 => PrefixOpCode::Bit3B,
+            0x59 => PrefixOpCode::Bit3C,
+            0x5A => PrefixOpCode::Bit3D,
+            0x5B => PrefixOpCode::Bit3E,
+            0x5C => PrefixOpCode::Bit3H,
+            0x5D => PrefixOpCode::Bit3L,
+            0x5F => PrefixOpCode::Bit3A,
+            0x60 => PrefixOpCode::Bit4B,
+            0x61 => PrefixOpCode::Bit4C,
+            0x62 => PrefixOpCode::Bit4D,
+            0x63 => PrefixOpCode::Bit4E,
+            0x64 => PrefixOpCode::Bit4H,
+            0x65 => PrefixOpCode::Bit4L,
+            0x67 => PrefixOpCode::Bit4A,
+            0x68 => PrefixOpCode::Bit5B,
+            0x69 => PrefixOpCode::Bit5C,
+            0x6A => PrefixOpCode::Bit5D,
+            0x6B => PrefixOpCode::Bit5E,
+            0x6C => PrefixOpCode::Bit5H,
+            0x6D => PrefixOpCode::Bit5L,
+            0x6F => PrefixOpCode::Bit5A,
+            0x70 => PrefixOpCode::Bit6B,
+            0x71 => PrefixOpCode::Bit6C,
+            0x72 => PrefixOpCode::Bit6D,
+            0x73 => PrefixOpCode::Bit6E,
+            0x74 => PrefixOpCode::Bit6H,
+            0x75 => PrefixOpCode::Bit6L,
+            0x77 => PrefixOpCode::Bit6A,
+            0x78 => PrefixOpCode::Bit7B,
+            0x79 => PrefixOpCode::Bit7C,
+            0x7A => PrefixOpCode::Bit7D,
+            0x7B => PrefixOpCode::Bit7E,
+            0x7C => PrefixOpCode::Bit7H,
+            0x7D => PrefixOpCode::Bit7L,
+            0x7F => PrefixOpCode::Bit7A,
             _ => panic!("unknown prefix opcode {:#04x}", orig),
         }
     }
@@ -0,0 +1,447 @@
+use std::path::PathBuf;
+
+use crate::{png_writer::write_png, serial_data_transfer::SerialPeer};
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+// Sent while the first byte following the checksum comes in.
+const ALIVE: u8 = 0x81;
+
+// Status bits.
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const IMAGE_DATA_FULL: u8 = 0x04;
+const UNPROCESSED_DATA: u8 = 0x08;
+const PACKET_ERROR: u8 = 0x10;
+
+// The paper is 160 pixels wide, data comes in bands of 2 rows of 20 tiles.
+const WIDTH: usize = 160;
+const TILES_PER_ROW: usize = WIDTH / 8;
+const BAND_BYTES: usize = 0x280;
+// 8 KB of RAM, 9 bands.
+const BUFFER_BYTES: usize = 9 * BAND_BYTES;
+// Pixel rows the paper moves for each unit of margin.
+const MARGIN_ROWS: usize = 16;
+// About how long the head takes to print a band.
+const BAND_CYCLES: u32 = 4_194_304 / 8;
+
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Magic(usize),
+    Command,
+    Compression,
+    Length(usize),
+    Data,
+    Checksum(usize),
+    Alive,
+    Status,
+}
+
+/// The Game Boy Printer, to plug into the serial port. Each sheet printed is written to
+/// `print-001.png`, `print-002.png` and so on in the given directory.
+pub struct Printer {
+    directory: PathBuf,
+    printed: usize,
+    on_sheet: Box<dyn FnMut(Result<PathBuf, std::io::Error>)>,
+
+    state: State,
+    // Byte coming in, byte going out and the bits of them exchanged so far.
+    incoming: u8,
+    outgoing: u8,
+    bits: u8,
+
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    status: u8,
+    busy_cycles: u32,
+    // Tile data received and waiting for a print command.
+    buffer: Vec<u8>,
+    // Shades, 0-3, of the sheet being printed.
+    paper: Vec<u8>,
+}
+
+// Packet data is compressed with a run-length encoding: a byte with bit 7 set repeats the
+// next byte (n & 0x7F) + 2 times, otherwise the n + 1 next bytes are copied.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut bytes = data.iter();
+    while let Some(&n) = bytes.next() {
+        if n & 0x80 != 0 {
+            if let Some(&value) = bytes.next() {
+                out.extend(std::iter::repeat_n(value, usize::from(n & 0x7F) + 2));
+            }
+        } else {
+            out.extend(bytes.by_ref().take(usize::from(n) + 1));
+        }
+    }
+    out
+}
+
+impl Printer {
+    /// `on_sheet` is called with the path of each sheet written, or the error writing it.
+    pub fn new(
+        directory: PathBuf,
+        on_sheet: impl FnMut(Result<PathBuf, std::io::Error>) + 'static,
+    ) -> Printer {
+        Printer {
+            directory,
+            printed: 0,
+            on_sheet: Box::new(on_sheet),
+            state: State::Magic(0),
+            incoming: 0,
+            outgoing: 0,
+            bits: 0,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: vec![],
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_cycles: 0,
+            buffer: vec![],
+            paper: vec![],
+        }
+    }
+
+    // Takes in a whole byte and picks the one to send back with the next.
+    fn receive(&mut self, byte: u8) -> u8 {
+        if matches!(
+            self.state,
+            State::Command | State::Compression | State::Length(_) | State::Data
+        ) {
+            self.checksum = self.checksum.wrapping_add(u16::from(byte));
+        }
+
+        match self.state {
+            State::Magic(i) => {
+                self.state = if byte == MAGIC[i] {
+                    if i == 0 {
+                        State::Magic(1)
+                    } else {
+                        self.checksum = 0;
+                        State::Command
+                    }
+                } else if byte == MAGIC[0] {
+                    State::Magic(1)
+                } else {
+                    State::Magic(0)
+                };
+            }
+            State::Command => {
+                self.command = byte;
+                self.state = State::Compression;
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.state = State::Length(0);
+            }
+            State::Length(0) => {
+                self.length = u16::from(byte);
+                self.state = State::Length(1);
+            }
+            State::Length(_) => {
+                self.length |= u16::from(byte) << 8;
+                self.data.clear();
+                self.state = if self.length > 0 {
+                    State::Data
+                } else {
+                    State::Checksum(0)
+                };
+            }
+            State::Data => {
+                self.data.push(byte);
+                if self.data.len() == usize::from(self.length) {
+                    self.state = State::Checksum(0);
+                }
+            }
+            State::Checksum(0) => {
+                self.received_checksum = u16::from(byte);
+                self.state = State::Checksum(1);
+            }
+            State::Checksum(_) => {
+                self.received_checksum |= u16::from(byte) << 8;
+                if self.received_checksum == self.checksum {
+                    self.status &= !CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= CHECKSUM_ERROR;
+                }
+                self.state = State::Alive;
+                return ALIVE;
+            }
+            State::Alive => {
+                self.state = State::Status;
+                return self.status;
+            }
+            State::Status => self.state = State::Magic(0),
+        }
+        0x00
+    }
+
+    fn run_command(&mut self) {
+        match self.command {
+            COMMAND_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_cycles = 0;
+            }
+            COMMAND_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    std::mem::take(&mut self.data)
+                };
+                let room = BUFFER_BYTES - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                if !self.buffer.is_empty() {
+                    self.status |= UNPROCESSED_DATA;
+                }
+                if self.buffer.len() == BUFFER_BYTES {
+                    self.status |= IMAGE_DATA_FULL;
+                }
+            }
+            COMMAND_PRINT if self.data.len() == 4 && self.status & PRINTING == 0 => {
+                // Number of sheets, margins before and after, palette and exposure, which
+                // only makes the print darker or lighter.
+                let margins = self.data[1];
+                let palette = match self.data[2] {
+                    // Some games leave it to the printer to pick the usual one.
+                    0x00 => 0xE4,
+                    palette => palette,
+                };
+                // No sheets only feeds the paper, which leaves nothing to see.
+                if self.data[0] == 0 {
+                    return;
+                }
+                self.print(margins >> 4, margins & 0x0F, palette);
+            }
+            COMMAND_STATUS | COMMAND_PRINT => (),
+            _ => self.status |= PACKET_ERROR,
+        }
+    }
+
+    fn print(&mut self, margin_before: u8, margin_after: u8, palette: u8) {
+        self.paper.resize(
+            self.paper.len() + usize::from(margin_before) * MARGIN_ROWS * WIDTH,
+            0,
+        );
+
+        let rows = self.buffer.len() / (TILES_PER_ROW * 16);
+        for row in 0..rows * 8 {
+            for x in 0..WIDTH {
+                let tile = (row / 8) * TILES_PER_ROW + x / 8;
+                let offset = tile * 16 + (row % 8) * 2;
+                let bit = 7 - (x % 8);
+                let low = (self.buffer[offset] >> bit) & 1;
+                let high = (self.buffer[offset + 1] >> bit) & 1;
+                let color = (high << 1) | low;
+                self.paper.push((palette >> (color * 2)) & 0x03);
+            }
+        }
+
+        self.busy_cycles = BAND_CYCLES * (rows as u32 / 2).max(1);
+        self.buffer.clear();
+        self.status = (self.status & !(UNPROCESSED_DATA | IMAGE_DATA_FULL)) | PRINTING;
+
+        if margin_after > 0 {
+            self.paper.resize(
+                self.paper.len() + usize::from(margin_after) * MARGIN_ROWS * WIDTH,
+                0,
+            );
+            self.cut();
+        }
+    }
+
+    // Writes the sheet printed so far to a new file.
+    fn cut(&mut self) {
+        if self.paper.is_empty() {
+            return;
+        }
+        let paper = std::mem::take(&mut self.paper);
+        let rgb = paper
+            .iter()
+            .flat_map(|&shade| [SHADES[usize::from(shade)]; 3])
+            .collect::<Vec<_>>();
+
+        self.printed += 1;
+        let path = self
+            .directory
+            .join(format!("print-{:03}.png", self.printed));
+        // A printer jam must not stop the emulation.
+        let written = std::fs::File::create(&path)
+            .and_then(|file| write_png(file, WIDTH, paper.len() / WIDTH, &rgb))
+            .map_err(|e| {
+                std::io::Error::new(e.kind(), format!("can't write {}: {}", path.display(), e))
+            });
+        (self.on_sheet)(written.map(|()| path));
+    }
+}
+
+impl SerialPeer for Printer {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        let out = self.outgoing & 0x80 != 0;
+        self.outgoing <<= 1;
+        self.incoming = (self.incoming << 1) | bit as u8;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.bits = 0;
+            self.outgoing = self.receive(self.incoming);
+        }
+        out
+    }
+
+    // The printer never drives the clock.
+    fn clocked_bit(&mut self, _bit: bool) -> Option<bool> {
+        None
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if self.busy_cycles > 0 {
+            self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+            if self.busy_cycles == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+    }
+}
+
+impl Drop for Printer {
+    // What's printed without a margin after it is still on paper.
+    fn drop(&mut self) {
+        self.cut();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Sends a whole packet and returns the two bytes the printer answers at the end.
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![command, compressed as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let checksum = packet.iter().map(|&b| u16::from(b)).sum::<u16>();
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(packet);
+        bytes.extend_from_slice(&[0x00, 0x00]);
+
+        let answers = bytes
+            .iter()
+            .map(|&byte| {
+                (0..8).fold(0u8, |answer, i| {
+                    (answer << 1) | printer.exchange_bit(byte & (0x80 >> i) != 0) as u8
+                })
+            })
+            .collect::<Vec<_>>();
+        assert!(answers[..answers.len() - 2].iter().all(|&a| a == 0));
+        (answers[answers.len() - 2], answers[answers.len() - 1])
+    }
+
+    type Sheets = Rc<RefCell<Vec<Result<PathBuf, std::io::ErrorKind>>>>;
+
+    fn printer(name: &str) -> (Printer, Sheets) {
+        let directory = std::env::temp_dir().join(format!("yobemag-printer-{}", name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let sheets = Sheets::default();
+        let report = sheets.clone();
+        let printer = Printer::new(directory, move |sheet| {
+            report.borrow_mut().push(sheet.map_err(|e| e.kind()))
+        });
+        (printer, sheets)
+    }
+
+    // Prints one band of shades 3 then 0, returns once the printer is done.
+    fn print_band(printer: &mut Printer) {
+        send(printer, COMMAND_INIT, false, &[]);
+        // A row of tiles all color 3 and one all color 0, compressed.
+        let mut band = vec![];
+        for value in [0xFF, 0x00] {
+            for count in [129, 129, 62] {
+                band.extend_from_slice(&[0x80 | (count - 2), value]);
+            }
+        }
+        assert_eq!(send(printer, COMMAND_DATA, true, &band).1, UNPROCESSED_DATA);
+        assert_eq!(printer.buffer.len(), BAND_BYTES);
+        send(printer, COMMAND_DATA, false, &[]);
+
+        // One sheet with a margin after, the palette inverted.
+        let print = [1, 0x01, 0x1B, 0x40];
+        assert_eq!(send(printer, COMMAND_PRINT, false, &print).1, PRINTING);
+        printer.step(BAND_CYCLES - 1);
+        assert_eq!(send(printer, COMMAND_STATUS, false, &[]).1, PRINTING);
+        printer.step(1);
+        assert_eq!(send(printer, COMMAND_STATUS, false, &[]).1, 0x00);
+    }
+
+    #[test]
+    fn run_length_encoding() {
+        assert_eq!(
+            decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34, 0x80, 0xFF]),
+            vec![0xAA, 0xAA, 0xAA, 0x12, 0x34, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn init_and_status() {
+        let (mut printer, _) = printer("status");
+        assert_eq!(send(&mut printer, COMMAND_INIT, false, &[]), (ALIVE, 0x00));
+        assert_eq!(
+            send(&mut printer, COMMAND_STATUS, false, &[]),
+            (ALIVE, 0x00)
+        );
+
+        // A wrong checksum.
+        let bytes = [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut answers = vec![];
+        for byte in bytes {
+            let mut answer = 0;
+            for i in 0..8 {
+                answer = (answer << 1) | printer.exchange_bit(byte & (0x80 >> i) != 0) as u8;
+            }
+            answers.push(answer);
+        }
+        assert_eq!(answers[8..], [ALIVE, CHECKSUM_ERROR]);
+    }
+
+    #[test]
+    fn prints_a_sheet() {
+        let (mut printer, sheets) = printer("sheet");
+        print_band(&mut printer);
+
+        let path = printer.directory.join("print-001.png");
+        assert_eq!(*sheets.borrow(), vec![Ok(path.clone())]);
+        assert!(path.exists());
+        let png = std::fs::read(path).unwrap();
+        // The band and the margin after it.
+        assert_eq!(&png[16..24], &[0, 0, 0, 160, 0, 0, 0, 16 + 16]);
+        // Rows are stored uncompressed after the zlib and block headers.
+        let pixel = |row: usize| png[33 + 8 + 2 + 5 + row * (1 + WIDTH * 3) + 1];
+        assert_eq!([pixel(0), pixel(8), pixel(16)], [0xFF, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn jam_is_reported() {
+        let (mut printer, sheets) = printer("jam");
+        std::fs::remove_dir(&printer.directory).unwrap();
+        print_band(&mut printer);
+        assert_eq!(*sheets.borrow(), vec![Err(std::io::ErrorKind::NotFound)]);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::{
+    length_counter::LengthCounter,
+    save_state::{SaveState, StateReader},
+    volume_envelope::VolumeEnvelope,
+};
+
+// Waveforms of the four duty cycles, played from bit 7 to bit 0.
+// 12.5%, 25%, 50% and 75%.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Frequency sweep of channel 1, NR10.
+/// Bit 6-4 - Sweep period (n*7.8 ms)
+/// Bit 3   - Sweep direction (0: Addition, 1: Subtraction)
+/// Bit 2-0 - Number of sweep shift (n: 0-7)
+#[derive(Default)]
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    shadow: u16,
+    // A calculation in subtraction mode happened since the last trigger.
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn negate(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if self.negate() {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// Returns false when switching from subtraction to addition after a subtraction
+    /// has been used, that disables the channel.
+    fn write(&mut self, value: u8) -> bool {
+        self.register = value;
+        self.negate() || !self.negate_used
+    }
+
+    /// Returns false when the frequency calculated on trigger overflows.
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.reload_timer();
+        self.enabled = self.period() != 0 || self.shift() != 0;
+        self.negate_used = false;
+        self.shift() == 0 || self.calculate() <= 0x7FF
+    }
+
+    /// Returns the new frequency, or Err when it overflows and the channel has to be disabled.
+    fn clock(&mut self) -> Result<Option<u16>, ()> {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return Ok(None);
+        }
+
+        self.reload_timer();
+        if !self.enabled || self.period() == 0 {
+            return Ok(None);
+        }
+
+        let frequency = self.calculate();
+        if frequency > 0x7FF {
+            return Err(());
+        }
+        if self.shift() == 0 {
+            return Ok(None);
+        }
+
+        self.shadow = frequency;
+        // The new frequency is checked again right away, without being written back.
+        if self.calculate() > 0x7FF {
+            return Err(());
+        }
+        Ok(Some(frequency))
+    }
+}
+
+/// Square wave channels 1 (0xFF10-0xFF14) and 2 (0xFF16-0xFF19), channel 2 has no sweep.
+pub struct PulseChannel {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    length: LengthCounter,
+    envelope: VolumeEnvelope,
+
+    // Bit 7-6 of NRx1.
+    duty: u8,
+    duty_position: u8,
+
+    // 11 bits from NRx3 and the lower bits of NRx4, the period is (2048 - frequency) * 4 cycles.
+    frequency: u16,
+    timer: u32,
+}
+
+impl PulseChannel {
+    pub fn new(with_sweep: bool) -> PulseChannel {
+        PulseChannel {
+            enabled: false,
+            sweep: if with_sweep {
+                Some(Sweep::default())
+            } else {
+                None
+            },
+            length: LengthCounter::new(64),
+            envelope: VolumeEnvelope::default(),
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 4
+    }
+
+    /// Reads NRx0-NRx4, `register` being 0-4. Write-only bits read back as 1.
+    pub fn read_register(&self, register: usize) -> u8 {
+        match register {
+            0 => match &self.sweep {
+                Some(sweep) => sweep.register | 0x80,
+                None => 0xFF,
+            },
+            1 => (self.duty << 6) | 0x3F,
+            2 => self.envelope.read(),
+            4 => ((self.length.is_enabled() as u8) << 6) | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes NRx0-NRx4, `frame_step` is the next step of the frame sequencer.
+    pub fn write_register(&mut self, register: usize, value: u8, frame_step: u8) {
+        match register {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    if !sweep.write(value) {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | u16::from(value),
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | (u16::from(value & 0x07) << 8);
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, frame_step & 0x01 == 0)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        // The low two bits of the timer are not reloaded, the first step comes a bit later.
+        self.timer = (self.timer & 0x03) | (self.period() & !0x03);
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Cycles until the output of the channel can next change.
+    pub fn cycles_until_step(&self) -> u32 {
+        if self.enabled {
+            self.timer
+        } else {
+            u32::MAX
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) & 0x07;
+        }
+        self.timer -= cycles;
+    }
+
+    /// Powering the APU off restarts the waveform from its first step.
+    pub fn power_off(&mut self) {
+        self.duty_position = 0;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            match sweep.clock() {
+                Ok(Some(frequency)) => self.frequency = frequency,
+                Ok(None) => {}
+                Err(()) => self.enabled = false,
+            }
+        }
+    }
+
+    /// Digital output of the channel, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_position) & 0x01;
+        high * self.envelope.volume()
+    }
+}
+
+impl SaveState for PulseChannel {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.enabled as u8);
+        if let Some(sweep) = &self.sweep {
+            out.extend_from_slice(&[sweep.register, sweep.enabled as u8, sweep.timer]);
+            out.extend_from_slice(&sweep.shadow.to_le_bytes());
+            out.push(sweep.negate_used as u8);
+        }
+        self.length.save_state(out);
+        self.envelope.save_state(out);
+        out.extend_from_slice(&[self.duty, self.duty_position]);
+        out.extend_from_slice(&self.frequency.to_le_bytes());
+        out.extend_from_slice(&self.timer.to_le_bytes());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.enabled = state.bool()?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.register = state.u8()?;
+            sweep.enabled = state.bool()?;
+            sweep.timer = state.u8()?;
+            sweep.shadow = state.u16()?;
+            sweep.negate_used = state.bool()?;
+        }
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.duty = state.u8()?;
+        self.duty_position = state.u8()?;
+        self.frequency = state.u16()?;
+        self.timer = state.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggered(with_sweep: bool, nrx1: u8, nrx2: u8, frequency: u16) -> PulseChannel {
+        let mut channel = PulseChannel::new(with_sweep);
+        channel.write_register(1, nrx1, 0);
+        channel.write_register(2, nrx2, 0);
+        channel.write_register(3, frequency as u8, 0);
+        channel.write_register(4, 0x80 | (frequency >> 8) as u8, 0);
+        channel
+    }
+
+    #[test]
+    fn duty_waveform() {
+        // 50% duty, period of 4 cycles per step.
+        let mut channel = triggered(false, 0x80, 0xF0, 0x7FF);
+        let mut wave = vec![];
+        for _ in 0..8 {
+            channel.step(4);
+            wave.push(channel.output());
+        }
+        assert_eq!(wave.iter().filter(|v| **v == 15).count(), 4);
+        assert_eq!(wave.iter().filter(|v| **v == 0).count(), 4);
+    }
+
+    #[test]
+    fn dac_off_keeps_channel_disabled() {
+        let channel = triggered(false, 0x80, 0x07, 0x700);
+        assert!(!channel.is_enabled());
+
+        let mut channel = triggered(false, 0x80, 0xF0, 0x700);
+        assert!(channel.is_enabled());
+        channel.write_register(2, 0x00, 0);
+        assert!(!channel.is_enabled());
+    }
+
+    #[test]
+    fn length_disables_channel() {
+        let mut channel = PulseChannel::new(false);
+        channel.write_register(1, 0x3E, 0);
+        channel.write_register(2, 0xF0, 0);
+        channel.write_register(4, 0xC0, 0);
+        channel.clock_length();
+        assert!(channel.is_enabled());
+        channel.clock_length();
+        assert!(!channel.is_enabled());
+    }
+
+    #[test]
+    fn register_reads() {
+        let mut channel = PulseChannel::new(true);
+        channel.write_register(0, 0x12, 0);
+        channel.write_register(1, 0x9F, 0);
+        channel.write_register(3, 0x42, 0);
+        channel.write_register(4, 0x40, 0);
+        assert_eq!(channel.read_register(0), 0x92);
+        assert_eq!(channel.read_register(1), 0xBF);
+        assert_eq!(channel.read_register(3), 0xFF);
+        assert_eq!(channel.read_register(4), 0xFF);
+        assert_eq!(PulseChannel::new(false).read_register(0), 0xFF);
+    }
+
+    #[test]
+    fn sweep_overflow_on_trigger() {
+        let mut channel = PulseChannel::new(true);
+        channel.write_register(0, 0x11, 0);
+        channel.write_register(2, 0xF0, 0);
+        channel.write_register(3, 0xFF, 0);
+        channel.write_register(4, 0x87, 0);
+        assert!(!channel.is_enabled());
+    }
+
+    #[test]
+    fn sweep_raises_frequency() {
+        let mut channel = PulseChannel::new(true);
+        channel.write_register(0, 0x11, 0);
+        channel.write_register(2, 0xF0, 0);
+        channel.write_register(3, 0x00, 0);
+        channel.write_register(4, 0x81, 0);
+        channel.clock_sweep();
+        assert_eq!(channel.frequency, 0x180);
+        assert!(channel.is_enabled());
+    }
+
+    #[test]
+    fn negate_mode_quirk() {
+        let mut channel = PulseChannel::new(true);
+        channel.write_register(0, 0x19, 0);
+        channel.write_register(2, 0xF0, 0);
+        channel.write_register(3, 0x00, 0);
+        channel.write_register(4, 0x84, 0);
+        channel.clock_sweep();
+        assert_eq!(channel.frequency, 0x200);
+        assert!(channel.is_enabled());
+
+        // Going back to addition after a subtraction disables the channel.
+        channel.write_register(0, 0x11, 0);
+        assert!(!channel.is_enabled());
+    }
+}
@@ -2,6 +2,8 @@ use std::cmp::PartialEq;
 use std::ops::BitXor;
 use std::ops::Shl;
 
+use crate::save_state::{SaveState, StateReader};
+
 // Description of register of GB.
 // -------------
 // | A   Flags |  ---> Program Status Word
@@ -38,7 +40,7 @@ pub enum ConditionOperand {
     C,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Default, Debug, PartialEq, Eq)]
 pub struct CpuFlag {
     // Carry Flag. This bit is set if a carry occurred from the last math operation or if register A is the smaller valuewhen executing the CP instruction.
     pub carry: bool,
@@ -96,6 +98,8 @@ pub fn convert<T: PartialEq + From<u8> + BitXor<Output = T> + Shl<Output = T> +
     }))
 }
 
+// Default is the state of the registers at power on, before running the boot ROM.
+#[derive(Default)]
 pub struct Registers {
     pub a: u8,
     pub flags: CpuFlag,
@@ -212,6 +216,31 @@ impl Registers {
     }
 }
 
+impl SaveState for Registers {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        for word in [
+            self.af(),
+            self.bc(),
+            self.de(),
+            self.hl(),
+            self.stack_pointer,
+            self.program_counter,
+        ] {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.set_af(state.u16()?);
+        self.set_bc(state.u16()?);
+        self.set_de(state.u16()?);
+        self.set_hl(state.u16()?);
+        self.stack_pointer = state.u16()?;
+        self.program_counter = state.u16()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{CpuFlag, Registers};
@@ -0,0 +1,356 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::save_state::{SaveState, StateReader};
+
+// The RTC runs from its own 32768 Hz crystal, we count it in master clock cycles
+// since the MMU hands out time in that unit.
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+// Size of the footer appended to `.sav` files by VBA-M and BGB, the 64-bit timestamp one.
+pub const FOOTER_LEN: usize = 48;
+// Older variant of the same footer with a 32-bit timestamp.
+pub const SHORT_FOOTER_LEN: usize = 44;
+
+/// Registers exposed by the MBC3 clock, mapped in 0xA000-0xBFFF when selected with 0x08-0x0C.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RtcRegisters {
+    // 0x08: Seconds 0-59 (0-3Bh)
+    pub seconds: u8,
+    // 0x09: Minutes 0-59 (0-3Bh)
+    pub minutes: u8,
+    // 0x0A: Hours 0-23 (0-17h)
+    pub hours: u8,
+    // 0x0B: Lower 8 bits of Day Counter (0-FFh)
+    // 0x0C: Upper 1 bit of Day Counter, Carry Bit, Halt Flag
+    //  - Bit 0: Most significant bit of Day Counter (Bit 8)
+    //  - Bit 6: Halt (0=Active, 1=Stop Timer)
+    //  - Bit 7: Day Counter Carry Bit (1=Counter Overflow)
+    pub days: u16,
+    pub halt: bool,
+    pub day_carry: bool,
+}
+
+impl RtcRegisters {
+    fn day_high(&self) -> u8 {
+        (self.days >> 8) as u8 & 0x01 | (self.halt as u8) << 6 | (self.day_carry as u8) << 7
+    }
+
+    fn set_day_high(&mut self, value: u8) {
+        self.days = (self.days & 0x00FF) | (u16::from(value & 0x01) << 8);
+        self.halt = value & 0x40 != 0;
+        self.day_carry = value & 0x80 != 0;
+    }
+
+    fn read(&self, select: u8) -> u8 {
+        match select {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => self.day_high(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, select: u8, value: u8) {
+        match select {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x0100) | u16::from(value),
+            0x0C => self.set_day_high(value),
+            _ => {}
+        }
+    }
+
+    // Counters only carry when they hit exactly their limit, a value written out of range
+    // keeps counting up to the register width and wraps to 0 without carrying.
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days += 1;
+        if self.days == 0x200 {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+
+    fn add_seconds(&mut self, elapsed: u64) {
+        let total = elapsed
+            + u64::from(self.seconds)
+            + u64::from(self.minutes) * 60
+            + u64::from(self.hours) * 3600;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+
+        let days = u64::from(self.days) + total / 86400;
+        if days >= 0x200 {
+            self.day_carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for v in [
+            u32::from(self.seconds),
+            u32::from(self.minutes),
+            u32::from(self.hours),
+            u32::from(self.days as u8),
+            u32::from(self.day_high()),
+        ] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn decode(data: &[u8]) -> RtcRegisters {
+        let field = |i: usize| data[i * 4];
+        let mut regs = RtcRegisters::default();
+        regs.write(0x08, field(0));
+        regs.write(0x09, field(1));
+        regs.write(0x0A, field(2));
+        regs.write(0x0B, field(3));
+        regs.write(0x0C, field(4));
+        regs
+    }
+}
+
+/// Real time clock of MBC3 cartridges.
+/// The live registers keep counting, while the game reads a copy that is frozen (latched)
+/// writing 0x00 and then 0x01 into 0x6000-0x7FFF.
+#[derive(Debug, Default)]
+pub struct RealTimeClock {
+    live: RtcRegisters,
+    latched: RtcRegisters,
+    latch_armed: bool,
+    cycles: u32,
+}
+
+impl RealTimeClock {
+    pub fn step(&mut self, cycles: u32) {
+        if self.live.halt {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.live.tick();
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.live;
+        }
+
+        self.latch_armed = value == 0x00;
+    }
+
+    pub fn read(&self, select: u8) -> u8 {
+        self.latched.read(select)
+    }
+
+    pub fn write(&mut self, select: u8, value: u8) {
+        // Writing seconds also resets the internal sub-second divider.
+        if select == 0x08 {
+            self.cycles = 0;
+        }
+
+        self.live.write(select, value);
+        self.latched.write(select, value);
+    }
+
+    /// Advances the clock by real time elapsed while the emulator was not running.
+    pub fn fast_forward(&mut self, elapsed: u64) {
+        if !self.live.halt {
+            self.live.add_seconds(elapsed);
+        }
+    }
+
+    /// Footer compatible with VBA-M and BGB: live and latched registers as 32-bit
+    /// little endian values followed by the 64-bit UNIX timestamp of the save.
+    pub fn footer(&self, timestamp: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FOOTER_LEN);
+        self.live.encode(&mut out);
+        self.latched.encode(&mut out);
+        out.extend_from_slice(&timestamp.to_le_bytes());
+        out
+    }
+
+    /// Parses a 48 or 44 bytes footer, returning the clock and the timestamp stored in it.
+    pub fn from_footer(data: &[u8]) -> Result<(RealTimeClock, u64), std::io::Error> {
+        let timestamp = match data.len() {
+            FOOTER_LEN => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            SHORT_FOOTER_LEN => u64::from(u32::from_le_bytes(data[40..44].try_into().unwrap())),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("rtc footer can't be {} bytes long.", data.len()),
+                ))
+            }
+        };
+
+        let rtc = RealTimeClock {
+            live: RtcRegisters::decode(&data[0..20]),
+            latched: RtcRegisters::decode(&data[20..40]),
+            latch_armed: false,
+            cycles: 0,
+        };
+
+        Ok((rtc, timestamp))
+    }
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl SaveState for RtcRegisters {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.seconds, self.minutes, self.hours]);
+        out.extend_from_slice(&self.days.to_le_bytes());
+        out.extend_from_slice(&[self.halt as u8, self.day_carry as u8]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.seconds = state.u8()?;
+        self.minutes = state.u8()?;
+        self.hours = state.u8()?;
+        self.days = state.u16()?;
+        self.halt = state.bool()?;
+        self.day_carry = state.bool()?;
+        Ok(())
+    }
+}
+
+// Unlike the save file the clock doesn't catch up with real time: a state loaded later
+// carries on from the same second.
+impl SaveState for RealTimeClock {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.live.save_state(out);
+        self.latched.save_state(out);
+        out.push(self.latch_armed as u8);
+        out.extend_from_slice(&self.cycles.to_le_bytes());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.live.load_state(state)?;
+        self.latched.load_state(state)?;
+        self.latch_armed = state.bool()?;
+        self.cycles = state.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_rolls_over() {
+        let mut rtc = RealTimeClock::default();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.step(CYCLES_PER_SECOND);
+
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(rtc.read(0x09), 0);
+        assert_eq!(rtc.read(0x0A), 0);
+        assert_eq!(rtc.read(0x0B), 0);
+        assert_eq!(rtc.read(0x0C), 0x80);
+    }
+
+    #[test]
+    fn halt_stops_counting() {
+        let mut rtc = RealTimeClock::default();
+        rtc.write(0x0C, 0x40);
+        rtc.step(CYCLES_PER_SECOND * 3);
+        rtc.fast_forward(100);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+    }
+
+    #[test]
+    fn latch_needs_zero_then_one() {
+        let mut rtc = RealTimeClock::default();
+        rtc.step(CYCLES_PER_SECOND);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 1);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = RealTimeClock::default();
+        rtc.write(0x08, 12);
+        rtc.write(0x09, 34);
+        rtc.write(0x0A, 5);
+        rtc.write(0x0B, 0x42);
+        rtc.write(0x0C, 0x81);
+        let footer = rtc.footer(1_600_000_000);
+        assert_eq!(footer.len(), FOOTER_LEN);
+        assert_eq!(&footer[0..4], &[12, 0, 0, 0]);
+        assert_eq!(&footer[16..20], &[0x81, 0, 0, 0]);
+
+        let (loaded, timestamp) = RealTimeClock::from_footer(&footer).unwrap();
+        assert_eq!(timestamp, 1_600_000_000);
+        assert_eq!(loaded.live, rtc.live);
+        assert_eq!(loaded.latched, rtc.latched);
+    }
+
+    #[test]
+    fn short_footer() {
+        let rtc = RealTimeClock::default();
+        let mut footer = rtc.footer(0);
+        footer.truncate(40);
+        footer.extend_from_slice(&1_234_u32.to_le_bytes());
+        let (_, timestamp) = RealTimeClock::from_footer(&footer).unwrap();
+        assert_eq!(timestamp, 1_234);
+
+        assert!(RealTimeClock::from_footer(&footer[..30]).is_err());
+    }
+
+    #[test]
+    fn fast_forward_elapsed_time() {
+        let mut rtc = RealTimeClock::default();
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.fast_forward(86400 + 3600 + 61);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 1);
+        assert_eq!(rtc.read(0x09), 1);
+        assert_eq!(rtc.read(0x0A), 1);
+        assert_eq!(rtc.read(0x0B), 0);
+        assert_eq!(rtc.read(0x0C), 0x80);
+    }
+}
@@ -0,0 +1,81 @@
+/// A part of the console that goes in a save state: what it takes to carry on from where it
+/// was. What's plugged into it and the sound already sent out are left out.
+pub trait SaveState {
+    fn save_state(&self, out: &mut Vec<u8>);
+
+    /// Restores what `save_state` wrote, read in the same order.
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error>;
+}
+
+/// Reads a save state a field at a time.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], std::io::Error> {
+        if self.data.len() < len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "save state is cut short.",
+            ));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Reads as many bytes as `out` holds, like RAM saved whole.
+    pub fn fill(&mut self, out: &mut [u8]) -> Result<(), std::io::Error> {
+        out.copy_from_slice(self.bytes(out.len())?);
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, std::io::Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, std::io::Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, std::io::Error> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, std::io::Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_in_order() {
+        let mut out = vec![0x01, 0x00];
+        out.extend_from_slice(&0xBEEFu16.to_le_bytes());
+        out.extend_from_slice(&0x12345678u32.to_le_bytes());
+        out.extend_from_slice(&[0xAA, 0xBB]);
+
+        let mut state = StateReader::new(&out);
+        assert!(state.bool().unwrap());
+        assert_eq!(state.u8().unwrap(), 0x00);
+        assert_eq!(state.u16().unwrap(), 0xBEEF);
+        assert_eq!(state.u32().unwrap(), 0x12345678);
+        let mut ram = [0; 2];
+        state.fill(&mut ram).unwrap();
+        assert_eq!(ram, [0xAA, 0xBB]);
+        assert!(state.is_empty());
+        assert!(state.u8().is_err());
+    }
+}
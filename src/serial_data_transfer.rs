@@ -1,6 +1,36 @@
-use crate::memory_device::ReadWrite;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::{
+    interrupt::{InterruptFlag, InterruptKind},
+    memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
+};
+
+// Cycles per bit with the internal clock: 8192 Hz, or 262144 Hz with the CGB fast clock.
+// Both double in CGB double speed, as they count CPU cycles.
+const NORMAL_BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+/// The other end of the link cable. Bits go out most significant first, one for each
+/// pulse of the shift clock, while a bit of the peer comes in.
+pub trait SerialPeer {
+    /// With the internal clock this side drives the pulse: sends `bit` and gets the one of the peer.
+    fn exchange_bit(&mut self, bit: bool) -> bool;
+
+    /// With the external clock the peer drives it: `Some` with its bit when it sent a pulse
+    /// since the last call, in which case `bit` went to it.
+    fn clocked_bit(&mut self, bit: bool) -> Option<bool>;
+
+    /// Called as `cycles` go by, before any bit is exchanged in that time.
+    fn step(&mut self, _cycles: u32) {}
+
+    /// Called after each step with the content of SB and the bits left to shift while this
+    /// side waits for the external clock.
+    fn set_listening(&mut self, _listening: Option<(u8, u8)>) {}
+}
 
-#[derive(Default)]
 pub struct SerialDataTransfer {
     // Before a transfer, it holds the next byte that will go out: 0xFF01
     // During a transfer, it has a blend of the outgoing and incoming bytes.
@@ -12,20 +42,121 @@ pub struct SerialDataTransfer {
     // Bit 1 - Clock Speed (0=Normal, 1=Fast) ** CGB Mode Only **
     // Bit 7 - Transfer Start Flag (0=No transfer is in progress or requested, 1=Transfer in progress, or requested)
     control: u8,
+    cgb: bool,
+
+    // Bits left in the transfer in progress and cycles until the next one with the internal clock.
+    bits_left: u8,
+    bit_timer: u32,
+    // Byte being sent, for the sink.
+    outgoing: u8,
 
-    debug_msg: String,
+    interrupt_flag: Rc<RefCell<InterruptFlag>>,
+    // Without a cable every bit coming in is 1.
+    peer: Option<Box<dyn SerialPeer>>,
+    // Receives each byte sent, test ROMs print their results this way.
+    sink: Option<Box<dyn Write>>,
 }
 
 impl SerialDataTransfer {
-    pub fn print_serial_debug(&mut self) {
-        if self.read_byte(0xFF02).unwrap() == 0x81 {
-            let ch = self.read_byte(0xFF01).unwrap();
-            self.debug_msg.push(ch as char);
-            self.write_byte(0xFF02, 0).unwrap();
+    pub fn new(interrupt_flag: Rc<RefCell<InterruptFlag>>, cgb: bool) -> SerialDataTransfer {
+        SerialDataTransfer {
+            data: 0,
+            control: 0,
+            cgb,
+            bits_left: 0,
+            bit_timer: 0,
+            outgoing: 0,
+            interrupt_flag,
+            peer: None,
+            sink: None,
+        }
+    }
+
+    pub fn set_sink(&mut self, sink: Box<dyn Write>) {
+        self.sink = Some(sink);
+    }
+
+    /// Plugs the link cable into the port.
+    pub fn connect(&mut self, peer: Box<dyn SerialPeer>) {
+        self.peer = Some(peer);
+    }
+
+    fn bit_cycles(&self) -> u32 {
+        if self.cgb && self.control & 0x02 != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            NORMAL_BIT_CYCLES
+        }
+    }
+
+    fn internal_clock(&self) -> bool {
+        self.control & 0x01 != 0
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if let Some(peer) = &mut self.peer {
+            peer.step(cycles);
+        }
+
+        self.shift_bits(cycles);
+
+        let listening = if self.bits_left > 0 && !self.internal_clock() {
+            Some((self.data, self.bits_left))
+        } else {
+            None
+        };
+        if let Some(peer) = &mut self.peer {
+            peer.set_listening(listening);
+        }
+    }
+
+    fn shift_bits(&mut self, cycles: u32) {
+        if self.bits_left == 0 {
+            return;
+        }
+
+        if self.internal_clock() {
+            let mut cycles = cycles;
+            while self.bits_left > 0 && cycles >= self.bit_timer {
+                cycles -= self.bit_timer;
+                self.bit_timer = self.bit_cycles();
+                let bit = match &mut self.peer {
+                    Some(peer) => peer.exchange_bit(self.data & 0x80 != 0),
+                    None => true,
+                };
+                self.shift(bit);
+            }
+            if self.bits_left > 0 {
+                self.bit_timer -= cycles;
+            }
+        } else {
+            while self.bits_left > 0 {
+                let bit = match &mut self.peer {
+                    Some(peer) => peer.clocked_bit(self.data & 0x80 != 0),
+                    None => None,
+                };
+                match bit {
+                    Some(bit) => self.shift(bit),
+                    None => break,
+                }
+            }
+        }
+    }
+
+    fn shift(&mut self, bit: bool) {
+        self.data = (self.data << 1) | bit as u8;
+        self.bits_left -= 1;
+        if self.bits_left > 0 {
+            return;
         }
 
-        if !self.debug_msg.is_empty() {
-            println!("DGB: {}", self.debug_msg);
+        self.control &= 0x7F;
+        self.interrupt_flag
+            .borrow_mut()
+            .request(InterruptKind::Serial);
+        if let Some(sink) = &mut self.sink {
+            // A debug aid, a sink failing must not stop the emulation.
+            let _ = sink.write_all(&[self.outgoing]).and_then(|_| sink.flush());
         }
     }
 }
@@ -38,7 +169,9 @@ impl ReadWrite for SerialDataTransfer {
     fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
         match address {
             0xFF01 => Ok(self.data),
-            0xFF02 => Ok(self.control),
+            // Unused bits read as 1, the clock speed too before CGB.
+            0xFF02 if self.cgb => Ok(self.control | 0x7C),
+            0xFF02 => Ok(self.control | 0x7E),
             _ => unimplemented!(),
         }
     }
@@ -50,7 +183,16 @@ impl ReadWrite for SerialDataTransfer {
     fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
         match address {
             0xFF01 => self.data = value,
-            0xFF02 => self.control = value,
+            0xFF02 => {
+                self.control = value & if self.cgb { 0x83 } else { 0x81 };
+                if self.control & 0x80 != 0 {
+                    self.bits_left = 8;
+                    self.bit_timer = self.bit_cycles();
+                    self.outgoing = self.data;
+                } else {
+                    self.bits_left = 0;
+                }
+            }
             _ => unimplemented!(),
         }
 
@@ -61,3 +203,139 @@ impl ReadWrite for SerialDataTransfer {
         unimplemented!()
     }
 }
+
+impl SaveState for SerialDataTransfer {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.data, self.control, self.bits_left]);
+        out.extend_from_slice(&self.bit_timer.to_le_bytes());
+        out.push(self.outgoing);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.data = state.u8()?;
+        self.control = state.u8()?;
+        self.bits_left = state.u8()?;
+        self.bit_timer = state.u32()?;
+        self.outgoing = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends back the bits of `data` and records the ones received, clocking `pulses`
+    // bits on its own when it drives the clock.
+    struct Peer {
+        data: u8,
+        received: Rc<RefCell<u8>>,
+        pulses: u8,
+    }
+
+    impl SerialPeer for Peer {
+        fn exchange_bit(&mut self, bit: bool) -> bool {
+            let out = self.data & 0x80 != 0;
+            self.data <<= 1;
+            let mut received = self.received.borrow_mut();
+            *received = (*received << 1) | bit as u8;
+            out
+        }
+
+        fn clocked_bit(&mut self, bit: bool) -> Option<bool> {
+            if self.pulses == 0 {
+                return None;
+            }
+            self.pulses -= 1;
+            Some(self.exchange_bit(bit))
+        }
+    }
+
+    fn serial(cgb: bool) -> (SerialDataTransfer, Rc<RefCell<InterruptFlag>>) {
+        let interrupt_flag = Rc::new(RefCell::new(InterruptFlag::default()));
+        (
+            SerialDataTransfer::new(interrupt_flag.clone(), cgb),
+            interrupt_flag,
+        )
+    }
+
+    #[test]
+    fn no_cable_reads_ones() {
+        let (mut serial, interrupt_flag) = serial(false);
+        serial.write_byte(0xFF01, 0x42).unwrap();
+        serial.write_byte(0xFF02, 0x81).unwrap();
+        assert_eq!(serial.read_byte(0xFF02).unwrap(), 0xFF);
+
+        serial.step(512 * 4);
+        assert_eq!(serial.read_byte(0xFF01).unwrap(), 0x2F);
+        assert_eq!(interrupt_flag.borrow().data, 0x00);
+        serial.step(512 * 4 - 1);
+        assert_eq!(serial.read_byte(0xFF02).unwrap(), 0xFF);
+        serial.step(1);
+        assert_eq!(serial.read_byte(0xFF01).unwrap(), 0xFF);
+        assert_eq!(serial.read_byte(0xFF02).unwrap(), 0x7F);
+        assert_eq!(interrupt_flag.borrow().data, 0x08);
+    }
+
+    #[test]
+    fn internal_clock_exchanges_with_peer() {
+        let (mut serial, _) = serial(true);
+        let received = Rc::new(RefCell::new(0));
+        serial.peer = Some(Box::new(Peer {
+            data: 0xA5,
+            received: received.clone(),
+            pulses: 0,
+        }));
+        serial.write_byte(0xFF01, 0x3C).unwrap();
+        // CGB fast clock, 16 cycles per bit.
+        serial.write_byte(0xFF02, 0x83).unwrap();
+        serial.step(16 * 8);
+        assert_eq!(serial.read_byte(0xFF01).unwrap(), 0xA5);
+        assert_eq!(*received.borrow(), 0x3C);
+        assert_eq!(serial.read_byte(0xFF02).unwrap(), 0x7F);
+    }
+
+    #[test]
+    fn external_clock_waits_for_peer() {
+        let (mut serial, interrupt_flag) = serial(false);
+        serial.write_byte(0xFF01, 0x3C).unwrap();
+        serial.write_byte(0xFF02, 0x80).unwrap();
+        serial.step(100_000);
+        assert_eq!(serial.read_byte(0xFF02).unwrap(), 0xFE);
+
+        let received = Rc::new(RefCell::new(0));
+        serial.peer = Some(Box::new(Peer {
+            data: 0xA5,
+            received: received.clone(),
+            pulses: 8,
+        }));
+        serial.step(4);
+        assert_eq!(serial.read_byte(0xFF01).unwrap(), 0xA5);
+        assert_eq!(*received.borrow(), 0x3C);
+        assert_eq!(interrupt_flag.borrow().data, 0x08);
+    }
+
+    #[test]
+    fn sink_gets_bytes_sent() {
+        struct Sink(Rc<RefCell<Vec<u8>>>);
+        impl Write for Sink {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (mut serial, _) = serial(false);
+        let out = Rc::new(RefCell::new(vec![]));
+        serial.set_sink(Box::new(Sink(out.clone())));
+        for c in b"ok" {
+            serial.write_byte(0xFF01, *c).unwrap();
+            serial.write_byte(0xFF02, 0x81).unwrap();
+            serial.step(512 * 8);
+        }
+        assert_eq!(*out.borrow(), b"ok");
+    }
+}
@@ -1,4 +1,36 @@
-use crate::memory_device::ReadWrite;
+use crate::{
+    audio_recorder::AudioRecorder,
+    blip_buffer::BlipBuffer,
+    memory_device::ReadWrite,
+    model::Model,
+    noise_channel::NoiseChannel,
+    pulse_channel::PulseChannel,
+    save_state::{SaveState, StateReader},
+    wave_channel::WaveChannel,
+};
+
+// Master clock of the APU, channel timers run at this rate.
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// Charge factor of the capacitor blocking DC on the outputs, per master clock cycle.
+// On CGB the capacitor discharges faster: 0.998943 per sample at 44.1 kHz.
+const DMG_CHARGE_PER_CYCLE: f64 = 0.999958;
+const CGB_CHARGE_PER_CYCLE: f64 = 0.999989;
+
+// Samples kept when nobody drains them, one second of stereo audio.
+const MAX_BUFFERED_SAMPLES: usize = DEFAULT_SAMPLE_RATE as usize * 2;
+
+// Cycles between two reads of the resampled output, about 4 ms.
+const BLIP_FRAME_CYCLES: u32 = 16_384;
+
+// Outputs of the channels before panning and volume, resampled like the mix,
+// for recording each channel on its own.
+struct Stems {
+    blip: [BlipBuffer; 4],
+    last_output: [f32; 4],
+    capacitor: [f32; 4],
+}
 
 pub struct Sound {
     /// On/Off sound 0xFF26.
@@ -7,7 +39,9 @@ pub struct Sound {
     /// Bit 2 - Sound 3 ON flag (Read Only)
     /// Bit 1 - Sound 2 ON flag (Read Only)
     /// Bit 0 - Sound 1 ON flag (Read Only)
-    on: u8, // TODO: create dedicated struct for better reading code.
+    /// Turning sound off clears all the registers from 0xFF10 to 0xFF25 and ignores writes
+    /// to them until it's turned back on, wave RAM is left untouched.
+    on: bool,
 
     /// Each channel can be panned hard left, center, or hard right 0xFF25.
     /// Bit 7 - Output sound 4 to SO2 terminal
@@ -18,6 +52,7 @@ pub struct Sound {
     /// Bit 2 - Output sound 3 to SO1 terminal
     /// Bit 1 - Output sound 2 to SO1 terminal
     /// Bit 0 - Output sound 1 to SO1 terminal
+    /// SO2 is the left output, SO1 the right one.
     sound_output: u8,
 
     /// Channel volume control 0xFF24
@@ -25,29 +60,439 @@ pub struct Sound {
     /// Bit 6-4 - SO2 output level (volume)  (0-7)
     /// Bit 3   - Output Vin to SO1 terminal (1=Enable)
     /// Bit 2-0 - SO1 output level (volume)  (0-7)
+    /// Vin is the audio input from the cartridge, no emulated cartridge drives it.
     channel_control: u8,
+
+    /// Channel 1, square wave with sweep: 0xFF10-0xFF14.
+    channel1: PulseChannel,
+    /// Channel 2, square wave: 0xFF16-0xFF19.
+    channel2: PulseChannel,
+    /// Channel 3, wave output: 0xFF1A-0xFF1E, with wave pattern RAM at 0xFF30-0xFF3F
+    /// holding 32 4-bit samples, upper nibble first.
+    channel3: WaveChannel,
+    /// Channel 4, noise: 0xFF20-0xFF23.
+    channel4: NoiseChannel,
+
+    // Next step of the frame sequencer, it is clocked at 512 Hz by DIV and in turn
+    // clocks the units of the channels:
+    // Step   Length Ctr  Vol Env     Sweep
+    // 0      Clock       -           -
+    // 1      -           -           -
+    // 2      Clock       -           Clock
+    // 3      -           -           -
+    // 4      Clock       -           -
+    // 5      -           -           -
+    // 6      Clock       -           Clock
+    // 7      -           Clock       -
+    frame_step: u8,
+
+    // On CGB length counters are cleared with power off and the DC filter is different.
+    cgb: bool,
+
+    // Changes of the mixed output are band-limited and resampled to `sample_rate`,
+    // one buffer per terminal. `frame_cycles` counts the cycles since they were last read.
+    sample_rate: u32,
+    blip: (BlipBuffer, BlipBuffer),
+    frame_cycles: u32,
+    rate_adjustment: f64,
+    last_output: (f32, f32),
+    // Output samples, interleaved left and right.
+    samples: Vec<f32>,
+    charge: f32,
+    capacitor: (f32, f32),
+
+    // Last values written to 0xFF10-0xFF25, write-only bits included.
+    written: [u8; 0x16],
+
+    // Muted channels are left out of the mix, not out of their stems.
+    muted: [bool; 4],
+    recorder: Option<AudioRecorder>,
+    stems: Option<Stems>,
 }
 
 impl Sound {
-    pub fn new() -> Sound {
-        Sound {
-            on: 0,
+    pub fn new(model: Model) -> Sound {
+        let mut sound = Sound {
+            on: false,
             sound_output: 0,
             channel_control: 0,
+            channel1: PulseChannel::new(true),
+            channel2: PulseChannel::new(false),
+            channel3: WaveChannel::new(model.is_cgb()),
+            channel4: NoiseChannel::new(),
+            frame_step: 0,
+            cgb: model.is_cgb(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            blip: (
+                BlipBuffer::new(CYCLES_PER_SECOND, DEFAULT_SAMPLE_RATE),
+                BlipBuffer::new(CYCLES_PER_SECOND, DEFAULT_SAMPLE_RATE),
+            ),
+            frame_cycles: 0,
+            rate_adjustment: 0.0,
+            last_output: (0.0, 0.0),
+            samples: vec![],
+            charge: 0.0,
+            capacitor: (0.0, 0.0),
+            written: [0; 0x16],
+            muted: [false; 4],
+            recorder: None,
+            stems: None,
+        };
+        sound.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        sound
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let per_cycle = if self.cgb {
+            CGB_CHARGE_PER_CYCLE
+        } else {
+            DMG_CHARGE_PER_CYCLE
+        };
+        self.sample_rate = sample_rate;
+        self.rate_adjustment = 0.0;
+        self.blip.0.set_sample_rate(sample_rate);
+        self.blip.1.set_sample_rate(sample_rate);
+        self.charge = per_cycle.powf(f64::from(CYCLES_PER_SECOND) / f64::from(sample_rate)) as f32;
+    }
+
+    /// Nudges the output rate by `adjustment`, clamped to ±0.5%, so a frontend can keep its
+    /// audio buffer from running dry (positive) or overflowing (negative).
+    pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+        self.rate_adjustment = adjustment;
+        if let Some(stems) = &mut self.stems {
+            for blip in stems.blip.iter_mut() {
+                blip.set_rate_adjustment(adjustment);
+            }
+        }
+        self.blip.0.set_rate_adjustment(adjustment);
+        self.blip.1.set_rate_adjustment(adjustment);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Leaves channel `channel` (0-3) out of the mix, or puts it back.
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel] = muted;
+    }
+
+    /// Starts writing the output to `recorder`, samples produced before aren't recorded.
+    pub fn start_recording(&mut self, recorder: AudioRecorder) {
+        self.stems = if recorder.has_stems() {
+            let new_blip = || {
+                let mut blip = BlipBuffer::new(CYCLES_PER_SECOND, self.sample_rate);
+                blip.set_rate_adjustment(self.rate_adjustment);
+                blip
+            };
+            Some(Stems {
+                blip: [new_blip(), new_blip(), new_blip(), new_blip()],
+                last_output: [0.0; 4],
+                capacitor: [0.0; 4],
+            })
+        } else {
+            None
+        };
+        self.recorder = Some(recorder);
+    }
+
+    /// Stops recording, the recorder still has to be finished.
+    pub fn stop_recording(&mut self) -> Option<AudioRecorder> {
+        self.stems = None;
+        self.recorder.take()
+    }
+
+    /// Takes the samples produced so far, interleaved left and right.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        // Register writes since the last step take effect now.
+        self.update_output();
+
+        let mut cycles = cycles;
+        while cycles > 0 {
+            // Run up to the next channel timer so every edge lands at its exact cycle.
+            let run = cycles.min(self.cycles_until_step().max(1));
+            self.step_channels(run);
+            cycles -= run;
+            self.frame_cycles += run;
+            self.update_output();
+        }
+
+        if self.frame_cycles >= BLIP_FRAME_CYCLES {
+            self.end_frame();
+        }
+    }
+
+    fn cycles_until_step(&self) -> u32 {
+        if !self.on {
+            return u32::MAX;
+        }
+
+        self.channel1
+            .cycles_until_step()
+            .min(self.channel2.cycles_until_step())
+            .min(self.channel3.cycles_until_step())
+            .min(self.channel4.cycles_until_step())
+    }
+
+    fn update_output(&mut self) {
+        let (left, right) = self.mix();
+        if left != self.last_output.0 {
+            self.blip
+                .0
+                .add_delta(self.frame_cycles, left - self.last_output.0);
+        }
+        if right != self.last_output.1 {
+            self.blip
+                .1
+                .add_delta(self.frame_cycles, right - self.last_output.1);
+        }
+        self.last_output = (left, right);
+
+        if let Some(stems) = &mut self.stems {
+            let outputs = [
+                analog(self.channel1.dac_enabled(), self.channel1.output()),
+                analog(self.channel2.dac_enabled(), self.channel2.output()),
+                analog(self.channel3.dac_enabled(), self.channel3.output()),
+                analog(self.channel4.dac_enabled(), self.channel4.output()),
+            ];
+            for (i, output) in outputs.iter().enumerate() {
+                if *output != stems.last_output[i] {
+                    stems.blip[i].add_delta(self.frame_cycles, output - stems.last_output[i]);
+                    stems.last_output[i] = *output;
+                }
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.blip.0.end_frame(self.frame_cycles);
+        self.blip.1.end_frame(self.frame_cycles);
+
+        let (mut left, mut right) = (vec![], vec![]);
+        self.blip.0.read_samples(&mut left);
+        self.blip.1.read_samples(&mut right);
+        let mut samples = Vec::with_capacity(left.len() * 2);
+        for (left, right) in left.into_iter().zip(right) {
+            samples.push(high_pass(&mut self.capacitor.0, self.charge, left));
+            samples.push(high_pass(&mut self.capacitor.1, self.charge, right));
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_mix(&samples);
+        }
+        self.push_samples(&samples);
+
+        if let Some(stems) = &mut self.stems {
+            for i in 0..4 {
+                stems.blip[i].end_frame(self.frame_cycles);
+                let mut samples = vec![];
+                stems.blip[i].read_samples(&mut samples);
+                for sample in samples.iter_mut() {
+                    *sample = high_pass(&mut stems.capacitor[i], self.charge, *sample);
+                }
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.write_stem(i, &samples);
+                }
+            }
+        }
+        self.frame_cycles = 0;
+    }
+
+    fn step_channels(&mut self, cycles: u32) {
+        if !self.on {
+            return;
+        }
+
+        self.channel1.step(cycles);
+        self.channel2.step(cycles);
+        self.channel3.step(cycles);
+        self.channel4.step(cycles);
+    }
+
+    /// Clocked by the falling edge of DIV bit 4 (bit 5 in double speed), 512 Hz.
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.on {
+            return;
+        }
+
+        if self.frame_step & 0x01 == 0 {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    fn channel_status(&self) -> u8 {
+        self.channel1.is_enabled() as u8
+            | (self.channel2.is_enabled() as u8) << 1
+            | (self.channel3.is_enabled() as u8) << 2
+            | (self.channel4.is_enabled() as u8) << 3
+    }
+
+    // Channels are summed on the terminals selected by NR51 and scaled by the NR50 volume.
+    fn mix(&self) -> (f32, f32) {
+        let channels = [
+            (self.channel1.dac_enabled(), self.channel1.output()),
+            (self.channel2.dac_enabled(), self.channel2.output()),
+            (self.channel3.dac_enabled(), self.channel3.output()),
+            (self.channel4.dac_enabled(), self.channel4.output()),
+        ];
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, (dac_enabled, output)) in channels.iter().enumerate() {
+            if self.muted[i] {
+                continue;
+            }
+            let analog = analog(*dac_enabled, *output);
+            if self.sound_output & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if self.sound_output & (0x01 << i) != 0 {
+                right += analog;
+            }
         }
+
+        let left_volume = f32::from((self.channel_control >> 4) & 0x07) + 1.0;
+        let right_volume = f32::from(self.channel_control & 0x07) + 1.0;
+        (
+            left / 4.0 * left_volume / 8.0,
+            right / 4.0 * right_volume / 8.0,
+        )
+    }
+
+    fn push_samples(&mut self, samples: &[f32]) {
+        if self.samples.len() + samples.len() > MAX_BUFFERED_SAMPLES {
+            self.samples.drain(..MAX_BUFFERED_SAMPLES / 2);
+        }
+        self.samples.extend_from_slice(samples);
+    }
+
+    fn power_off(&mut self) {
+        for address in 0xFF10..=0xFF25 {
+            // On DMG length counters keep running with the power off.
+            if !self.cgb && matches!(address, 0xFF11 | 0xFF16 | 0xFF1B | 0xFF20) {
+                continue;
+            }
+            self.write_register(address, 0x00);
+        }
+        self.channel1.power_off();
+        self.channel2.power_off();
+        self.on = false;
     }
+
+    /// Writes that bring a sound unit just powered on to the current state of this one,
+    /// channels playing are triggered again.
+    pub fn register_writes(&self) -> Vec<(usize, u8)> {
+        let mut writes = vec![(0xFF26, (self.on as u8) << 7)];
+        for (i, value) in self.channel3.wave_ram().iter().enumerate() {
+            writes.push((0xFF30 + i, *value));
+        }
+        if !self.on {
+            return writes;
+        }
+
+        let status = self.channel_status();
+        for (i, value) in self.written.iter().enumerate() {
+            let address = 0xFF10 + i;
+            let value = match address {
+                0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => {
+                    let channel = (address - 0xFF14) / 5;
+                    (value & 0x7F)
+                        | if status & (1 << channel) != 0 {
+                            0x80
+                        } else {
+                            0
+                        }
+                }
+                _ => *value,
+            };
+            writes.push((address, value));
+        }
+        writes
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) {
+        if let Some(written) = self.written.get_mut(address.wrapping_sub(0xFF10)) {
+            *written = value;
+        }
+        match address {
+            0xFF10..=0xFF14 => {
+                self.channel1
+                    .write_register(address - 0xFF10, value, self.frame_step);
+            }
+            0xFF15..=0xFF19 => {
+                self.channel2
+                    .write_register(address - 0xFF15, value, self.frame_step);
+            }
+            0xFF1A..=0xFF1E => {
+                self.channel3
+                    .write_register(address - 0xFF1A, value, self.frame_step);
+            }
+            0xFF20..=0xFF23 => {
+                self.channel4
+                    .write_register(address - 0xFF20, value, self.frame_step);
+            }
+            0xFF24 => self.channel_control = value,
+            0xFF25 => self.sound_output = value,
+            _ => {}
+        }
+    }
+}
+
+// The DAC of a channel turns its 0-15 digital output into -1.0..1.0, a disabled DAC outputs 0.
+fn analog(dac_enabled: bool, output: u8) -> f32 {
+    if dac_enabled {
+        f32::from(output) / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+// The output capacitor removes the DC offset of the DACs.
+fn high_pass(capacitor: &mut f32, charge: f32, input: f32) -> f32 {
+    let out = input - *capacitor;
+    *capacitor = input - out * charge;
+    out
 }
 
 impl ReadWrite for Sound {
     fn contains(&self, address: usize) -> bool {
-        0xFF26 == address || 0xFF25 == address || 0xFF24 == address
+        (0xFF10..=0xFF1E).contains(&address)
+            || (0xFF20..=0xFF23).contains(&address)
+            || 0xFF26 == address
+            || 0xFF25 == address
+            || 0xFF24 == address
+            || (0xFF30..=0xFF3F).contains(&address)
+            || (self.cgb && (0xFF76..=0xFF77).contains(&address))
     }
 
     fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
         match address {
-            0xFF26 => Ok(self.on),
+            0xFF10..=0xFF14 => Ok(self.channel1.read_register(address - 0xFF10)),
+            0xFF15..=0xFF19 => Ok(self.channel2.read_register(address - 0xFF15)),
+            0xFF1A..=0xFF1E => Ok(self.channel3.read_register(address - 0xFF1A)),
+            0xFF20..=0xFF23 => Ok(self.channel4.read_register(address - 0xFF20)),
+            0xFF26 => Ok(((self.on as u8) << 7) | 0x70 | self.channel_status()),
             0xFF25 => Ok(self.sound_output),
             0xFF24 => Ok(self.channel_control),
+            0xFF30..=0xFF3F => Ok(self.channel3.read_wave_ram(address)),
+            // PCM12 and PCM34, the digital outputs of the channels, CGB only.
+            0xFF76 => Ok(self.channel1.output() | (self.channel2.output() << 4)),
+            0xFF77 => Ok(self.channel3.output() | (self.channel4.output() << 4)),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "can't read byte here",
@@ -62,25 +507,244 @@ impl ReadWrite for Sound {
     fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
         match address {
             0xFF26 => {
-                self.on = value;
-                Ok(())
+                if value & 0x80 == 0 && self.on {
+                    self.power_off();
+                } else if value & 0x80 != 0 && !self.on {
+                    self.on = true;
+                    self.frame_step = 0;
+                }
             }
-            0xFF25 => {
-                self.sound_output = value;
-                Ok(())
+            0xFF10..=0xFF25 if self.on => self.write_register(address, value),
+            // With the power off the length counters of DMG can still be written.
+            0xFF11 | 0xFF16 | 0xFF20 if !self.cgb => self.write_register(address, value & 0x3F),
+            0xFF1B if !self.cgb => self.write_register(address, value),
+            0xFF10..=0xFF25 => {}
+            0xFF30..=0xFF3F => self.channel3.write_wave_ram(address, value),
+            // PCM12 and PCM34 are read-only.
+            0xFF76..=0xFF77 => {}
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "can't write byte here",
+                ))
             }
-            0xFF24 => {
-                self.channel_control = value;
-                Ok(())
-            }
-            _ => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "can't write byte here",
-            )),
         }
+
+        Ok(())
     }
 
     fn write_word(&mut self, _address: usize, _value: u16) -> Result<(), std::io::Error> {
         unimplemented!()
     }
 }
+
+// Samples already made and the filters shaping them belong to the output, not the console.
+impl SaveState for Sound {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.on as u8, self.sound_output, self.channel_control]);
+        self.channel1.save_state(out);
+        self.channel2.save_state(out);
+        self.channel3.save_state(out);
+        self.channel4.save_state(out);
+        out.push(self.frame_step);
+        out.extend_from_slice(&self.written);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.on = state.bool()?;
+        self.sound_output = state.u8()?;
+        self.channel_control = state.u8()?;
+        self.channel1.load_state(state)?;
+        self.channel2.load_state(state)?;
+        self.channel3.load_state(state)?;
+        self.channel4.load_state(state)?;
+        self.frame_step = state.u8()?;
+        state.fill(&mut self.written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_status_in_nr52() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        assert_eq!(sound.read_byte(0xFF26).unwrap(), 0xF0);
+
+        sound.write_byte(0xFF17, 0xF0).unwrap();
+        sound.write_byte(0xFF19, 0x80).unwrap();
+        assert_eq!(sound.read_byte(0xFF26).unwrap(), 0xF2);
+        assert_eq!(sound.read_byte(0xFF15).unwrap(), 0xFF);
+
+        sound.write_byte(0xFF1A, 0x80).unwrap();
+        sound.write_byte(0xFF1E, 0x80).unwrap();
+        assert_eq!(sound.read_byte(0xFF26).unwrap(), 0xF6);
+
+        sound.write_byte(0xFF21, 0xF0).unwrap();
+        sound.write_byte(0xFF23, 0x80).unwrap();
+        assert_eq!(sound.read_byte(0xFF26).unwrap(), 0xFE);
+    }
+
+    #[test]
+    fn frame_sequencer_clocks_length() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF11, 0x3F).unwrap();
+        sound.write_byte(0xFF12, 0xF0).unwrap();
+        sound.write_byte(0xFF14, 0xC0).unwrap();
+        assert_eq!(sound.read_byte(0xFF26).unwrap() & 0x01, 0x01);
+        sound.clock_frame_sequencer();
+        assert_eq!(sound.read_byte(0xFF26).unwrap() & 0x01, 0x00);
+    }
+
+    #[test]
+    fn power_off_clears_registers() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF24, 0x77).unwrap();
+        sound.write_byte(0xFF12, 0xF0).unwrap();
+        sound.write_byte(0xFF14, 0x80).unwrap();
+        sound.write_byte(0xFF30, 0x12).unwrap();
+
+        sound.write_byte(0xFF26, 0x00).unwrap();
+        assert_eq!(sound.read_byte(0xFF26).unwrap(), 0x70);
+        assert_eq!(sound.read_byte(0xFF24).unwrap(), 0x00);
+        assert_eq!(sound.read_byte(0xFF12).unwrap(), 0x00);
+        assert_eq!(sound.read_byte(0xFF30).unwrap(), 0x12);
+
+        // Writes are ignored until power is back on.
+        sound.write_byte(0xFF24, 0x77).unwrap();
+        assert_eq!(sound.read_byte(0xFF24).unwrap(), 0x00);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF24, 0x77).unwrap();
+        assert_eq!(sound.read_byte(0xFF24).unwrap(), 0x77);
+    }
+
+    #[test]
+    fn panning_and_volume() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF24, 0x70).unwrap();
+        sound.write_byte(0xFF25, 0x10).unwrap();
+        // Channel 2 DAC on, channel not triggered: the DAC outputs -1.
+        sound.write_byte(0xFF17, 0x08).unwrap();
+        assert_eq!(sound.mix(), (0.0, 0.0));
+        sound.write_byte(0xFF25, 0x22).unwrap();
+        assert_eq!(sound.mix(), (-0.25, -0.25 / 8.0));
+    }
+
+    #[test]
+    fn dc_is_filtered_out() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF24, 0x77).unwrap();
+        sound.write_byte(0xFF25, 0xFF).unwrap();
+        sound.write_byte(0xFF17, 0x08).unwrap();
+        sound.step(CYCLES_PER_SECOND);
+
+        let samples = sound.drain_samples();
+        assert_eq!(samples.len(), DEFAULT_SAMPLE_RATE as usize * 2);
+        // The resampler delays the output by a few samples.
+        assert!(samples[0].abs() < 0.001);
+        assert!(samples[40] < -0.2);
+        assert!(samples[samples.len() - 1].abs() < 0.001);
+        assert!(sound.drain_samples().is_empty());
+    }
+
+    #[test]
+    fn square_wave_is_resampled() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF24, 0x77).unwrap();
+        sound.write_byte(0xFF25, 0x22).unwrap();
+        // Channel 2 at 50% duty, frequency 1750: 131072 / 298 = 440 Hz.
+        sound.write_byte(0xFF16, 0x80).unwrap();
+        sound.write_byte(0xFF17, 0xF0).unwrap();
+        sound.write_byte(0xFF18, 0xD6).unwrap();
+        sound.write_byte(0xFF19, 0x86).unwrap();
+        sound.step(CYCLES_PER_SECOND / 4);
+
+        let left: Vec<f32> = sound.drain_samples().into_iter().step_by(2).collect();
+        assert_eq!(left.len(), DEFAULT_SAMPLE_RATE as usize / 4);
+        // 440 Hz has two edges per period, each one crossing zero once.
+        let crossings = left
+            .windows(2)
+            .skip(100)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        assert!((215..=225).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn rate_adjustment_changes_sample_count() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.set_sample_rate(44_100);
+        sound.set_rate_adjustment(0.01);
+        sound.step(CYCLES_PER_SECOND);
+        assert_eq!(sound.drain_samples().len(), 44_320 * 2);
+    }
+
+    #[test]
+    fn register_writes_restore_state() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF30, 0x12).unwrap();
+        sound.write_byte(0xFF17, 0xF0).unwrap();
+        sound.write_byte(0xFF18, 0x34).unwrap();
+        sound.write_byte(0xFF19, 0x86).unwrap();
+        sound.write_byte(0xFF1E, 0x05).unwrap();
+
+        let writes = sound.register_writes();
+        assert_eq!(writes[0], (0xFF26, 0x80));
+        assert!(writes.contains(&(0xFF30, 0x12)));
+        assert!(writes.contains(&(0xFF18, 0x34)));
+        assert!(writes.contains(&(0xFF19, 0x86)));
+        assert!(writes.contains(&(0xFF1E, 0x05)));
+
+        let mut copy = Sound::new(Model::Dmg);
+        for (address, value) in writes {
+            copy.write_byte(address, value).unwrap();
+        }
+        assert_eq!(copy.read_byte(0xFF26).unwrap(), 0xF2);
+        assert_eq!(copy.mix(), sound.mix());
+    }
+
+    #[test]
+    fn pcm_registers_on_cgb() {
+        let sound = Sound::new(Model::Dmg);
+        assert!(!sound.contains(0xFF76));
+
+        let mut sound = Sound::new(Model::Cgb);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        assert_eq!(sound.read_byte(0xFF76).unwrap(), 0x00);
+        assert_eq!(sound.read_byte(0xFF77).unwrap(), 0x00);
+
+        // Channel 2 at 50% duty is high on its first step, at volume 0xA.
+        sound.write_byte(0xFF16, 0x80).unwrap();
+        sound.write_byte(0xFF17, 0xA0).unwrap();
+        sound.write_byte(0xFF19, 0x80).unwrap();
+        // Channel 4 at volume 0x7 outputs the inverted LFSR bit 0, 0 at first.
+        sound.write_byte(0xFF21, 0x70).unwrap();
+        sound.write_byte(0xFF23, 0x80).unwrap();
+        assert_eq!(sound.read_byte(0xFF76).unwrap(), 0xA0);
+        assert_eq!(sound.read_byte(0xFF77).unwrap(), 0x00);
+
+        sound.write_byte(0xFF76, 0x12).unwrap();
+        assert_eq!(sound.read_byte(0xFF76).unwrap(), 0xA0);
+    }
+
+    #[test]
+    fn muted_channel_left_out_of_mix() {
+        let mut sound = Sound::new(Model::Dmg);
+        sound.write_byte(0xFF26, 0x80).unwrap();
+        sound.write_byte(0xFF24, 0x77).unwrap();
+        sound.write_byte(0xFF25, 0x22).unwrap();
+        sound.write_byte(0xFF17, 0x08).unwrap();
+        sound.set_channel_muted(1, true);
+        assert_eq!(sound.mix(), (0.0, 0.0));
+        sound.set_channel_muted(1, false);
+        assert_eq!(sound.mix(), (-0.25, -0.25));
+    }
+}
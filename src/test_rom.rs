@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::emulator::{Emulator, EmulatorBuilder};
+
+// Tests run in parallel, each rom gets a file of its own.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Tetris written out as a rom file for tests to run, removed once dropped.
+pub struct TestRom {
+    path: PathBuf,
+}
+
+impl TestRom {
+    /// Tetris with the code after the entry point, at 0x0150, replaced by `code`.
+    pub fn tetris(code: &[u8]) -> TestRom {
+        let mut data = std::fs::read_to_string("./testdata/tetris")
+            .unwrap()
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect::<Vec<u8>>();
+        data[0x150..0x150 + code.len()].copy_from_slice(code);
+        let path = std::env::temp_dir().join(format!(
+            "yobemag-{}-{}.gb",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, data).unwrap();
+        TestRom { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestRom {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// An emulator with defaults running `TestRom::tetris(code)`, the file is gone already.
+pub fn emulator(code: &[u8]) -> Emulator {
+    EmulatorBuilder::new(TestRom::tetris(code).path())
+        .build()
+        .unwrap()
+}
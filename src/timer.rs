@@ -2,19 +2,25 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::{
-    clock::Clock,
     interrupt::{InterruptFlag, InterruptKind},
     memory_device::ReadWrite,
+    save_state::{SaveState, StateReader},
 };
 
+// Cycles between TIMA overflowing and getting reloaded from TMA, one M-cycle.
+const RELOAD_DELAY: u8 = 4;
+
 // Each time when the timer overflows (ie. when TIMA gets bigger than FFh), then an interrupt is requested by
 // setting Bit 2 in the IF Register (0xFF0F). When that interrupt is enabled, then the CPU will execute it by calling
 // the timer interrupt vector at 0050h.
+//
+// DIV and TIMA are both driven by a 16-bit system counter incremented every cycle. DIV is its upper byte,
+// TIMA is incremented on the falling edge of one of its bits, selected by TAC and ANDed with the enable bit.
+// The quirks come from this edge detector: resetting the counter through DIV or changing TAC can turn the
+// selected bit from 1 to 0, which increments TIMA as a normal edge would.
 pub struct Timer {
-    // This register is incremented at rate of 16384Hz (~16779Hz on SGB).
-    // Writing any value to this register resets it to 00h.
-    // Note: The divider is affected by CGB double speed mode, and will increment at 32768Hz in double speed.
-    divider: u8,
+    // Incremented every cycle, at 4194304 Hz (8388608 Hz in CGB double speed).
+    system_counter: u16,
     // This timer is incremented by a clock frequency specified by the TAC register (0xFF07).
     // When the value overflows (gets bigger than FFh) then it will be reset to the value specified in TMA (0xFF06),
     // and an interrupt will be requested, as described below.
@@ -32,41 +38,111 @@ pub struct Timer {
     //    - 11: CPU Clock / 256  (DMG, CGB:  16384 Hz, SGB:  ~16780 Hz)
     tac: u8,
 
-    clock1: Clock,
-    clock2: Clock,
+    // After TIMA overflows it reads 0x00 for one M-cycle, then TMA is loaded and the interrupt
+    // requested. Writing TIMA during that M-cycle cancels both.
+    overflow_delay: u8,
+    // During the M-cycle TMA is loaded, writes to TIMA are ignored and writes to TMA
+    // reach TIMA too.
+    reloading: u8,
     interrupt_flag: Rc<RefCell<InterruptFlag>>,
+
+    // The APU frame sequencer is clocked when bit 4 of DIV goes from 1 to 0, bit 5 in double
+    // speed mode. Resetting DIV while the bit is set counts as a falling edge too.
+    frame_sequencer_clocks: u32,
+    double_speed: bool,
 }
 
 impl Timer {
     pub fn new(interrupt_flag: Rc<RefCell<InterruptFlag>>) -> Self {
         Self {
-            divider: 0xAC,
+            system_counter: 0,
             tima: 0,
             tma: 0,
             tac: 0,
-            clock1: Clock::new(256),
-            clock2: Clock::new(1024),
+            overflow_delay: 0,
+            reloading: 0,
             interrupt_flag,
+            frame_sequencer_clocks: 0,
+            double_speed: false,
         }
     }
 }
 
 impl Timer {
+    /// Seeds DIV with the value left by the boot ROM when it's not emulated.
+    pub fn set_divider(&mut self, value: u8) {
+        self.system_counter = u16::from(value) << 8;
+    }
+
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    fn divider(&self) -> u8 {
+        (self.system_counter >> 8) as u8
+    }
+
+    fn frame_sequencer_bit(&self) -> u8 {
+        if self.double_speed {
+            0x20
+        } else {
+            0x10
+        }
+    }
+
+    /// Frame sequencer clocks produced by DIV since the last call.
+    pub fn take_frame_sequencer_clocks(&mut self) -> u32 {
+        std::mem::take(&mut self.frame_sequencer_clocks)
+    }
+
+    // Input of the TIMA edge detector: the counter bit selected by TAC, while enabled.
+    fn timer_input(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.system_counter & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        self.tima = self.tima.wrapping_add(1);
+        if self.tima == 0x00 {
+            self.overflow_delay = RELOAD_DELAY;
+        }
+    }
+
+    // Sets the system counter, counting the falling edges it causes.
+    fn set_system_counter(&mut self, value: u16) {
+        let input = self.timer_input();
+        let divider = self.divider();
+        self.system_counter = value;
+
+        let bit = self.frame_sequencer_bit();
+        if divider & bit != 0 && self.divider() & bit == 0 {
+            self.frame_sequencer_clocks += 1;
+        }
+        if input && !self.timer_input() {
+            self.increment_tima();
+        }
+    }
+
     pub fn step(&mut self, cycles: u32) {
-        // clock cycles is 4194304, so divider increment every 256 cycles.
-        self.divider = self.divider.wrapping_add(self.clock1.step(cycles));
-
-        if (self.tac & 0x04) != 0x00 {
-            let n = self.clock2.step(cycles);
-            for _ in 0..n {
-                self.tima = self.tima.wrapping_add(1);
-                if self.tima == 0x00 {
+        for _ in 0..cycles {
+            self.reloading = self.reloading.saturating_sub(1);
+            if self.overflow_delay > 0 {
+                self.overflow_delay -= 1;
+                if self.overflow_delay == 0 {
                     self.tima = self.tma;
+                    self.reloading = RELOAD_DELAY;
                     self.interrupt_flag
                         .borrow_mut()
                         .request(InterruptKind::Timer);
                 }
             }
+
+            self.set_system_counter(self.system_counter.wrapping_add(1));
         }
     }
 }
@@ -78,10 +154,11 @@ impl ReadWrite for Timer {
 
     fn read_byte(&self, address: usize) -> Result<u8, std::io::Error> {
         match address {
-            0xFF04 => Ok(self.divider),
+            0xFF04 => Ok(self.divider()),
             0xFF05 => Ok(self.tima),
             0xFF06 => Ok(self.tma),
-            0xFF07 => Ok(self.tac),
+            // Unused bits read as 1.
+            0xFF07 => Ok(self.tac | 0xF8),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "can't write byte here",
@@ -95,32 +172,25 @@ impl ReadWrite for Timer {
 
     fn write_byte(&mut self, address: usize, value: u8) -> Result<(), std::io::Error> {
         match address {
-            0xFF04 => {
-                self.divider = 0;
-                self.clock1.reset_counter();
+            0xFF04 => self.set_system_counter(0),
+            0xFF05 => {
+                if self.reloading == 0 {
+                    self.tima = value;
+                    self.overflow_delay = 0;
+                }
+            }
+            0xFF06 => {
+                self.tma = value;
+                if self.reloading > 0 {
+                    self.tima = value;
+                }
             }
-            0xFF05 => self.tima = value,
-            0xFF06 => self.tma = value,
             0xFF07 => {
-                if (self.tac & 0x03) != (value & 0x03) {
-                    self.clock2.reset_counter();
-                    let new_period = match value & 0x03 {
-                        0x00 => 1024,
-                        0x01 => 16,
-                        0x02 => 64,
-                        0x03 => 256,
-                        _ => {
-                            return Err(std::io::Error::new(
-                                std::io::ErrorKind::InvalidData,
-                                format!("period of clock can't be {}", value),
-                            ))
-                        }
-                    };
-
-                    self.clock2.set_period(new_period);
-                    self.tima = self.tma;
+                let input = self.timer_input();
+                self.tac = value & 0x07;
+                if input && !self.timer_input() {
+                    self.increment_tima();
                 }
-                self.tac = value;
             }
             _ => {
                 return Err(std::io::Error::new(
@@ -136,3 +206,168 @@ impl ReadWrite for Timer {
         unimplemented!()
     }
 }
+
+impl SaveState for Timer {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.system_counter.to_le_bytes());
+        out.extend_from_slice(&[
+            self.tima,
+            self.tma,
+            self.tac,
+            self.overflow_delay,
+            self.reloading,
+        ]);
+        out.extend_from_slice(&self.frame_sequencer_clocks.to_le_bytes());
+        out.push(self.double_speed as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.system_counter = state.u16()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()?;
+        self.overflow_delay = state.u8()?;
+        self.reloading = state.u8()?;
+        self.frame_sequencer_clocks = state.u32()?;
+        self.double_speed = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer() -> (Timer, Rc<RefCell<InterruptFlag>>) {
+        let interrupt_flag = Rc::new(RefCell::new(InterruptFlag::default()));
+        (Timer::new(interrupt_flag.clone()), interrupt_flag)
+    }
+
+    #[test]
+    fn frame_sequencer_on_div_falling_edge() {
+        let (mut timer, _) = timer();
+        timer.step(256 * 0x1F);
+        assert_eq!(timer.take_frame_sequencer_clocks(), 0);
+        timer.step(256);
+        assert_eq!(timer.take_frame_sequencer_clocks(), 1);
+        timer.step(256 * 0x20 * 3);
+        assert_eq!(timer.take_frame_sequencer_clocks(), 3);
+
+        // Resetting DIV with bit 4 set clocks it early.
+        timer.set_divider(0x10);
+        timer.write_byte(0xFF04, 0x00).unwrap();
+        assert_eq!(timer.take_frame_sequencer_clocks(), 1);
+        timer.write_byte(0xFF04, 0x00).unwrap();
+        assert_eq!(timer.take_frame_sequencer_clocks(), 0);
+    }
+
+    #[test]
+    fn double_speed_uses_bit_5() {
+        let (mut timer, _) = timer();
+        timer.set_double_speed(true);
+        timer.step(256 * 0x20);
+        assert_eq!(timer.take_frame_sequencer_clocks(), 0);
+        timer.step(256 * 0x20);
+        assert_eq!(timer.take_frame_sequencer_clocks(), 1);
+    }
+
+    #[test]
+    fn div_is_upper_byte_of_counter() {
+        let (mut timer, _) = timer();
+        timer.step(255);
+        assert_eq!(timer.read_byte(0xFF04).unwrap(), 0x00);
+        timer.step(1);
+        assert_eq!(timer.read_byte(0xFF04).unwrap(), 0x01);
+        timer.write_byte(0xFF04, 0x12).unwrap();
+        assert_eq!(timer.read_byte(0xFF04).unwrap(), 0x00);
+        assert_eq!(timer.read_byte(0xFF07).unwrap(), 0xF8);
+    }
+
+    #[test]
+    fn tima_rates() {
+        for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let (mut timer, _) = timer();
+            timer.write_byte(0xFF07, tac).unwrap();
+            timer.step(period - 1);
+            assert_eq!(timer.read_byte(0xFF05).unwrap(), 0);
+            timer.step(1);
+            assert_eq!(timer.read_byte(0xFF05).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn div_reset_increments_tima() {
+        let (mut timer, _) = timer();
+        timer.write_byte(0xFF07, 0x05).unwrap();
+        // Bit 3 set, halfway through a period.
+        timer.step(8);
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0);
+        timer.write_byte(0xFF04, 0x00).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 1);
+        // Bit 3 clear, nothing happens.
+        timer.step(4);
+        timer.write_byte(0xFF04, 0x00).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 1);
+    }
+
+    #[test]
+    fn tac_change_increments_tima() {
+        let (mut timer, _) = timer();
+        timer.write_byte(0xFF07, 0x05).unwrap();
+        timer.step(8);
+        // Disabling the timer while the selected bit is set is a falling edge.
+        timer.write_byte(0xFF07, 0x01).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 1);
+
+        // So is selecting a bit that is clear.
+        timer.write_byte(0xFF07, 0x05).unwrap();
+        timer.write_byte(0xFF07, 0x04).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 2);
+    }
+
+    #[test]
+    fn overflow_reload_is_delayed() {
+        let (mut timer, interrupt_flag) = timer();
+        timer.write_byte(0xFF06, 0xAB).unwrap();
+        timer.write_byte(0xFF05, 0xFF).unwrap();
+        timer.write_byte(0xFF07, 0x05).unwrap();
+        timer.step(16);
+        // TIMA reads 0 for one M-cycle before the reload.
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0x00);
+        assert_eq!(interrupt_flag.borrow().data, 0x00);
+        timer.step(4);
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0xAB);
+        assert_eq!(interrupt_flag.borrow().data, 0x04);
+    }
+
+    #[test]
+    fn tima_write_cancels_overflow() {
+        let (mut timer, interrupt_flag) = timer();
+        timer.write_byte(0xFF06, 0xAB).unwrap();
+        timer.write_byte(0xFF05, 0xFF).unwrap();
+        timer.write_byte(0xFF07, 0x05).unwrap();
+        timer.step(16);
+        timer.write_byte(0xFF05, 0x12).unwrap();
+        timer.step(4);
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0x12);
+        assert_eq!(interrupt_flag.borrow().data, 0x00);
+    }
+
+    #[test]
+    fn writes_while_reloading() {
+        let (mut timer, _) = timer();
+        timer.write_byte(0xFF06, 0xAB).unwrap();
+        timer.write_byte(0xFF05, 0xFF).unwrap();
+        timer.write_byte(0xFF07, 0x05).unwrap();
+        timer.step(20);
+        // TIMA writes are ignored during the reload M-cycle, TMA writes go through.
+        timer.write_byte(0xFF05, 0x12).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0xAB);
+        timer.write_byte(0xFF06, 0x34).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0x34);
+
+        timer.step(4);
+        timer.write_byte(0xFF05, 0x12).unwrap();
+        assert_eq!(timer.read_byte(0xFF05).unwrap(), 0x12);
+    }
+}
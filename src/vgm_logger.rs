@@ -0,0 +1,302 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// VGM files count time in samples at 44.1 kHz whatever the chip.
+const VGM_SAMPLE_RATE: u64 = 44_100;
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+
+// Frames are counted in time rather than v-blanks, they go on with the LCD off.
+const FRAME_CYCLES: u64 = 70_224;
+
+// The header is brought up to date about once a second, so a log cut short is still readable.
+const FLUSH_CYCLES: u64 = CYCLES_PER_SECOND;
+
+const VERSION: u32 = 0x0000_0171;
+// Commands start right after the 1.71 header.
+const DATA_OFFSET: usize = 0x100;
+
+const WAIT: u8 = 0x61;
+const WAIT_NTSC_FRAME: u8 = 0x62;
+const WAIT_PAL_FRAME: u8 = 0x63;
+const WAIT_SHORT: u8 = 0x70;
+const END_OF_DATA: u8 = 0x66;
+// GameBoy DMG write, the register is the offset of its address from 0xFF10.
+const GB_DMG_WRITE: u8 = 0xB3;
+
+/// Streams writes to the APU registers, 0xFF10-0xFF3F, to a VGM 1.71 file with waits
+/// matching the cycles between them. Frames are counted from the start of the log, the loop
+/// starts at `loop_frame` and logging ends at `stop_frame`.
+pub struct VgmLogger<W: Write + Seek = BufWriter<File>> {
+    out: W,
+    // Bytes of commands written after the header.
+    data_len: usize,
+
+    // Master clock cycles logged so far and the samples already written as waits.
+    cycles: u64,
+    samples: u64,
+    flushed_cycles: u64,
+
+    loop_frame: Option<u64>,
+    stop_frame: Option<u64>,
+    // Offset in the commands and sample where the loop starts.
+    loop_start: Option<(usize, u64)>,
+    // The APU can't fail a write, the first error stops the log and is kept for `finish`.
+    error: Option<std::io::Error>,
+}
+
+impl VgmLogger {
+    pub fn create(
+        path: &Path,
+        loop_frame: Option<u64>,
+        stop_frame: Option<u64>,
+    ) -> Result<VgmLogger, std::io::Error> {
+        VgmLogger::new(BufWriter::new(File::create(path)?), loop_frame, stop_frame)
+    }
+}
+
+impl<W: Write + Seek> VgmLogger<W> {
+    pub fn new(
+        mut out: W,
+        loop_frame: Option<u64>,
+        stop_frame: Option<u64>,
+    ) -> Result<VgmLogger<W>, std::io::Error> {
+        // Filled in by `flush`.
+        out.write_all(&[0; DATA_OFFSET])?;
+        let mut logger = VgmLogger {
+            out,
+            data_len: 0,
+            cycles: 0,
+            samples: 0,
+            flushed_cycles: 0,
+            loop_frame,
+            stop_frame,
+            loop_start: None,
+            error: None,
+        };
+        if loop_frame == Some(0) {
+            logger.mark_loop(0);
+        }
+        logger.flush()?;
+        Ok(logger)
+    }
+
+    /// True once `stop_frame` frames have been logged, or the file can't be written.
+    pub fn is_done(&self) -> bool {
+        self.error.is_some()
+            || self
+                .stop_frame
+                .is_some_and(|f| self.cycles >= f * FRAME_CYCLES)
+    }
+
+    /// Logs the write of `value` to `address`.
+    pub fn write(&mut self, address: usize, value: u8) {
+        if self.is_done() || !(0xFF10..=0xFF3F).contains(&address) {
+            return;
+        }
+
+        self.flush_wait(self.cycles);
+        self.push(&[GB_DMG_WRITE, (address - 0xFF10) as u8, value]);
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if self.is_done() {
+            return;
+        }
+
+        let end = self.stop_frame.map_or(u64::MAX, |f| f * FRAME_CYCLES);
+        let before = self.cycles;
+        self.cycles = (self.cycles + u64::from(cycles)).min(end);
+        if let Some(frame) = self.loop_frame {
+            let start = frame * FRAME_CYCLES;
+            if before < start && start <= self.cycles {
+                self.mark_loop(start);
+            }
+        }
+        if self.cycles - self.flushed_cycles >= FLUSH_CYCLES {
+            if let Err(e) = self.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn mark_loop(&mut self, cycles: u64) {
+        self.flush_wait(cycles);
+        self.loop_start = Some((self.data_len, self.samples));
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.out.write_all(bytes) {
+            Ok(()) => self.data_len += bytes.len(),
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    // Turns the time elapsed since the last command, up to `cycles`, into wait commands.
+    fn flush_wait(&mut self, cycles: u64) {
+        let target = cycles * VGM_SAMPLE_RATE / CYCLES_PER_SECOND;
+        let mut wait = target - self.samples;
+        self.samples = target;
+
+        while wait > 0 {
+            match wait {
+                735 => {
+                    self.push(&[WAIT_NTSC_FRAME]);
+                    wait = 0;
+                }
+                882 => {
+                    self.push(&[WAIT_PAL_FRAME]);
+                    wait = 0;
+                }
+                1..=16 => {
+                    self.push(&[WAIT_SHORT + (wait - 1) as u8]);
+                    wait = 0;
+                }
+                _ => {
+                    let chunk = wait.min(u64::from(u16::MAX));
+                    let [low, high] = (chunk as u16).to_le_bytes();
+                    self.push(&[WAIT, low, high]);
+                    wait -= chunk;
+                }
+            }
+        }
+    }
+
+    // The header for the commands so far.
+    fn header(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; DATA_OFFSET];
+        let mut put = |offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        // Up to the end of data marker.
+        put(0x04, (DATA_OFFSET + self.data_len + 1 - 0x04) as u32);
+        put(0x08, VERSION);
+        put(0x18, self.samples as u32);
+        if let Some((offset, sample)) = self.loop_start {
+            // Relative to the field itself.
+            put(0x1C, (DATA_OFFSET + offset - 0x1C) as u32);
+            put(0x20, (self.samples - sample) as u32);
+        }
+        put(0x34, (DATA_OFFSET - 0x34) as u32);
+        put(0x80, CYCLES_PER_SECOND as u32);
+        bytes[0x00..0x04].copy_from_slice(b"Vgm ");
+        bytes
+    }
+
+    // Makes the file complete as it is: the waits up to now, the end of data marker and the
+    // header. The next command goes over the marker.
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.flush_wait(self.cycles);
+        self.flushed_cycles = self.cycles;
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        let end = self.out.stream_position()?;
+        self.out.write_all(&[END_OF_DATA])?;
+        self.out.seek(SeekFrom::Start(0))?;
+        let header = self.header();
+        self.out.write_all(&header)?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()
+    }
+
+    /// Completes the file, reporting any error that happened while logging.
+    pub fn finish(mut self) -> Result<W, std::io::Error> {
+        self.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn logger(loop_frame: Option<u64>, stop_frame: Option<u64>) -> VgmLogger<Cursor<Vec<u8>>> {
+        VgmLogger::new(Cursor::new(vec![]), loop_frame, stop_frame).unwrap()
+    }
+
+    #[test]
+    fn header() {
+        let bytes = logger(None, None).finish().unwrap().into_inner();
+        assert_eq!(&bytes[0..4], b"Vgm ");
+        assert_eq!(u32_at(&bytes, 0x04), 0x100 + 1 - 4);
+        assert_eq!(u32_at(&bytes, 0x08), 0x171);
+        assert_eq!(u32_at(&bytes, 0x1C), 0);
+        assert_eq!(u32_at(&bytes, 0x34), 0xCC);
+        assert_eq!(u32_at(&bytes, 0x80), 4_194_304);
+        assert_eq!(&bytes[0x100..], [END_OF_DATA]);
+    }
+
+    #[test]
+    fn writes_with_waits() {
+        let mut logger = logger(None, None);
+        logger.write(0xFF26, 0x80);
+        // One frame at 60 Hz is 735 samples.
+        logger.step(4_194_304 / 60 + 1);
+        logger.write(0xFF12, 0xF0);
+        // 50 cycles is not a whole sample yet.
+        logger.step(50);
+        logger.write(0xFF14, 0x87);
+        logger.step(4_194_304 * 2);
+        logger.write(0xFF00, 0x00);
+
+        let bytes = logger.finish().unwrap().into_inner();
+        assert_eq!(
+            &bytes[0x100..],
+            &[
+                0xB3, 0x16, 0x80, 0x62, 0xB3, 0x02, 0xF0, 0xB3, 0x04, 0x87, 0x61, 0xFF, 0xFF, 0x61,
+                0x89, 0x58, 0x66
+            ]
+        );
+        assert_eq!(u32_at(&bytes, 0x04), bytes.len() as u32 - 4);
+        assert_eq!(u32_at(&bytes, 0x18), 88_935);
+    }
+
+    #[test]
+    fn complete_after_each_second() {
+        let mut logger = logger(None, None);
+        logger.write(0xFF26, 0x80);
+        logger.step(4_194_304);
+        logger.write(0xFF12, 0xF0);
+        let bytes = logger.out.get_ref().clone();
+        // The header still ends the file at the marker after the wait, the write after it
+        // went over the marker.
+        assert_eq!(u32_at(&bytes, 0x18), 44_100);
+        assert_eq!(u32_at(&bytes, 0x04), 0x100 + 3 + 3 + 1 - 4);
+        assert_eq!(&bytes[0x103..], &[0x61, 0x44, 0xAC, 0xB3, 0x02, 0xF0]);
+    }
+
+    #[test]
+    fn loop_and_stop_frames() {
+        let mut logger = logger(Some(1), Some(2));
+        logger.write(0xFF26, 0x80);
+        // Frames are counted whatever the PPU does, the loop starts right at frame 1.
+        logger.step(70_000);
+        logger.step(1_000);
+        logger.write(0xFF30, 0x12);
+        logger.step(70_000);
+        assert!(logger.is_done());
+        logger.write(0xFF31, 0x34);
+        logger.step(1_000);
+
+        let bytes = logger.finish().unwrap().into_inner();
+        // 70224 cycles are 738 samples, the loop starts right after the wait up to them.
+        let loop_offset = u32_at(&bytes, 0x1C) as usize + 0x1C;
+        assert_eq!(&bytes[loop_offset - 3..loop_offset], &[0x61, 0xE2, 0x02]);
+        assert_eq!(
+            &bytes[loop_offset..],
+            &[0x77, 0xB3, 0x20, 0x12, 0x61, 0xDA, 0x02, 0x66]
+        );
+        assert_eq!(u32_at(&bytes, 0x18), 1476);
+        assert_eq!(u32_at(&bytes, 0x20), 1476 - 738);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::save_state::{SaveState, StateReader};
+
+/// Volume envelope of the pulse and noise channels, NRx2.
+/// Bit 7-4 - Initial volume of envelope (0-0Fh) (0=No Sound)
+/// Bit 3   - Envelope direction (0=Decrease, 1=Increase)
+/// Bit 2-0 - Number of envelope sweep (n: 0-7) (If zero, stop envelope operation.)
+/// The volume changes by one every n/64 seconds, clocked by the frame sequencer.
+#[derive(Default)]
+pub struct VolumeEnvelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl VolumeEnvelope {
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// The DAC of the channel is on when any of the upper 5 bits is set.
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+
+        self.timer = self.period();
+        if self.register & 0x08 != 0 && self.volume < 0x0F {
+            self.volume += 1;
+        } else if self.register & 0x08 == 0 && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+impl SaveState for VolumeEnvelope {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.register, self.volume, self.timer]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.register = state.u8()?;
+        self.volume = state.u8()?;
+        self.timer = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrease_every_period() {
+        let mut envelope = VolumeEnvelope::default();
+        envelope.write(0xF2);
+        envelope.trigger();
+        assert_eq!(envelope.volume(), 15);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 14);
+    }
+
+    #[test]
+    fn increase_stops_at_max() {
+        let mut envelope = VolumeEnvelope::default();
+        envelope.write(0xE9);
+        envelope.trigger();
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 15);
+    }
+
+    #[test]
+    fn dac_enable() {
+        let mut envelope = VolumeEnvelope::default();
+        envelope.write(0x08);
+        assert!(envelope.dac_enabled());
+        envelope.write(0x07);
+        assert!(!envelope.dac_enabled());
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Streams 16-bit PCM samples to a RIFF WAVE file. The sizes in the header are written
+/// as 0 first and patched by `flush` and `finish`, so samples never pile up in memory.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(
+        mut out: W,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Result<WavWriter<W>, std::io::Error> {
+        let block_align = channels * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM format.
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, data_size: 0 })
+    }
+
+    /// Appends samples in -1.0..1.0, interleaved when there is more than one channel.
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), std::io::Error> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        self.out.write_all(&bytes)?;
+        self.data_size = self.data_size.saturating_add(bytes.len() as u32);
+        Ok(())
+    }
+
+    /// Writes the sizes of the samples so far to the header, the file is valid from here on.
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(u64::from(HEADER_SIZE) - 4))?;
+        self.out.write_all(&self.data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()
+    }
+
+    pub fn finish(mut self) -> Result<W, std::io::Error> {
+        self.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header_and_samples() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 2, 48_000).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 36 + 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([bytes[22], bytes[23]]), 2);
+        assert_eq!(u32_at(&bytes, 24), 48_000);
+        assert_eq!(u32_at(&bytes, 28), 48_000 * 4);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), 8);
+        assert_eq!(
+            &bytes[44..],
+            &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn flush_keeps_appending() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 1, 8_000).unwrap();
+        wav.write_samples(&[0.5]).unwrap();
+        wav.flush().unwrap();
+        wav.write_samples(&[0.5, 0.5]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(u32_at(&bytes, 40), 6);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{
+    length_counter::LengthCounter,
+    save_state::{SaveState, StateReader},
+};
+
+// The first sample is fetched a few cycles later than a full period after trigger.
+const TRIGGER_DELAY: u32 = 6;
+
+/// Channel 3, plays the 32 4-bit samples of wave RAM: 0xFF1A-0xFF1E and 0xFF30-0xFF3F.
+pub struct WaveChannel {
+    enabled: bool,
+
+    // NR30 Bit 7 - Sound Channel 3 Off  (0=Stop, 1=Playback)
+    dac_enabled: bool,
+    length: LengthCounter,
+
+    // NR32 Bit 6-5 - Select output level
+    //  0: Mute (No sound)
+    //  1: 100% Volume (Produce Wave Pattern RAM Data as it is)
+    //  2: 50% Volume (Produce Wave Pattern RAM data shifted once to the right)
+    //  3: 25% Volume (Produce Wave Pattern RAM data shifted twice to the right)
+    output_level: u8,
+
+    // 11 bits from NR33 and NR34, the period is (2048 - frequency) * 2 cycles.
+    frequency: u16,
+    timer: u32,
+
+    // Index of the sample being played, 0-31, and the wave RAM byte it was read from.
+    position: u8,
+    sample_buffer: u8,
+    // Cycles since the last byte was fetched from wave RAM.
+    since_fetch: u32,
+
+    wave_ram: [u8; 16],
+
+    // On CGB wave RAM accesses while playing always reach the byte being played,
+    // on DMG only when they happen right as the channel reads it.
+    cgb: bool,
+}
+
+impl WaveChannel {
+    pub fn new(cgb: bool) -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            output_level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            since_fetch: u32::MAX,
+            wave_ram: [0; 16],
+            cgb,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 2
+    }
+
+    /// Reads NR30-NR34, `register` being 0-4. Write-only bits read back as 1.
+    pub fn read_register(&self, register: usize) -> u8 {
+        match register {
+            0 => ((self.dac_enabled as u8) << 7) | 0x7F,
+            2 => (self.output_level << 5) | 0x9F,
+            4 => ((self.length.is_enabled() as u8) << 6) | 0xBF,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes NR30-NR34, `frame_step` is the next step of the frame sequencer.
+    pub fn write_register(&mut self, register: usize, value: u8, frame_step: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.output_level = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | u16::from(value),
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | (u16::from(value & 0x07) << 8);
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, frame_step & 0x01 == 0)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.position = 0;
+                    self.timer = self.period() + TRIGGER_DELAY;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // While playing the CPU sees the byte being played instead of the one addressed.
+    fn wave_ram_index(&self, address: usize) -> Option<usize> {
+        if !self.enabled {
+            Some(address - 0xFF30)
+        } else if self.cgb || self.since_fetch == 0 {
+            Some(self.position as usize / 2)
+        } else {
+            None
+        }
+    }
+
+    pub fn read_wave_ram(&self, address: usize) -> u8 {
+        match self.wave_ram_index(address) {
+            Some(i) => self.wave_ram[i],
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_wave_ram(&mut self, address: usize, value: u8) {
+        if let Some(i) = self.wave_ram_index(address) {
+            self.wave_ram[i] = value;
+        }
+    }
+
+    /// Cycles until the output of the channel can next change.
+    pub fn cycles_until_step(&self) -> u32 {
+        if self.enabled {
+            self.timer
+        } else {
+            u32::MAX
+        }
+    }
+
+    pub fn wave_ram(&self) -> [u8; 16] {
+        self.wave_ram
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut cycles = cycles;
+        self.since_fetch = self.since_fetch.saturating_add(cycles);
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+            self.sample_buffer = self.wave_ram[self.position as usize / 2];
+            self.since_fetch = cycles;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Digital output of the channel, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.output_level == 0 {
+            return 0;
+        }
+
+        let sample = if self.position & 0x01 == 0 {
+            self.sample_buffer >> 4
+        } else {
+            self.sample_buffer & 0x0F
+        };
+        sample >> (self.output_level - 1)
+    }
+}
+
+impl SaveState for WaveChannel {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.enabled as u8, self.dac_enabled as u8]);
+        self.length.save_state(out);
+        out.push(self.output_level);
+        out.extend_from_slice(&self.frequency.to_le_bytes());
+        out.extend_from_slice(&self.timer.to_le_bytes());
+        out.extend_from_slice(&[self.position, self.sample_buffer]);
+        out.extend_from_slice(&self.since_fetch.to_le_bytes());
+        out.extend_from_slice(&self.wave_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), std::io::Error> {
+        self.enabled = state.bool()?;
+        self.dac_enabled = state.bool()?;
+        self.length.load_state(state)?;
+        self.output_level = state.u8()?;
+        self.frequency = state.u16()?;
+        self.timer = state.u32()?;
+        self.position = state.u8()?;
+        self.sample_buffer = state.u8()?;
+        self.since_fetch = state.u32()?;
+        state.fill(&mut self.wave_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(cgb: bool, nr32: u8) -> WaveChannel {
+        let mut channel = WaveChannel::new(cgb);
+        for (i, v) in [0x01, 0x23, 0x45, 0x67].iter().enumerate() {
+            channel.write_wave_ram(0xFF30 + i, *v);
+        }
+        channel.write_register(0, 0x80, 0);
+        channel.write_register(2, nr32, 0);
+        channel.write_register(3, 0xFF, 0);
+        channel.write_register(4, 0x87, 0);
+        channel
+    }
+
+    #[test]
+    fn plays_nibbles_in_order() {
+        // Period of 2 cycles per sample.
+        let mut channel = playing(false, 0x20);
+        channel.step(2 + TRIGGER_DELAY);
+        assert_eq!(channel.output(), 0x1);
+        channel.step(2);
+        assert_eq!(channel.output(), 0x2);
+        channel.step(2);
+        assert_eq!(channel.output(), 0x3);
+    }
+
+    #[test]
+    fn output_level_shifts() {
+        let mut channel = playing(false, 0x60);
+        channel.step(2 + TRIGGER_DELAY + 4);
+        // Sample 0x3 at 25%.
+        assert_eq!(channel.output(), 0x0);
+        let mut channel = playing(false, 0x40);
+        channel.step(2 + TRIGGER_DELAY + 4);
+        assert_eq!(channel.output(), 0x1);
+        let mut channel = playing(false, 0x00);
+        channel.step(2 + TRIGGER_DELAY + 4);
+        assert_eq!(channel.output(), 0x0);
+    }
+
+    #[test]
+    fn dac_off_disables_channel() {
+        let mut channel = playing(false, 0x20);
+        assert!(channel.is_enabled());
+        channel.write_register(0, 0x00, 0);
+        assert!(!channel.is_enabled());
+        assert_eq!(channel.read_register(0), 0x7F);
+    }
+
+    #[test]
+    fn length_counts_256_steps() {
+        let mut channel = playing(false, 0x20);
+        channel.write_register(1, 0x00, 0);
+        channel.write_register(4, 0x40, 0);
+        for _ in 0..255 {
+            channel.clock_length();
+        }
+        assert!(channel.is_enabled());
+        channel.clock_length();
+        assert!(!channel.is_enabled());
+    }
+
+    #[test]
+    fn cgb_wave_ram_access_while_playing() {
+        let mut channel = playing(true, 0x20);
+        channel.step(2 + TRIGGER_DELAY + 2);
+        // Playing sample 2, the second byte.
+        assert_eq!(channel.read_wave_ram(0xFF3F), 0x23);
+        channel.write_wave_ram(0xFF30, 0xAB);
+        assert_eq!(channel.wave_ram[1], 0xAB);
+        assert_eq!(channel.wave_ram[0], 0x01);
+    }
+
+    #[test]
+    fn dmg_wave_ram_access_while_playing() {
+        let mut channel = playing(false, 0x20);
+        channel.step(2 + TRIGGER_DELAY + 2);
+        assert_eq!(channel.read_wave_ram(0xFF3F), 0x23);
+        channel.step(1);
+        assert_eq!(channel.read_wave_ram(0xFF3F), 0xFF);
+        channel.write_wave_ram(0xFF30, 0xAB);
+        assert_eq!(channel.wave_ram[0], 0x01);
+        assert_eq!(channel.wave_ram[1], 0x23);
+    }
+}